@@ -0,0 +1,2 @@
+#!/bin/sh
+exec cargo run --example fit_split -- "$@"
@@ -0,0 +1,42 @@
+//! Benchmark parsing and decoding of the larger test fixtures
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::fs;
+use std::path::PathBuf;
+
+const FIXTURES: [&str; 2] = ["MonitoringFile.fit", "sample_mulitple_header.fit"];
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    fs::read(path).expect("missing test fixture")
+}
+
+/// Parse the raw message structure without applying the profile
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for name in FIXTURES {
+        let data = fixture(name);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &data, |b, data| {
+            b.iter(|| fitparser::raw::from_bytes(black_box(data)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Parse and decode every message using the FIT profile
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for name in FIXTURES {
+        let data = fixture(name);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &data, |b, data| {
+            b.iter(|| fitparser::from_bytes(black_box(data)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, decode);
+criterion_main!(benches);
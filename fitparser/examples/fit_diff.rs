@@ -0,0 +1,41 @@
+//! Compare two FIT files and print the messages, fields and definitions that changed
+use fitparser::diff::Differ;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use structopt::StructOpt;
+
+/// Compare two FIT files at message and field level, exits with status 1 if they differ
+#[derive(Debug, StructOpt)]
+#[structopt(name = "fit_diff")]
+struct Cli {
+    /// Original FIT file
+    #[structopt(name = "OLD", parse(from_os_str))]
+    old: PathBuf,
+
+    /// Changed FIT file
+    #[structopt(name = "NEW", parse(from_os_str))]
+    new: PathBuf,
+
+    /// Largest difference between numeric values that are considered equal
+    #[structopt(short, long, default_value = "0.000001")]
+    tolerance: f64,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::from_args();
+
+    let mut differ = Differ::new();
+    differ.set_tolerance(cli.tolerance);
+    let changes = differ.diff(&fs::read(&cli.old)?, &fs::read(&cli.new)?)?;
+    if changes.is_empty() {
+        println!("no differences");
+        return Ok(());
+    }
+    for change in &changes {
+        println!("{}", change);
+    }
+    println!("{} differences", changes.len());
+    process::exit(1);
+}
@@ -0,0 +1,63 @@
+//! Repair FIT files with an invalid header data size or missing and invalid CRC values
+use fitparser::repair::repair;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+/// Repair FIT files by dropping incomplete trailing data, fixing the header data size and
+/// recomputing the header and data CRC values
+#[derive(Debug, StructOpt)]
+#[structopt(name = "fit_repair")]
+struct Cli {
+    /// FIT files to repair
+    #[structopt(name = "FILE", parse(from_os_str))]
+    files: Vec<PathBuf>,
+
+    /// Output directory, if not provided the repaired file is written alongside the input file
+    /// with a '.repaired.fit' extension
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Overwrite the input files instead of writing new ones
+    #[structopt(long, conflicts_with = "output")]
+    in_place: bool,
+
+    /// Only report the fixes without writing any files
+    #[structopt(long)]
+    dry_run: bool,
+}
+
+fn output_path(cli: &Cli, file: &Path) -> PathBuf {
+    if cli.in_place {
+        return file.to_path_buf();
+    }
+    let name = file.with_extension("repaired.fit");
+    match &cli.output {
+        Some(dir) => dir.join(name.file_name().unwrap()),
+        None => name,
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::from_args();
+
+    for file in &cli.files {
+        let data = fs::read(file)?;
+        let repaired = repair(&data)?;
+        if !repaired.is_modified() {
+            println!("{}: no repairs needed", file.display());
+            continue;
+        }
+        for fix in repaired.fixes() {
+            println!("{}: {}", file.display(), fix);
+        }
+        if !cli.dry_run {
+            let path = output_path(&cli, file);
+            fs::write(&path, repaired.data())?;
+            println!("{}: wrote {}", file.display(), path.display());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+//! Split a chained FIT file into standalone FIT files
+use fitparser::de::{files_from_bytes_with_options, DecodeOption};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Split a chained FIT file into one file per chained file, each written alongside the input
+/// with its index added to the name unless an output directory is given
+#[derive(Debug, StructOpt)]
+#[structopt(name = "fit_split")]
+struct Cli {
+    /// Chained FIT file to split
+    #[structopt(name = "FILE", parse(from_os_str))]
+    file: PathBuf,
+
+    /// Output directory
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Split files with invalid CRC values instead of failing
+    #[structopt(long)]
+    skip_crc_validation: bool,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::from_args();
+
+    let mut options = HashSet::new();
+    if cli.skip_crc_validation {
+        options.insert(DecodeOption::SkipHeaderCrcValidation);
+        options.insert(DecodeOption::SkipDataCrcValidation);
+    }
+    let data = fs::read(&cli.file)?;
+    let files = files_from_bytes_with_options(&data, &options)?;
+    for (idx, file) in files.iter().enumerate() {
+        let name = cli.file.with_extension(format!("{}.fit", idx));
+        let path = match &cli.output {
+            Some(dir) => dir.join(name.file_name().unwrap()),
+            None => name,
+        };
+        fs::write(&path, &data[file.range()])?;
+        println!(
+            "{}: bytes {}..{}, {} records, header CRC {:?}, data CRC {:?}",
+            path.display(),
+            file.range().start,
+            file.range().end,
+            file.records().len(),
+            file.header_crc(),
+            file.data_crc()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,461 @@
+//! Group the flat list of decoded messages of an activity file into sessions and laps.
+//!
+//! Multisport files (e.g. a triathlon) contain one `Session` per leg, including the transitions,
+//! and the `Lap`, `Record`, `Length` and `Split` messages of all legs in a single stream.
+//! [`Activity`] assigns laps to sessions using the `first_lap_index` and `num_laps` fields of the
+//! session, falling back to the session time range, and assigns records, lengths and splits by
+//! time. Messages that don't fall in any group are kept in [`Activity::unassigned`].
+//! ```
+//! use fitparser::activity::Activity;
+//! use std::fs::File;
+//!
+//! let mut fp = File::open("tests/fixtures/Activity.fit")?;
+//! let records = fitparser::from_reader(&mut fp)?;
+//! let activity = Activity::from_records(&records);
+//! for session in activity.sessions() {
+//!     println!("{:?}: {} laps", session.sport(), session.laps().len());
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::analysis::{enum_value, field_timestamp};
+use crate::profile::field_types::{Sport, SubSport};
+use crate::profile::MesgNum;
+use crate::FitDataRecord;
+use chrono::{DateTime, Local};
+use std::convert::TryInto;
+
+/// A lap and the records and lengths recorded during it
+#[derive(Clone, Debug)]
+pub struct Lap<'a> {
+    message: Option<&'a FitDataRecord>,
+    records: Vec<&'a FitDataRecord>,
+    lengths: Vec<&'a FitDataRecord>,
+}
+
+impl<'a> Lap<'a> {
+    /// The `Lap` message, None if the file has no laps for the session and the lap covers the
+    /// whole session
+    pub fn message(&self) -> Option<&'a FitDataRecord> {
+        self.message
+    }
+
+    /// Start time of the lap
+    pub fn start_time(&self) -> Option<DateTime<Local>> {
+        self.message
+            .and_then(|m| field_timestamp(m, "start_time"))
+            .or_else(|| self.records.first().and_then(|r| r.timestamp()))
+    }
+
+    /// End time of the lap
+    pub fn end_time(&self) -> Option<DateTime<Local>> {
+        self.message
+            .and_then(|m| m.timestamp())
+            .or_else(|| self.records.last().and_then(|r| r.timestamp()))
+    }
+
+    /// `Record` messages of the lap
+    pub fn records(&self) -> &[&'a FitDataRecord] {
+        &self.records
+    }
+
+    /// `Length` messages of the lap, only present for pool swims
+    pub fn lengths(&self) -> &[&'a FitDataRecord] {
+        &self.lengths
+    }
+}
+
+/// A session, e.g. a single leg of a multisport activity, and its laps
+#[derive(Clone, Debug)]
+pub struct Session<'a> {
+    message: Option<&'a FitDataRecord>,
+    laps: Vec<Lap<'a>>,
+    splits: Vec<&'a FitDataRecord>,
+}
+
+impl<'a> Session<'a> {
+    /// The `Session` message, None if the file has no sessions and the session covers the
+    /// whole file
+    pub fn message(&self) -> Option<&'a FitDataRecord> {
+        self.message
+    }
+
+    /// Sport of the session
+    pub fn sport(&self) -> Option<Sport> {
+        self.message.and_then(|m| enum_value::<Sport>(m, "sport"))
+    }
+
+    /// Sub sport of the session
+    pub fn sub_sport(&self) -> Option<SubSport> {
+        self.message
+            .and_then(|m| enum_value::<SubSport>(m, "sub_sport"))
+    }
+
+    /// Check if the session is a transition between the legs of a multisport activity
+    pub fn is_transition(&self) -> bool {
+        self.sport() == Some(Sport::Transition)
+    }
+
+    /// Start time of the session
+    pub fn start_time(&self) -> Option<DateTime<Local>> {
+        self.message
+            .and_then(|m| field_timestamp(m, "start_time"))
+            .or_else(|| self.laps.first().and_then(|l| l.start_time()))
+    }
+
+    /// End time of the session
+    pub fn end_time(&self) -> Option<DateTime<Local>> {
+        self.message
+            .and_then(|m| m.timestamp())
+            .or_else(|| self.laps.last().and_then(|l| l.end_time()))
+    }
+
+    /// Laps of the session in order
+    pub fn laps(&self) -> &[Lap<'a>] {
+        &self.laps
+    }
+
+    /// `Split` messages that started during the session
+    pub fn splits(&self) -> &[&'a FitDataRecord] {
+        &self.splits
+    }
+
+    /// `Record` messages of all laps of the session
+    pub fn records(&self) -> Vec<&'a FitDataRecord> {
+        self.laps
+            .iter()
+            .flat_map(|l| l.records.iter().copied())
+            .collect()
+    }
+
+    /// `Length` messages of all laps of the session
+    pub fn lengths(&self) -> Vec<&'a FitDataRecord> {
+        self.laps
+            .iter()
+            .flat_map(|l| l.lengths.iter().copied())
+            .collect()
+    }
+}
+
+/// The messages of an activity file grouped into sessions and laps
+#[derive(Clone, Debug)]
+pub struct Activity<'a> {
+    message: Option<&'a FitDataRecord>,
+    sessions: Vec<Session<'a>>,
+    unassigned: Vec<&'a FitDataRecord>,
+}
+
+impl<'a> Activity<'a> {
+    /// Group the `Lap`, `Record`, `Length` and `Split` messages by session. If the file has no
+    /// `Session` messages a single session holding all laps is created and a session without
+    /// `Lap` messages gets a single lap covering the whole session.
+    pub fn from_records(records: &'a [FitDataRecord]) -> Self {
+        let of_kind = |kind: MesgNum| -> Vec<&'a FitDataRecord> {
+            records.iter().filter(|r| r.kind() == kind).collect()
+        };
+        let session_messages = of_kind(MesgNum::Session);
+        let lap_messages = of_kind(MesgNum::Lap);
+        let length_messages = of_kind(MesgNum::Length);
+        let mut unassigned = Vec::new();
+
+        // laps claimed by each session, by index or by time when the indices are missing
+        let mut claimed = vec![false; lap_messages.len()];
+        let mut session_laps: Vec<Vec<usize>> = session_messages
+            .iter()
+            .map(|session| {
+                let by_index = match (
+                    index_value(session, "first_lap_index"),
+                    index_value(session, "num_laps"),
+                ) {
+                    (Some(first), Some(num)) if first + num <= lap_messages.len() => {
+                        Some((first..first + num).collect::<Vec<_>>())
+                    }
+                    _ => None,
+                };
+                let laps = by_index.unwrap_or_else(|| {
+                    let range = TimeRange::inclusive(
+                        field_timestamp(session, "start_time"),
+                        session.timestamp(),
+                    );
+                    (0..lap_messages.len())
+                        .filter(|idx| {
+                            !claimed[*idx]
+                                && field_timestamp(lap_messages[*idx], "start_time")
+                                    .is_some_and(|ts| range.contains(ts))
+                        })
+                        .collect()
+                });
+                laps.iter().for_each(|idx| claimed[*idx] = true);
+                laps
+            })
+            .collect();
+        let mut session_messages: Vec<Option<&FitDataRecord>> =
+            session_messages.into_iter().map(Some).collect();
+        if session_messages.is_empty() {
+            session_messages.push(None);
+            session_laps.push((0..lap_messages.len()).collect());
+            claimed.iter_mut().for_each(|c| *c = true);
+        }
+
+        // the time range of each lap ends where the next one starts
+        let lap_starts: Vec<Option<DateTime<Local>>> = lap_messages
+            .iter()
+            .map(|l| field_timestamp(l, "start_time"))
+            .collect();
+        let lap_range = |idx: usize| match lap_starts.get(idx + 1).copied().flatten() {
+            Some(next) => TimeRange::exclusive(lap_starts[idx], Some(next)),
+            None => TimeRange::inclusive(lap_starts[idx], lap_messages[idx].timestamp()),
+        };
+
+        let mut sessions: Vec<Session> = session_messages
+            .iter()
+            .zip(&session_laps)
+            .map(|(message, laps)| Session {
+                message: *message,
+                laps: if laps.is_empty() {
+                    vec![Lap {
+                        message: None,
+                        records: Vec::new(),
+                        lengths: Vec::new(),
+                    }]
+                } else {
+                    laps.iter()
+                        .map(|idx| Lap {
+                            message: Some(lap_messages[*idx]),
+                            records: Vec::new(),
+                            lengths: Vec::new(),
+                        })
+                        .collect()
+                },
+                splits: Vec::new(),
+            })
+            .collect();
+        let ranges: Vec<Vec<TimeRange>> = session_messages
+            .iter()
+            .zip(&session_laps)
+            .map(|(message, laps)| {
+                if laps.is_empty() {
+                    vec![match message {
+                        Some(m) => {
+                            TimeRange::inclusive(field_timestamp(m, "start_time"), m.timestamp())
+                        }
+                        None => TimeRange::inclusive(None, None),
+                    }]
+                } else {
+                    laps.iter().map(|idx| lap_range(*idx)).collect()
+                }
+            })
+            .collect();
+        let find_lap = |ts: Option<DateTime<Local>>| -> Option<(usize, usize)> {
+            let ts = ts?;
+            ranges.iter().enumerate().find_map(|(s, laps)| {
+                laps.iter()
+                    .position(|range| range.contains(ts))
+                    .map(|l| (s, l))
+            })
+        };
+
+        // lengths referenced by index from their lap
+        let mut length_claimed = vec![false; length_messages.len()];
+        for (session, laps) in sessions.iter_mut().zip(&session_laps) {
+            for (lap, idx) in session.laps.iter_mut().zip(laps) {
+                let message = lap_messages[*idx];
+                if let (Some(first), Some(num)) = (
+                    index_value(message, "first_length_index"),
+                    index_value(message, "num_lengths"),
+                ) {
+                    for length in first..(first + num).min(length_messages.len()) {
+                        lap.lengths.push(length_messages[length]);
+                        length_claimed[length] = true;
+                    }
+                }
+            }
+        }
+
+        let mut length_idx = 0;
+        for record in records {
+            match record.kind() {
+                MesgNum::Record => match find_lap(record.timestamp()) {
+                    Some((s, l)) => sessions[s].laps[l].records.push(record),
+                    None => unassigned.push(record),
+                },
+                MesgNum::Length => {
+                    let claimed = length_claimed[length_idx];
+                    length_idx += 1;
+                    if claimed {
+                        continue;
+                    }
+                    let ts = field_timestamp(record, "start_time").or_else(|| record.timestamp());
+                    match find_lap(ts) {
+                        Some((s, l)) => sessions[s].laps[l].lengths.push(record),
+                        None => unassigned.push(record),
+                    }
+                }
+                MesgNum::Split => {
+                    let ts = field_timestamp(record, "start_time").or_else(|| record.timestamp());
+                    match find_lap(ts) {
+                        Some((s, _)) => sessions[s].splits.push(record),
+                        None => unassigned.push(record),
+                    }
+                }
+                _ => {}
+            }
+        }
+        for (idx, lap) in lap_messages.iter().enumerate() {
+            if !claimed[idx] {
+                unassigned.push(lap);
+            }
+        }
+
+        Activity {
+            message: records.iter().find(|r| r.kind() == MesgNum::Activity),
+            sessions,
+            unassigned,
+        }
+    }
+
+    /// The `Activity` message
+    pub fn message(&self) -> Option<&'a FitDataRecord> {
+        self.message
+    }
+
+    /// Sessions in order, including transitions
+    pub fn sessions(&self) -> &[Session<'a>] {
+        &self.sessions
+    }
+
+    /// `Record`, `Length`, `Split` and `Lap` messages that don't belong to any session, e.g.
+    /// records logged before the first lap started
+    pub fn unassigned(&self) -> &[&'a FitDataRecord] {
+        &self.unassigned
+    }
+}
+
+/// A time range with an optional start and end, an open end matches any later time
+#[derive(Clone, Copy, Debug)]
+struct TimeRange {
+    start: Option<DateTime<Local>>,
+    end: Option<DateTime<Local>>,
+    end_inclusive: bool,
+}
+
+impl TimeRange {
+    fn inclusive(start: Option<DateTime<Local>>, end: Option<DateTime<Local>>) -> Self {
+        TimeRange {
+            start,
+            end,
+            end_inclusive: true,
+        }
+    }
+
+    fn exclusive(start: Option<DateTime<Local>>, end: Option<DateTime<Local>>) -> Self {
+        TimeRange {
+            start,
+            end,
+            end_inclusive: false,
+        }
+    }
+
+    fn contains(&self, timestamp: DateTime<Local>) -> bool {
+        self.start.is_none_or(|s| timestamp >= s)
+            && self.end.is_none_or(|e| {
+                if self.end_inclusive {
+                    timestamp <= e
+                } else {
+                    timestamp < e
+                }
+            })
+    }
+}
+
+/// Return an index or count field as a usize
+fn index_value(record: &FitDataRecord, name: &str) -> Option<usize> {
+    let value: i64 = record.field(name)?.value().try_into().ok()?;
+    value.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::message;
+    use crate::{from_bytes, Value};
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn single_session_activity() {
+        let data = include_bytes!("../tests/fixtures/Activity.fit").to_vec();
+        let records = from_bytes(&data).unwrap();
+        let activity = Activity::from_records(&records);
+        assert!(activity.message().is_some());
+        assert_eq!(activity.sessions().len(), 1);
+        let session = &activity.sessions()[0];
+        assert_eq!(session.laps().len(), 1);
+        let count = records
+            .iter()
+            .filter(|r| r.kind() == MesgNum::Record)
+            .count();
+        assert_eq!(session.records().len() + activity.unassigned().len(), count);
+        assert!(!session.records().is_empty());
+    }
+
+    #[test]
+    fn multisport_sessions() {
+        let start = Local.timestamp_opt(1_600_000_000, 0).unwrap();
+        let at = |secs: i64| Value::Timestamp(start + Duration::seconds(secs));
+        let mut records = Vec::new();
+        // swim 0-100 s with two laps, transition 100-130 s, bike 130-300 s
+        let legs = [
+            (Sport::Swimming, 0, 100, vec![0, 50]),
+            (Sport::Transition, 100, 130, vec![100]),
+            (Sport::Cycling, 130, 300, vec![130]),
+        ];
+        let mut lap_index = 0;
+        let mut lap_messages = Vec::new();
+        let mut session_messages = Vec::new();
+        for (sport, leg_start, leg_end, lap_starts) in legs.iter() {
+            for (idx, lap_start) in lap_starts.iter().enumerate() {
+                let lap_end = lap_starts.get(idx + 1).unwrap_or(leg_end);
+                lap_messages.push(message(
+                    MesgNum::Lap,
+                    vec![("start_time", at(*lap_start)), ("timestamp", at(*lap_end))],
+                ));
+            }
+            session_messages.push(message(
+                MesgNum::Session,
+                vec![
+                    ("timestamp", at(*leg_end)),
+                    ("start_time", at(*leg_start)),
+                    ("sport", Value::String(sport.to_string())),
+                    ("first_lap_index", Value::UInt16(lap_index)),
+                    ("num_laps", Value::UInt16(lap_starts.len() as u16)),
+                ],
+            ));
+            lap_index += lap_starts.len() as u16;
+        }
+        for secs in (0..300).step_by(10) {
+            records.push(message(MesgNum::Record, vec![("timestamp", at(secs))]));
+        }
+        records.push(message(
+            MesgNum::Split,
+            vec![("start_time", at(140)), ("timestamp", at(200))],
+        ));
+        records.extend(lap_messages);
+        records.extend(session_messages);
+
+        let activity = Activity::from_records(&records);
+        let sessions = activity.sessions();
+        assert_eq!(sessions.len(), 3);
+        assert_eq!(sessions[0].sport(), Some(Sport::Swimming));
+        assert!(sessions[1].is_transition());
+        assert_eq!(sessions[0].laps().len(), 2);
+        assert_eq!(sessions[0].laps()[0].records().len(), 5);
+        assert_eq!(sessions[0].laps()[1].records().len(), 5);
+        assert_eq!(sessions[1].records().len(), 3);
+        assert_eq!(sessions[2].records().len(), 17);
+        assert_eq!(sessions[2].splits().len(), 1);
+        assert_eq!(
+            sessions[1].start_time(),
+            Some(start + Duration::seconds(100))
+        );
+        assert!(activity.unassigned().is_empty());
+    }
+}
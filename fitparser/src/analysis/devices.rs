@@ -0,0 +1,362 @@
+//! Build an inventory of the devices and sensors recorded in the `DeviceInfo` messages.
+//!
+//! Devices write a `DeviceInfo` message for every device and sensor at the start of an activity
+//! and again whenever the timer is started or stopped, so the same sensor usually appears many
+//! times. Messages are grouped into a single [`Device`] when they have the same serial number or
+//! ANT device number, comparing only the numbers present in both messages since a sensor may only
+//! report its serial number in later messages, or when neither has one, the same device index.
+//! The device type has to match as well since a single sensor can provide multiple ANT+ profiles.
+//! ```
+//! use fitparser::analysis::devices::device_inventory;
+//! use std::fs::File;
+//!
+//! let mut fp = File::open("tests/fixtures/garmin-fenix-5-bike.fit")?;
+//! let records = fitparser::from_reader(&mut fp)?;
+//! for device in device_inventory(&records) {
+//!     println!("{:?} {:?}", device.product_name(), device.last_seen());
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use super::{enum_value, field_f64};
+use crate::profile::field_types::{
+    AntplusDeviceType, BatteryStatus, BleDeviceType, DeviceIndex, FaveroProduct, GarminProduct,
+    LocalDeviceType, Manufacturer, SourceType,
+};
+use crate::profile::MesgNum;
+use crate::{FitDataRecord, Value};
+use chrono::{DateTime, Local};
+use std::convert::TryInto;
+
+/// Type of a device, the profile type depends on the `source_type` of the message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceType {
+    /// A sensor built into the recording device
+    Local(LocalDeviceType),
+    /// An ANT+ sensor
+    AntPlus(AntplusDeviceType),
+    /// A Bluetooth Low Energy sensor
+    Ble(BleDeviceType),
+    /// A device type of another source
+    Other(u8),
+}
+
+/// A battery reading of a device
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatteryReading {
+    timestamp: Option<DateTime<Local>>,
+    status: Option<BatteryStatus>,
+    voltage: Option<f64>,
+    level: Option<f64>,
+}
+
+impl BatteryReading {
+    /// Time of the reading
+    pub fn timestamp(&self) -> Option<DateTime<Local>> {
+        self.timestamp
+    }
+
+    /// Reported battery status
+    pub fn status(&self) -> Option<BatteryStatus> {
+        self.status
+    }
+
+    /// Battery voltage in V
+    pub fn voltage(&self) -> Option<f64> {
+        self.voltage
+    }
+
+    /// Battery level in percent
+    pub fn level(&self) -> Option<f64> {
+        self.level
+    }
+}
+
+/// A device or sensor and the times it was seen in the file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Device {
+    device_index: Option<DeviceIndex>,
+    device_type: Option<DeviceType>,
+    source_type: Option<SourceType>,
+    manufacturer: Option<Manufacturer>,
+    product: Option<u16>,
+    product_name: Option<String>,
+    serial_number: Option<u32>,
+    ant_device_number: Option<u16>,
+    software_version: Option<f64>,
+    hardware_version: Option<u8>,
+    first_seen: Option<DateTime<Local>>,
+    last_seen: Option<DateTime<Local>>,
+    battery: Vec<BatteryReading>,
+}
+
+impl Device {
+    /// Device index from the most recent message, `creator` is the recording device
+    pub fn device_index(&self) -> Option<DeviceIndex> {
+        self.device_index
+    }
+
+    /// Type of the device
+    pub fn device_type(&self) -> Option<DeviceType> {
+        self.device_type
+    }
+
+    /// Connection the device data came from, e.g. local or ANT+
+    pub fn source_type(&self) -> Option<SourceType> {
+        self.source_type
+    }
+
+    /// Manufacturer of the device
+    pub fn manufacturer(&self) -> Option<Manufacturer> {
+        self.manufacturer
+    }
+
+    /// Numeric product ID
+    pub fn product(&self) -> Option<u16> {
+        self.product
+    }
+
+    /// Name of the product, from the `product_name` field or the manufacturer specific product
+    /// subfield, e.g. `fenix5` for Garmin devices
+    pub fn product_name(&self) -> Option<&str> {
+        self.product_name.as_deref()
+    }
+
+    /// Serial number of the device
+    pub fn serial_number(&self) -> Option<u32> {
+        self.serial_number
+    }
+
+    /// ANT device number of a wireless sensor
+    pub fn ant_device_number(&self) -> Option<u16> {
+        self.ant_device_number
+    }
+
+    /// Most recent software version
+    pub fn software_version(&self) -> Option<f64> {
+        self.software_version
+    }
+
+    /// Hardware version
+    pub fn hardware_version(&self) -> Option<u8> {
+        self.hardware_version
+    }
+
+    /// Time of the first message for the device
+    pub fn first_seen(&self) -> Option<DateTime<Local>> {
+        self.first_seen
+    }
+
+    /// Time of the last message for the device
+    pub fn last_seen(&self) -> Option<DateTime<Local>> {
+        self.last_seen
+    }
+
+    /// Battery readings in order, only messages that reported a battery value are included
+    pub fn battery(&self) -> &[BatteryReading] {
+        &self.battery
+    }
+
+    /// Update the device from a later message, values missing from the message are kept
+    fn update(&mut self, other: Device) {
+        self.device_index = other.device_index.or(self.device_index.take());
+        self.serial_number = other.serial_number.or(self.serial_number.take());
+        self.ant_device_number = other.ant_device_number.or(self.ant_device_number.take());
+        self.source_type = other.source_type.or(self.source_type.take());
+        self.manufacturer = other.manufacturer.or(self.manufacturer.take());
+        self.product = other.product.or(self.product.take());
+        self.product_name = other.product_name.or(self.product_name.take());
+        self.software_version = other.software_version.or(self.software_version.take());
+        self.hardware_version = other.hardware_version.or(self.hardware_version.take());
+        self.first_seen = min_time(self.first_seen, other.first_seen);
+        self.last_seen = self.last_seen.max(other.last_seen);
+        self.battery.extend(other.battery);
+    }
+
+    /// Check if the devices are the same physical device and profile, the serial and ANT device
+    /// numbers are only compared if both devices have them
+    fn is_same_device(&self, other: &Device) -> bool {
+        if self.device_type != other.device_type {
+            return false;
+        }
+        if let (Some(a), Some(b)) = (self.serial_number, other.serial_number) {
+            return a == b;
+        }
+        if let (Some(a), Some(b)) = (self.ant_device_number, other.ant_device_number) {
+            return a == b;
+        }
+        let has_number = |d: &Device| d.serial_number.is_some() || d.ant_device_number.is_some();
+        !has_number(self) && !has_number(other) && self.device_index == other.device_index
+    }
+}
+
+/// Return the devices and sensors described by the `DeviceInfo` messages in order of their
+/// first appearance
+pub fn device_inventory(records: &[FitDataRecord]) -> Vec<Device> {
+    let mut devices: Vec<Device> = Vec::new();
+    for record in records.iter().filter(|r| r.kind() == MesgNum::DeviceInfo) {
+        let device = device_from_record(record);
+        match devices.iter_mut().find(|d| d.is_same_device(&device)) {
+            Some(existing) => existing.update(device),
+            None => devices.push(device),
+        }
+    }
+    devices
+}
+
+fn device_from_record(record: &FitDataRecord) -> Device {
+    let device_type = if record.field("local_device_type").is_some() {
+        enum_value::<LocalDeviceType>(record, "local_device_type").map(DeviceType::Local)
+    } else if record.field("antplus_device_type").is_some() {
+        enum_value::<AntplusDeviceType>(record, "antplus_device_type").map(DeviceType::AntPlus)
+    } else if record.field("ble_device_type").is_some() {
+        enum_value::<BleDeviceType>(record, "ble_device_type").map(DeviceType::Ble)
+    } else {
+        field_f64(record, &["device_type"]).map(|v| DeviceType::Other(v as u8))
+    };
+
+    let product_field = ["product", "garmin_product", "favero_product"]
+        .iter()
+        .find_map(|name| record.field(name));
+    let product_name = match record.field("product_name").map(|f| f.value()) {
+        Some(Value::String(name)) if !name.is_empty() => Some(name.clone()),
+        _ => match product_field.map(|f| f.value()) {
+            Some(Value::String(name)) => Some(name.clone()),
+            _ => None,
+        },
+    };
+    // named products only keep their name, recover the ID from the profile enums
+    let product = product_field.and_then(|f| match f.value() {
+        Value::String(name) => match f.name() {
+            "garmin_product" => Some(GarminProduct::from(name.as_str()).as_u16()),
+            "favero_product" => Some(FaveroProduct::from(name.as_str()).as_u16()),
+            _ => None,
+        },
+        val => val.try_into().ok().and_then(|v: i64| v.try_into().ok()),
+    });
+
+    let timestamp = record.timestamp();
+    let status = enum_value::<BatteryStatus>(record, "battery_status");
+    let voltage = field_f64(record, &["battery_voltage"]);
+    let level = field_f64(record, &["battery_level"]);
+    let battery = if status.is_some() || voltage.is_some() || level.is_some() {
+        vec![BatteryReading {
+            timestamp,
+            status,
+            voltage,
+            level,
+        }]
+    } else {
+        Vec::new()
+    };
+
+    Device {
+        device_index: enum_value::<DeviceIndex>(record, "device_index"),
+        device_type,
+        source_type: enum_value::<SourceType>(record, "source_type"),
+        manufacturer: enum_value::<Manufacturer>(record, "manufacturer"),
+        product,
+        product_name,
+        serial_number: field_f64(record, &["serial_number"]).map(|v| v as u32),
+        ant_device_number: field_f64(record, &["ant_device_number"]).map(|v| v as u16),
+        software_version: field_f64(record, &["software_version"]),
+        hardware_version: field_f64(record, &["hardware_version"]).map(|v| v as u8),
+        first_seen: timestamp,
+        last_seen: timestamp,
+        battery,
+    }
+}
+
+fn min_time(a: Option<DateTime<Local>>, b: Option<DateTime<Local>>) -> Option<DateTime<Local>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_bytes;
+    use crate::test_util::message_at;
+
+    #[test]
+    fn sensors_are_deduplicated() {
+        let data = include_bytes!("../../tests/fixtures/sample_mulitple_header.fit").to_vec();
+        let records = from_bytes(&data).unwrap();
+        let devices = device_inventory(&records);
+
+        let creator = &devices[0];
+        assert_eq!(creator.device_index(), Some(DeviceIndex::Creator));
+        assert_eq!(creator.manufacturer(), Some(Manufacturer::Garmin));
+        assert_eq!(creator.product_name(), Some("fr920xt"));
+        assert_eq!(creator.serial_number(), Some(3915525118));
+
+        let hrm: Vec<&Device> = devices
+            .iter()
+            .filter(|d| d.product_name() == Some("hrm_tri"))
+            .collect();
+        // heart rate and running dynamics profiles of the same strap
+        assert_eq!(hrm.len(), 2);
+        let hr = hrm
+            .iter()
+            .find(|d| d.device_type() == Some(DeviceType::AntPlus(AntplusDeviceType::HeartRate)))
+            .unwrap();
+        assert_eq!(hr.ant_device_number(), Some(8407));
+        assert_eq!(hr.source_type(), Some(SourceType::Antplus));
+        assert!(hr.battery().len() > 1);
+        assert_eq!(hr.battery()[0].status(), Some(BatteryStatus::Ok));
+        assert!(hr.first_seen() < hr.last_seen());
+
+        let count = records
+            .iter()
+            .filter(|r| r.kind() == MesgNum::DeviceInfo)
+            .count();
+        assert!(devices.len() < count);
+    }
+
+    #[test]
+    fn serial_number_reported_later() {
+        let records = vec![
+            message_at(
+                MesgNum::DeviceInfo,
+                0,
+                vec![
+                    (
+                        "antplus_device_type",
+                        Value::String("heart_rate".to_string()),
+                    ),
+                    ("ant_device_number", Value::UInt16(8407)),
+                ],
+            ),
+            message_at(
+                MesgNum::DeviceInfo,
+                60,
+                vec![
+                    (
+                        "antplus_device_type",
+                        Value::String("heart_rate".to_string()),
+                    ),
+                    ("ant_device_number", Value::UInt16(8407)),
+                    ("serial_number", Value::UInt32(123456)),
+                ],
+            ),
+            message_at(
+                MesgNum::DeviceInfo,
+                120,
+                vec![
+                    (
+                        "antplus_device_type",
+                        Value::String("heart_rate".to_string()),
+                    ),
+                    ("ant_device_number", Value::UInt16(1234)),
+                ],
+            ),
+        ];
+        let devices = device_inventory(&records);
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].ant_device_number(), Some(8407));
+        assert_eq!(devices[0].serial_number(), Some(123456));
+        assert!(devices[0].first_seen() < devices[0].last_seen());
+        assert_eq!(devices[1].ant_device_number(), Some(1234));
+    }
+}
@@ -0,0 +1,725 @@
+//! Build a dive log from the dive messages of an activity file.
+//!
+//! Each `Session` of a dive activity is treated as a dive. The depth profile is taken from the
+//! `depth`, `n2_load`, `cns_load` and `ndl_time` fields of the `Record` messages, gas switches
+//! from the `dive_gas_switched` events resolved against the `DiveGas` messages and tank
+//! pressures from the `TankUpdate` and `TankSummary` messages. The `DiveSummary` referencing the
+//! session is attached to the dive.
+//! ```
+//! use fitparser::analysis::dive::dive_log;
+//! # let records = Vec::new();
+//!
+//! let log = dive_log(&records);
+//! for dive in log.dives() {
+//!     println!("max depth {:?} m", dive.max_depth());
+//!     for tank in dive.tanks() {
+//!         println!("tank {}: {:?} bar/min", tank.sensor(), tank.pressure_sac());
+//!     }
+//! }
+//! ```
+use super::{enum_value, field_f64, field_timestamp};
+use crate::profile::field_types::{
+    DiveAlarmType, DiveGasMode, DiveGasStatus, Event, TissueModelType, WaterType,
+};
+use crate::profile::MesgNum;
+use crate::{FitDataRecord, Value};
+use chrono::{DateTime, Local};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+/// Atmospheric pressure at the surface in bar
+const SURFACE_PRESSURE: f64 = 1.01325;
+
+/// Standard gravity in m/s^2
+const GRAVITY: f64 = 9.80665;
+
+/// Water density in kg/m^3 of the EN13319 standard used when the settings don't specify one
+const DEFAULT_WATER_DENSITY: f64 = 1020.0;
+
+/// Start and end of a dive, open ends when the file has no sessions
+type DiveRange = (Option<DateTime<Local>>, Option<DateTime<Local>>);
+
+/// Dive computer settings from the `DiveSettings` message
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DiveSettings {
+    name: Option<String>,
+    model: Option<TissueModelType>,
+    gf_low: Option<f64>,
+    gf_high: Option<f64>,
+    water_type: Option<WaterType>,
+    water_density: Option<f64>,
+    po2_warn: Option<f64>,
+    po2_critical: Option<f64>,
+    po2_deco: Option<f64>,
+}
+
+impl DiveSettings {
+    fn from_record(record: &FitDataRecord) -> Self {
+        DiveSettings {
+            name: match record.field("name").map(|f| f.value()) {
+                Some(Value::String(val)) => Some(val.clone()),
+                _ => None,
+            },
+            model: enum_value::<TissueModelType>(record, "model"),
+            gf_low: field_f64(record, &["gf_low"]),
+            gf_high: field_f64(record, &["gf_high"]),
+            water_type: enum_value::<WaterType>(record, "water_type"),
+            water_density: field_f64(record, &["water_density"]),
+            po2_warn: field_f64(record, &["po2_warn"]),
+            po2_critical: field_f64(record, &["po2_critical"]),
+            po2_deco: field_f64(record, &["po2_deco"]),
+        }
+    }
+
+    /// Name of the settings profile
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Decompression model
+    pub fn model(&self) -> Option<TissueModelType> {
+        self.model
+    }
+
+    /// Low gradient factor in percent
+    pub fn gf_low(&self) -> Option<f64> {
+        self.gf_low
+    }
+
+    /// High gradient factor in percent
+    pub fn gf_high(&self) -> Option<f64> {
+        self.gf_high
+    }
+
+    /// Type of water the dive computer is set to
+    pub fn water_type(&self) -> Option<WaterType> {
+        self.water_type
+    }
+
+    /// Water density in kg/m^3, the `water_density` field if set or the density of the water
+    /// type
+    pub fn water_density(&self) -> f64 {
+        match (self.water_density, self.water_type) {
+            (Some(density), _) if density > 0.0 => density,
+            (_, Some(WaterType::Fresh)) => 1000.0,
+            (_, Some(WaterType::Salt)) => 1025.0,
+            _ => DEFAULT_WATER_DENSITY,
+        }
+    }
+
+    /// PO2 warning level in percent
+    pub fn po2_warn(&self) -> Option<f64> {
+        self.po2_warn
+    }
+
+    /// PO2 critical level in percent
+    pub fn po2_critical(&self) -> Option<f64> {
+        self.po2_critical
+    }
+
+    /// PO2 deco level in percent
+    pub fn po2_deco(&self) -> Option<f64> {
+        self.po2_deco
+    }
+}
+
+/// A breathing gas from the `DiveGas` messages
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiveGas {
+    index: u16,
+    oxygen: f64,
+    helium: f64,
+    status: Option<DiveGasStatus>,
+    mode: Option<DiveGasMode>,
+}
+
+impl DiveGas {
+    /// Message index of the gas, used by gas switch events to reference it
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Oxygen content in percent
+    pub fn oxygen(&self) -> f64 {
+        self.oxygen
+    }
+
+    /// Helium content in percent
+    pub fn helium(&self) -> f64 {
+        self.helium
+    }
+
+    /// Whether the gas is enabled or a backup gas
+    pub fn status(&self) -> Option<DiveGasStatus> {
+        self.status
+    }
+
+    /// Open or closed circuit gas
+    pub fn mode(&self) -> Option<DiveGasMode> {
+        self.mode
+    }
+}
+
+/// A depth or time alarm from the `DiveAlarm` messages
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiveAlarm {
+    alarm_type: Option<DiveAlarmType>,
+    depth: Option<f64>,
+    time: Option<f64>,
+    enabled: bool,
+}
+
+impl DiveAlarm {
+    /// Type of the alarm
+    pub fn alarm_type(&self) -> Option<DiveAlarmType> {
+        self.alarm_type
+    }
+
+    /// Depth in meters for depth alarms
+    pub fn depth(&self) -> Option<f64> {
+        self.depth
+    }
+
+    /// Time in seconds for time alarms
+    pub fn time(&self) -> Option<f64> {
+        self.time
+    }
+
+    /// Check if the alarm is enabled
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+/// A point of the depth profile
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DepthSample {
+    timestamp: DateTime<Local>,
+    depth: f64,
+    n2_load: Option<f64>,
+    cns_load: Option<f64>,
+    ndl_time: Option<f64>,
+}
+
+impl DepthSample {
+    /// Time of the sample
+    pub fn timestamp(&self) -> DateTime<Local> {
+        self.timestamp
+    }
+
+    /// Depth in meters
+    pub fn depth(&self) -> f64 {
+        self.depth
+    }
+
+    /// Nitrogen tissue load in percent
+    pub fn n2_load(&self) -> Option<f64> {
+        self.n2_load
+    }
+
+    /// CNS oxygen toxicity load in percent
+    pub fn cns_load(&self) -> Option<f64> {
+        self.cns_load
+    }
+
+    /// No decompression limit in seconds
+    pub fn ndl_time(&self) -> Option<f64> {
+        self.ndl_time
+    }
+}
+
+/// A switch to another breathing gas during the dive
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GasSwitch {
+    timestamp: DateTime<Local>,
+    gas_index: Option<u16>,
+    gas: Option<DiveGas>,
+}
+
+impl GasSwitch {
+    /// Time of the switch
+    pub fn timestamp(&self) -> DateTime<Local> {
+        self.timestamp
+    }
+
+    /// Message index of the gas switched to as stored in the event
+    pub fn gas_index(&self) -> Option<u16> {
+        self.gas_index
+    }
+
+    /// Gas switched to, None if the event references an unknown gas
+    pub fn gas(&self) -> Option<DiveGas> {
+        self.gas
+    }
+}
+
+/// Pressure readings and consumption of a tank during a dive
+#[derive(Clone, Debug, PartialEq)]
+pub struct TankUsage {
+    sensor: i64,
+    pressures: Vec<(DateTime<Local>, f64)>,
+    start_pressure: Option<f64>,
+    end_pressure: Option<f64>,
+    volume_used: Option<f64>,
+    pressure_sac: Option<f64>,
+}
+
+impl TankUsage {
+    /// ANT channel ID of the tank pod
+    pub fn sensor(&self) -> i64 {
+        self.sensor
+    }
+
+    /// Pressure readings in bar from the `TankUpdate` messages
+    pub fn pressures(&self) -> &[(DateTime<Local>, f64)] {
+        &self.pressures
+    }
+
+    /// Pressure in bar at the start of the dive, from the `TankSummary` or the first reading
+    pub fn start_pressure(&self) -> Option<f64> {
+        self.start_pressure
+    }
+
+    /// Pressure in bar at the end of the dive, from the `TankSummary` or the last reading
+    pub fn end_pressure(&self) -> Option<f64> {
+        self.end_pressure
+    }
+
+    /// Pressure used in bar
+    pub fn pressure_used(&self) -> Option<f64> {
+        Some(self.start_pressure? - self.end_pressure?)
+    }
+
+    /// Volume of gas used in liters as reported by the `TankSummary`
+    pub fn volume_used(&self) -> Option<f64> {
+        self.volume_used
+    }
+
+    /// Surface air consumption in bar/min: the pressure used per minute between the first and
+    /// last reading, divided by the mean ambient pressure over that time
+    pub fn pressure_sac(&self) -> Option<f64> {
+        self.pressure_sac
+    }
+}
+
+/// A single dive with its depth profile, gas switches and tank usage
+#[derive(Clone, Debug)]
+pub struct Dive {
+    start: Option<DateTime<Local>>,
+    end: Option<DateTime<Local>>,
+    samples: Vec<DepthSample>,
+    gas_switches: Vec<GasSwitch>,
+    tanks: Vec<TankUsage>,
+    summary: Option<FitDataRecord>,
+}
+
+impl Dive {
+    /// Start of the dive
+    pub fn start(&self) -> Option<DateTime<Local>> {
+        self.start
+    }
+
+    /// End of the dive
+    pub fn end(&self) -> Option<DateTime<Local>> {
+        self.end
+    }
+
+    /// Depth profile of the dive
+    pub fn samples(&self) -> &[DepthSample] {
+        &self.samples
+    }
+
+    /// Gas switches in order
+    pub fn gas_switches(&self) -> &[GasSwitch] {
+        &self.gas_switches
+    }
+
+    /// Tanks used during the dive
+    pub fn tanks(&self) -> &[TankUsage] {
+        &self.tanks
+    }
+
+    /// The `DiveSummary` message referencing the dive's session
+    pub fn summary(&self) -> Option<&FitDataRecord> {
+        self.summary.as_ref()
+    }
+
+    /// Maximum depth in meters, from the summary or the depth profile
+    pub fn max_depth(&self) -> Option<f64> {
+        self.summary
+            .as_ref()
+            .and_then(|s| field_f64(s, &["max_depth"]))
+            .or_else(|| {
+                self.samples
+                    .iter()
+                    .map(|s| s.depth)
+                    .fold(None, |acc: Option<f64>, d| {
+                        Some(acc.map_or(d, |a| a.max(d)))
+                    })
+            })
+    }
+
+    /// Time weighted mean depth in meters, from the summary or the depth profile
+    pub fn avg_depth(&self) -> Option<f64> {
+        self.summary
+            .as_ref()
+            .and_then(|s| field_f64(s, &["avg_depth"]))
+            .or_else(|| mean_depth(&self.samples, self.start, self.end))
+    }
+}
+
+/// Everything in the file related to diving
+#[derive(Clone, Debug, Default)]
+pub struct DiveLog {
+    settings: Option<DiveSettings>,
+    gases: Vec<DiveGas>,
+    alarms: Vec<DiveAlarm>,
+    dives: Vec<Dive>,
+}
+
+impl DiveLog {
+    /// Dive computer settings
+    pub fn settings(&self) -> Option<&DiveSettings> {
+        self.settings.as_ref()
+    }
+
+    /// Breathing gases configured on the dive computer
+    pub fn gases(&self) -> &[DiveGas] {
+        &self.gases
+    }
+
+    /// Depth and time alarms configured on the dive computer
+    pub fn alarms(&self) -> &[DiveAlarm] {
+        &self.alarms
+    }
+
+    /// Dives in order
+    pub fn dives(&self) -> &[Dive] {
+        &self.dives
+    }
+}
+
+/// Build the dive log from the records of a dive activity. A file without `Session` messages is
+/// treated as a single dive.
+pub fn dive_log(records: &[FitDataRecord]) -> DiveLog {
+    let of_kind = |kind: MesgNum| records.iter().filter(move |r| r.kind() == kind);
+    let settings = of_kind(MesgNum::DiveSettings)
+        .next()
+        .map(DiveSettings::from_record);
+    let water_density = settings
+        .as_ref()
+        .map_or(DEFAULT_WATER_DENSITY, |s| s.water_density());
+    let gases: Vec<DiveGas> = of_kind(MesgNum::DiveGas)
+        .enumerate()
+        .map(|(idx, r)| DiveGas {
+            index: field_f64(r, &["message_index"]).map_or(idx as u16, |v| v as u16),
+            oxygen: field_f64(r, &["oxygen_content"]).unwrap_or(0.0),
+            helium: field_f64(r, &["helium_content"]).unwrap_or(0.0),
+            status: enum_value::<DiveGasStatus>(r, "status"),
+            mode: enum_value::<DiveGasMode>(r, "mode"),
+        })
+        .collect();
+    let alarms = of_kind(MesgNum::DiveAlarm)
+        .map(|r| DiveAlarm {
+            alarm_type: enum_value::<DiveAlarmType>(r, "alarm_type"),
+            depth: field_f64(r, &["depth"]),
+            time: field_f64(r, &["time"]),
+            enabled: field_f64(r, &["enabled"]).is_some_and(|v| v > 0.0),
+        })
+        .collect();
+
+    let sessions: Vec<&FitDataRecord> = of_kind(MesgNum::Session).collect();
+    let ranges: Vec<DiveRange> = if sessions.is_empty() {
+        vec![(None, None)]
+    } else {
+        sessions
+            .iter()
+            .map(|s| (field_timestamp(s, "start_time"), s.timestamp()))
+            .collect()
+    };
+    let in_range = |idx: usize, ts: DateTime<Local>| {
+        let (start, end) = ranges[idx];
+        start.is_none_or(|s| ts >= s) && end.is_none_or(|e| ts <= e)
+    };
+
+    let mut dives = Vec::new();
+    for idx in 0..ranges.len() {
+        let samples: Vec<DepthSample> = of_kind(MesgNum::Record)
+            .filter_map(|r| {
+                let timestamp = r.timestamp().filter(|ts| in_range(idx, *ts))?;
+                Some(DepthSample {
+                    timestamp,
+                    depth: field_f64(r, &["depth"])?,
+                    n2_load: field_f64(r, &["n2_load"]),
+                    cns_load: field_f64(r, &["cns_load"]),
+                    ndl_time: field_f64(r, &["ndl_time"]),
+                })
+            })
+            .collect();
+        let (start, end) = match ranges[idx] {
+            (Some(start), Some(end)) => (Some(start), Some(end)),
+            _ => (
+                samples.first().map(|s| s.timestamp),
+                samples.last().map(|s| s.timestamp),
+            ),
+        };
+
+        let gas_switches = of_kind(MesgNum::Event)
+            .filter(|r| enum_value::<Event>(r, "event") == Some(Event::DiveGasSwitched))
+            .filter_map(|r| {
+                let timestamp = r.timestamp().filter(|ts| in_range(idx, *ts))?;
+                let gas_index = field_f64(r, &["data"]).map(|v| v as u16);
+                Some(GasSwitch {
+                    timestamp,
+                    gas_index,
+                    gas: gas_index.and_then(|i| gases.iter().find(|g| g.index == i).copied()),
+                })
+            })
+            .collect();
+
+        let mut tanks: BTreeMap<i64, TankUsage> = BTreeMap::new();
+        for record in of_kind(MesgNum::TankUpdate) {
+            let (timestamp, sensor, pressure) = match (
+                record.timestamp(),
+                sensor_id(record),
+                field_f64(record, &["pressure"]),
+            ) {
+                (Some(ts), Some(sensor), Some(pressure)) if in_range(idx, ts) => {
+                    (ts, sensor, pressure)
+                }
+                _ => continue,
+            };
+            tanks
+                .entry(sensor)
+                .or_insert_with(|| tank(sensor))
+                .pressures
+                .push((timestamp, pressure));
+        }
+        // summaries are written after the session ends so they belong to the last dive that
+        // started before them
+        for record in of_kind(MesgNum::TankSummary) {
+            let belongs = match (record.timestamp(), ranges.get(idx + 1)) {
+                (Some(ts), Some((Some(next_start), _))) => ts < *next_start,
+                _ => true,
+            } && record
+                .timestamp()
+                .is_none_or(|ts| ranges[idx].0.is_none_or(|s| ts >= s));
+            let sensor = match sensor_id(record) {
+                Some(sensor) if belongs => sensor,
+                _ => continue,
+            };
+            let usage = tanks.entry(sensor).or_insert_with(|| tank(sensor));
+            usage.start_pressure = field_f64(record, &["start_pressure"]);
+            usage.end_pressure = field_f64(record, &["end_pressure"]);
+            usage.volume_used = field_f64(record, &["volume_used"]);
+        }
+        for usage in tanks.values_mut() {
+            if usage.start_pressure.is_none() {
+                usage.start_pressure = usage.pressures.first().map(|p| p.1);
+            }
+            if usage.end_pressure.is_none() {
+                usage.end_pressure = usage.pressures.last().map(|p| p.1);
+            }
+            usage.pressure_sac = pressure_sac(&usage.pressures, &samples, water_density);
+        }
+
+        let summary = sessions.get(idx).and_then(|session| {
+            let session_index = field_f64(session, &["message_index"]).map_or(idx, |v| v as usize);
+            of_kind(MesgNum::DiveSummary)
+                .find(|r| {
+                    enum_value::<MesgNum>(r, "reference_mesg") == Some(MesgNum::Session)
+                        && field_f64(r, &["reference_index"]).map(|v| v as usize)
+                            == Some(session_index)
+                })
+                .cloned()
+        });
+
+        dives.push(Dive {
+            start,
+            end,
+            samples,
+            gas_switches,
+            tanks: tanks.into_values().collect(),
+            summary,
+        });
+    }
+
+    DiveLog {
+        settings,
+        gases,
+        alarms,
+        dives,
+    }
+}
+
+fn tank(sensor: i64) -> TankUsage {
+    TankUsage {
+        sensor,
+        pressures: Vec::new(),
+        start_pressure: None,
+        end_pressure: None,
+        volume_used: None,
+        pressure_sac: None,
+    }
+}
+
+fn sensor_id(record: &FitDataRecord) -> Option<i64> {
+    record.field("sensor")?.value().try_into().ok()
+}
+
+/// Pressure drop per minute between the first and last reading normalized to the surface
+fn pressure_sac(
+    pressures: &[(DateTime<Local>, f64)],
+    samples: &[DepthSample],
+    water_density: f64,
+) -> Option<f64> {
+    let (first, last) = (pressures.first()?, pressures.last()?);
+    let minutes = (last.0 - first.0).num_milliseconds() as f64 / 60_000.0;
+    if minutes <= 0.0 {
+        return None;
+    }
+    let depth = mean_depth(samples, Some(first.0), Some(last.0))?;
+    let ambient = SURFACE_PRESSURE + water_density * GRAVITY * depth / 100_000.0;
+    Some((first.1 - last.1) / minutes / (ambient / SURFACE_PRESSURE))
+}
+
+/// Time weighted mean of the depth samples between the two times, each sample holds until the
+/// next one
+fn mean_depth(
+    samples: &[DepthSample],
+    from: Option<DateTime<Local>>,
+    to: Option<DateTime<Local>>,
+) -> Option<f64> {
+    let samples: Vec<&DepthSample> = samples
+        .iter()
+        .filter(|s| from.is_none_or(|f| s.timestamp >= f) && to.is_none_or(|t| s.timestamp <= t))
+        .collect();
+    let (mut total, mut weighted) = (0.0, 0.0);
+    for pair in samples.windows(2) {
+        let secs = (pair[1].timestamp - pair[0].timestamp).num_milliseconds() as f64 / 1000.0;
+        total += secs;
+        weighted += pair[0].depth * secs;
+    }
+    if total > 0.0 {
+        Some(weighted / total)
+    } else {
+        samples.first().map(|s| s.depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::TimestampField;
+    use crate::test_util::{message, message_at};
+
+    fn string(val: &str) -> Value {
+        Value::String(val.to_string())
+    }
+
+    #[test]
+    fn dive_profile_gases_and_tanks() {
+        let mut records = vec![
+            message(MesgNum::DiveSettings, vec![("water_type", string("fresh"))]),
+            message(
+                MesgNum::DiveGas,
+                vec![
+                    ("oxygen_content", Value::UInt8(21)),
+                    ("message_index", Value::UInt16(0)),
+                ],
+            ),
+            message(
+                MesgNum::DiveGas,
+                vec![
+                    ("oxygen_content", Value::UInt8(50)),
+                    ("message_index", Value::UInt16(1)),
+                ],
+            ),
+        ];
+        // 10 minutes at 10 m then back to the surface
+        for minute in 0..=10 {
+            let depth = if minute == 0 || minute == 10 {
+                0.0
+            } else {
+                10.0
+            };
+            records.push(message_at(
+                MesgNum::Record,
+                minute * 60,
+                vec![
+                    ("depth", Value::Float64(depth)),
+                    ("n2_load", Value::UInt16(minute as u16 * 5)),
+                ],
+            ));
+        }
+        records.push(message_at(
+            MesgNum::Event,
+            480,
+            vec![
+                ("event", string("dive_gas_switched")),
+                ("data", Value::UInt32(1)),
+            ],
+        ));
+        for minute in 1..=9 {
+            records.push(message_at(
+                MesgNum::TankUpdate,
+                minute * 60,
+                vec![
+                    ("sensor", Value::UInt32z(42)),
+                    (
+                        "pressure",
+                        Value::Float64(200.0 - 8.0 * (minute - 1) as f64),
+                    ),
+                ],
+            ));
+        }
+        records.push(message_at(
+            MesgNum::Session,
+            600,
+            vec![
+                ("start_time", Value::from(TimestampField::Utc(0))),
+                ("message_index", Value::UInt16(0)),
+            ],
+        ));
+        records.push(message_at(
+            MesgNum::TankSummary,
+            610,
+            vec![
+                ("sensor", Value::UInt32z(42)),
+                ("start_pressure", Value::Float64(200.0)),
+                ("end_pressure", Value::Float64(130.0)),
+            ],
+        ));
+        records.push(message_at(
+            MesgNum::DiveSummary,
+            610,
+            vec![
+                ("reference_mesg", string("session")),
+                ("reference_index", Value::UInt16(0)),
+                ("max_depth", Value::Float64(10.5)),
+            ],
+        ));
+
+        let log = dive_log(&records);
+        assert_eq!(log.settings().unwrap().water_density(), 1000.0);
+        assert_eq!(log.gases().len(), 2);
+        assert_eq!(log.dives().len(), 1);
+        let dive = &log.dives()[0];
+        assert_eq!(dive.samples().len(), 11);
+        assert_eq!(dive.samples()[2].n2_load(), Some(10.0));
+        assert_eq!(dive.max_depth(), Some(10.5));
+        assert_eq!(dive.avg_depth(), Some(9.0));
+
+        assert_eq!(dive.gas_switches().len(), 1);
+        assert_eq!(dive.gas_switches()[0].gas().unwrap().oxygen(), 50.0);
+
+        let tank = &dive.tanks()[0];
+        assert_eq!(tank.sensor(), 42);
+        assert_eq!(tank.pressures().len(), 9);
+        assert_eq!(tank.pressure_used(), Some(70.0));
+        // 64 bar over 8 minutes at 10 m of fresh water
+        let ambient = SURFACE_PRESSURE + 1000.0 * GRAVITY * 10.0 / 100_000.0;
+        let expected = 8.0 / (ambient / SURFACE_PRESSURE);
+        assert!((tank.pressure_sac().unwrap() - expected).abs() < 1e-9);
+    }
+}
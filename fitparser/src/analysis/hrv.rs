@@ -0,0 +1,334 @@
+//! Heart rate variability helpers that assemble the beat-to-beat (RR) intervals stored in the
+//! `Hrv`, `BeatIntervals` and `RawBbi` messages into a single timestamp aligned series and
+//! compute common time domain HRV metrics from it.
+//!
+//! `BeatIntervals` and `RawBbi` messages carry their own `timestamp` and `timestamp_ms` fields
+//! and each beat is placed at the end of its interval. `Hrv` messages have no timestamp so they
+//! are anchored to the last timestamp seen in the file, assuming the intervals of a message end
+//! at that time, while never moving backwards past the end of the previous interval.
+use super::field_f64;
+use crate::profile::MesgNum;
+use crate::{FitDataRecord, Value};
+use chrono::{DateTime, Duration, Local};
+use std::convert::TryInto;
+
+/// Intervals outside of this range (in milliseconds) are not physiologically plausible and
+/// are always flagged as artifacts.
+const VALID_INTERVAL_RANGE: (f64, f64) = (250.0, 2500.0);
+
+/// Default maximum relative deviation from the local median before an interval is flagged
+pub const DEFAULT_ARTIFACT_TOLERANCE: f64 = 0.2;
+
+/// Number of intervals on either side used to compute the local median
+const MEDIAN_WINDOW: usize = 5;
+
+/// A single beat-to-beat interval
+#[derive(Clone, Debug, PartialEq)]
+pub struct RrInterval {
+    timestamp: Option<DateTime<Local>>,
+    duration: f64,
+    low_confidence: bool,
+    follows_gap: bool,
+    artifact: bool,
+}
+
+impl RrInterval {
+    fn new(
+        timestamp: Option<DateTime<Local>>,
+        duration: f64,
+        low_confidence: bool,
+        follows_gap: bool,
+    ) -> Self {
+        RrInterval {
+            timestamp,
+            duration,
+            low_confidence,
+            follows_gap,
+            artifact: false,
+        }
+    }
+
+    /// Time of the beat ending the interval, None if the file had no timestamp to anchor it to
+    pub fn timestamp(&self) -> Option<DateTime<Local>> {
+        self.timestamp
+    }
+
+    /// Length of the interval in milliseconds
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// True if the device marked the interval as low confidence (`RawBbi` quality of 0)
+    pub fn low_confidence(&self) -> bool {
+        self.low_confidence
+    }
+
+    /// True if a gap in the data precedes this interval, successive differences are not
+    /// computed across gaps
+    pub fn follows_gap(&self) -> bool {
+        self.follows_gap
+    }
+
+    /// True if the interval was flagged as an artifact and is excluded from the metrics
+    pub fn is_artifact(&self) -> bool {
+        self.artifact
+    }
+}
+
+/// Time domain HRV metrics computed from the intervals not flagged as artifacts
+#[derive(Clone, Debug)]
+pub struct HrvMetrics {
+    count: usize,
+    artifacts: usize,
+    mean_rr: f64,
+    sdnn: f64,
+    rmssd: Option<f64>,
+    pnn50: Option<f64>,
+}
+
+impl HrvMetrics {
+    /// Number of intervals used in the calculation
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Number of intervals that were excluded as artifacts
+    pub fn artifacts(&self) -> usize {
+        self.artifacts
+    }
+
+    /// Mean interval length in milliseconds
+    pub fn mean_rr(&self) -> f64 {
+        self.mean_rr
+    }
+
+    /// Mean heart rate in bpm derived from the mean interval
+    pub fn mean_heart_rate(&self) -> f64 {
+        60_000.0 / self.mean_rr
+    }
+
+    /// Standard deviation of the intervals in milliseconds
+    pub fn sdnn(&self) -> f64 {
+        self.sdnn
+    }
+
+    /// Root mean square of successive differences in milliseconds
+    pub fn rmssd(&self) -> Option<f64> {
+        self.rmssd
+    }
+
+    /// Percentage of successive differences greater than 50 milliseconds
+    pub fn pnn50(&self) -> Option<f64> {
+        self.pnn50
+    }
+}
+
+/// Assemble the RR intervals from every `Hrv`, `BeatIntervals` and `RawBbi` message in the
+/// records and flag artifacts using the default tolerance.
+pub fn rr_intervals(records: &[FitDataRecord]) -> Vec<RrInterval> {
+    let mut intervals = Vec::new();
+    let mut last_timestamp: Option<DateTime<Local>> = None;
+    // time of the last beat, used to keep the Hrv messages continuous
+    let mut clock: Option<DateTime<Local>> = None;
+
+    for record in records {
+        match record.kind() {
+            MesgNum::Hrv => {
+                let durations: Vec<f64> = numeric_values(record, "time")
+                    .into_iter()
+                    .flatten()
+                    .map(|v| v * 1000.0)
+                    .collect();
+                let total = Duration::milliseconds(durations.iter().sum::<f64>().round() as i64);
+                let mut time = match (last_timestamp.map(|ts| ts - total), clock) {
+                    (Some(anchor), Some(clock)) => Some(anchor.max(clock)),
+                    (anchor, clock) => anchor.or(clock),
+                };
+                for duration in durations {
+                    time = time.map(|t| t + Duration::milliseconds(duration.round() as i64));
+                    intervals.push(RrInterval::new(time, duration, false, false));
+                }
+                clock = time;
+            }
+            MesgNum::BeatIntervals | MesgNum::RawBbi => {
+                let mut time = record.timestamp().map(|ts| {
+                    ts + Duration::milliseconds(
+                        field_f64(record, &["timestamp_ms"]).unwrap_or(0.0) as i64
+                    )
+                });
+                let quality = numeric_values(record, "quality");
+                let gap = numeric_values(record, "gap");
+                let mut follows_gap = false;
+                for (idx, duration) in numeric_values(record, "time").into_iter().enumerate() {
+                    let duration = match duration {
+                        Some(val) => val,
+                        None => continue,
+                    };
+                    time = time.map(|t| t + Duration::milliseconds(duration.round() as i64));
+                    if gap.get(idx).copied().flatten() == Some(1.0) {
+                        // the value is the length of the gap rather than a beat interval
+                        follows_gap = true;
+                        continue;
+                    }
+                    let low_confidence = quality.get(idx).copied().flatten() == Some(0.0);
+                    intervals.push(RrInterval::new(time, duration, low_confidence, follows_gap));
+                    follows_gap = false;
+                }
+                clock = time.or(clock);
+            }
+            _ => {}
+        }
+        if let Some(ts) = record.timestamp() {
+            last_timestamp = Some(ts);
+        }
+    }
+
+    flag_artifacts(&mut intervals, DEFAULT_ARTIFACT_TOLERANCE);
+    intervals
+}
+
+/// Flag intervals as artifacts if they are outside of the plausible range, marked as low
+/// confidence or deviate from the median of the surrounding intervals by more than the
+/// tolerance (a fraction, e.g. 0.2 for 20%). Existing flags are replaced.
+pub fn flag_artifacts(intervals: &mut [RrInterval], tolerance: f64) {
+    let durations: Vec<f64> = intervals.iter().map(|i| i.duration).collect();
+    for (idx, interval) in intervals.iter_mut().enumerate() {
+        let lo = idx.saturating_sub(MEDIAN_WINDOW);
+        let hi = (idx + MEDIAN_WINDOW + 1).min(durations.len());
+        let mut window: Vec<f64> = durations[lo..hi]
+            .iter()
+            .copied()
+            .filter(|d| *d >= VALID_INTERVAL_RANGE.0 && *d <= VALID_INTERVAL_RANGE.1)
+            .collect();
+        window.sort_by(|a, b| a.total_cmp(b));
+        let median = window.get(window.len() / 2).copied();
+
+        interval.artifact = interval.low_confidence
+            || interval.duration < VALID_INTERVAL_RANGE.0
+            || interval.duration > VALID_INTERVAL_RANGE.1
+            || median.is_some_and(|m| (interval.duration - m).abs() > tolerance * m);
+    }
+}
+
+/// Compute the HRV metrics, successive differences are only taken between intervals that are
+/// adjacent in the series and neither of which is an artifact. None is returned if there are
+/// fewer than two usable intervals.
+pub fn metrics(intervals: &[RrInterval]) -> Option<HrvMetrics> {
+    let valid: Vec<f64> = intervals
+        .iter()
+        .filter(|i| !i.artifact)
+        .map(|i| i.duration)
+        .collect();
+    if valid.len() < 2 {
+        return None;
+    }
+    let mean_rr = valid.iter().sum::<f64>() / valid.len() as f64;
+    let variance =
+        valid.iter().map(|d| (d - mean_rr).powi(2)).sum::<f64>() / (valid.len() - 1) as f64;
+
+    let differences: Vec<f64> = intervals
+        .windows(2)
+        .filter(|w| !w[0].artifact && !w[1].artifact && !w[1].follows_gap)
+        .map(|w| w[1].duration - w[0].duration)
+        .collect();
+    let (rmssd, pnn50) = if differences.is_empty() {
+        (None, None)
+    } else {
+        let count = differences.len() as f64;
+        let rmssd = (differences.iter().map(|d| d * d).sum::<f64>() / count).sqrt();
+        let nn50 = differences.iter().filter(|d| d.abs() > 50.0).count() as f64;
+        (Some(rmssd), Some(100.0 * nn50 / count))
+    };
+
+    Some(HrvMetrics {
+        count: valid.len(),
+        artifacts: intervals.len() - valid.len(),
+        mean_rr,
+        sdnn: variance.sqrt(),
+        rmssd,
+        pnn50,
+    })
+}
+
+/// Collect the numeric values of every field with the given name in order, array values are
+/// flattened. Invalid array entries are kept as None so positions line up across fields.
+fn numeric_values(record: &FitDataRecord, name: &str) -> Vec<Option<f64>> {
+    let mut values = Vec::new();
+    for field in record.fields().iter().filter(|f| f.name() == name) {
+        match field.value() {
+            Value::Array(vals) => values.extend(vals.iter().map(|v| v.try_into().ok())),
+            val => values.push(val.try_into().ok()),
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::TimestampField;
+    use crate::test_util::{field, timestamp_field};
+
+    fn beat_intervals(offset: i64, times: &[u16]) -> FitDataRecord {
+        let mut record = FitDataRecord::new(MesgNum::BeatIntervals);
+        record.push(field("timestamp_ms", 0, Value::UInt16(500)));
+        record.push(field(
+            "time",
+            1,
+            Value::Array(times.iter().map(|t| Value::UInt16(*t)).collect()),
+        ));
+        record.push(timestamp_field(offset));
+        record
+    }
+
+    #[test]
+    fn beat_interval_series_and_metrics() {
+        let records = vec![
+            beat_intervals(1000, &[800, 850, 800, 850]),
+            beat_intervals(1004, &[800, 1900, 800, 850]),
+        ];
+        let intervals = rr_intervals(&records);
+        assert_eq!(intervals.len(), 8);
+        let first = intervals[0].timestamp().unwrap();
+        let base = match Value::from(TimestampField::Utc(1000)) {
+            Value::Timestamp(ts) => ts,
+            _ => unreachable!(),
+        };
+        assert_eq!(first - base, Duration::milliseconds(1300));
+        assert!(intervals[5].is_artifact());
+        assert_eq!(intervals.iter().filter(|i| i.is_artifact()).count(), 1);
+
+        let metrics = metrics(&intervals).unwrap();
+        assert_eq!(metrics.count(), 7);
+        assert_eq!(metrics.artifacts(), 1);
+        // successive differences around the artifact are skipped
+        assert_eq!(metrics.rmssd(), Some(50.0));
+        assert_eq!(metrics.pnn50(), Some(0.0));
+        assert!((metrics.mean_rr() - 5750.0 / 7.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn hrv_messages_follow_last_timestamp() {
+        let mut event = FitDataRecord::new(MesgNum::Event);
+        event.push(timestamp_field(2000));
+        let mut hrv = FitDataRecord::new(MesgNum::Hrv);
+        hrv.push(field(
+            "time",
+            0,
+            Value::Array(vec![
+                Value::Float64(1.0),
+                Value::Float64(1.0),
+                Value::Invalid,
+            ]),
+        ));
+        let intervals = rr_intervals(&[event.clone(), hrv.clone(), hrv]);
+        assert_eq!(intervals.len(), 4);
+        let end = match event.timestamp() {
+            Some(ts) => ts,
+            None => unreachable!(),
+        };
+        assert_eq!(intervals[1].timestamp(), Some(end));
+        assert_eq!(intervals[3].timestamp(), Some(end + Duration::seconds(2)));
+        assert_eq!(intervals[0].duration(), 1000.0);
+    }
+}
@@ -0,0 +1,135 @@
+//! Analysis routines that operate on decoded FIT records to derive values that are either not
+//! stored in the file or need to be recomputed from the raw `Record` stream.
+use crate::profile::field_types::{Event, EventType};
+use crate::profile::MesgNum;
+use crate::{FitDataRecord, Value};
+use chrono::{DateTime, Local};
+use std::convert::TryInto;
+
+pub mod devices;
+pub mod dive;
+pub mod hrv;
+pub mod monitoring;
+pub mod power;
+pub mod resample;
+pub mod sleep;
+pub mod summary;
+pub mod swim;
+
+/// Time ranges where the activity timer was running, derived from the timer `Event` messages.
+///
+/// A timer `start` event begins a period and any of the `stop` event types end it. If the file
+/// doesn't contain any timer events the timer is considered to be running from the first to the
+/// last timestamped record. A period that was never stopped ends at the last timestamp in the
+/// file.
+#[derive(Clone, Debug, Default)]
+pub struct TimerPeriods {
+    periods: Vec<(DateTime<Local>, DateTime<Local>)>,
+}
+
+impl TimerPeriods {
+    /// Build the timer periods from the `Event` messages present in the records
+    pub fn from_records(records: &[FitDataRecord]) -> Self {
+        let last_timestamp = records.iter().filter_map(|r| r.timestamp()).max();
+        let mut periods = Vec::new();
+        let mut started: Option<DateTime<Local>> = None;
+        let mut has_timer_events = false;
+
+        for record in records.iter().filter(|r| r.kind() == MesgNum::Event) {
+            if enum_value::<Event>(record, "event") != Some(Event::Timer) {
+                continue;
+            }
+            let timestamp = match record.timestamp() {
+                Some(ts) => ts,
+                None => continue,
+            };
+            has_timer_events = true;
+            match enum_value::<EventType>(record, "event_type") {
+                Some(EventType::Start) => {
+                    started.get_or_insert(timestamp);
+                }
+                Some(EventType::Stop)
+                | Some(EventType::StopAll)
+                | Some(EventType::StopDisable)
+                | Some(EventType::StopDisableAll) => {
+                    if let Some(start) = started.take() {
+                        periods.push((start, timestamp));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if !has_timer_events {
+            let first_timestamp = records.iter().filter_map(|r| r.timestamp()).min();
+            started = first_timestamp;
+        }
+        if let (Some(start), Some(end)) = (started, last_timestamp) {
+            if end >= start {
+                periods.push((start, end));
+            }
+        }
+
+        TimerPeriods { periods }
+    }
+
+    /// Return the (start, stop) pairs of each period the timer was running
+    pub fn periods(&self) -> &[(DateTime<Local>, DateTime<Local>)] {
+        &self.periods
+    }
+
+    /// Check if the timer was running at the given time, the start and stop times of a period are
+    /// both considered to be running.
+    pub fn is_running(&self, timestamp: DateTime<Local>) -> bool {
+        self.periods
+            .iter()
+            .any(|(start, stop)| *start <= timestamp && timestamp <= *stop)
+    }
+
+    /// Return the number of seconds the timer was running between the two times
+    pub fn running_time(&self, from: DateTime<Local>, to: DateTime<Local>) -> f64 {
+        self.periods
+            .iter()
+            .map(|(start, stop)| {
+                let start = (*start).max(from);
+                let stop = (*stop).min(to);
+                if stop > start {
+                    (stop - start).num_milliseconds() as f64 / 1000.0
+                } else {
+                    0.0
+                }
+            })
+            .sum()
+    }
+}
+
+/// Return the value of an enum field as the profile type regardless of whether the records were
+/// decoded with the `ReturnNumericEnumValues` option or not.
+pub(crate) fn enum_value<T>(record: &FitDataRecord, name: &str) -> Option<T>
+where
+    T: for<'a> From<&'a str> + From<i64>,
+{
+    match record.field(name)?.value() {
+        Value::String(val) => Some(T::from(val.as_str())),
+        val => {
+            let val: i64 = val.try_into().ok()?;
+            Some(T::from(val))
+        }
+    }
+}
+
+/// Return the value of the first field present in the record as an f64
+pub(crate) fn field_f64(record: &FitDataRecord, names: &[&str]) -> Option<f64> {
+    names
+        .iter()
+        .find_map(|name| record.field(name))
+        .and_then(|f| f.value().try_into().ok())
+}
+
+/// Return the value of a timestamp field other than `timestamp`, e.g. `start_time`
+pub(crate) fn field_timestamp(record: &FitDataRecord, name: &str) -> Option<DateTime<Local>> {
+    match record.field(name)?.value() {
+        Value::Timestamp(val) => Some(*val),
+        _ => None,
+    }
+}
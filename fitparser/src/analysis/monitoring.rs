@@ -0,0 +1,405 @@
+//! Aggregate the `Monitoring` messages recorded by activity trackers into hourly or daily totals.
+//!
+//! Monitoring files store most readings with a compressed `timestamp_16` field and the step,
+//! distance, calorie and active time counters are cumulative per activity type, resetting at
+//! local midnight. The device's local time offset is read from the `MonitoringInfo` message so
+//! the day boundaries line up with the counter resets.
+//! ```
+//! use fitparser::analysis::monitoring::{aggregate, Interval};
+//! use std::fs::File;
+//!
+//! let mut fp = File::open("tests/fixtures/MonitoringFile.fit")?;
+//! let records = fitparser::from_reader(&mut fp)?;
+//! for day in aggregate(&records, Interval::Day) {
+//!     println!("{}: {} steps, {:.0} kcal", day.start(), day.steps(), day.total_calories());
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use super::{enum_value, field_f64};
+use crate::profile::field_types::ActivityType;
+use crate::profile::{MesgNum, TimestampField};
+use crate::{FitDataRecord, Value};
+use chrono::{DateTime, Duration, Local};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Length of the periods the monitoring data is aggregated into
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interval {
+    /// One hour periods starting on the hour
+    Hour,
+    /// One day periods starting at local midnight
+    Day,
+}
+
+impl Interval {
+    fn seconds(self) -> i64 {
+        match self {
+            Interval::Hour => 3600,
+            Interval::Day => SECONDS_PER_DAY,
+        }
+    }
+}
+
+/// Totals of the monitoring data recorded during one interval
+#[derive(Clone, Debug)]
+pub struct MonitoringSummary {
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+    steps: u64,
+    distance: f64,
+    active_calories: f64,
+    resting_calories: Option<f64>,
+    active_time: f64,
+    moderate_activity_minutes: f64,
+    vigorous_activity_minutes: f64,
+    heart_rates: Vec<f64>,
+    resting_heart_rate: Option<f64>,
+}
+
+impl MonitoringSummary {
+    fn new(start: DateTime<Local>, end: DateTime<Local>) -> Self {
+        MonitoringSummary {
+            start,
+            end,
+            steps: 0,
+            distance: 0.0,
+            active_calories: 0.0,
+            resting_calories: None,
+            active_time: 0.0,
+            moderate_activity_minutes: 0.0,
+            vigorous_activity_minutes: 0.0,
+            heart_rates: Vec::new(),
+            resting_heart_rate: None,
+        }
+    }
+
+    /// Start of the interval
+    pub fn start(&self) -> DateTime<Local> {
+        self.start
+    }
+
+    /// End of the interval
+    pub fn end(&self) -> DateTime<Local> {
+        self.end
+    }
+
+    /// Steps taken while walking or running
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Distance covered in meters
+    pub fn distance(&self) -> f64 {
+        self.distance
+    }
+
+    /// Calories burned through activity in kcal
+    pub fn active_calories(&self) -> f64 {
+        self.active_calories
+    }
+
+    /// Calories burned at rest in kcal, prorated from the resting metabolic rate in the
+    /// `MonitoringInfo` message
+    pub fn resting_calories(&self) -> Option<f64> {
+        self.resting_calories
+    }
+
+    /// Sum of the active and resting calories in kcal
+    pub fn total_calories(&self) -> f64 {
+        self.active_calories + self.resting_calories.unwrap_or(0.0)
+    }
+
+    /// Time spent active in seconds
+    pub fn active_time(&self) -> f64 {
+        self.active_time
+    }
+
+    /// Minutes of moderate intensity activity
+    pub fn moderate_activity_minutes(&self) -> f64 {
+        self.moderate_activity_minutes
+    }
+
+    /// Minutes of vigorous intensity activity
+    pub fn vigorous_activity_minutes(&self) -> f64 {
+        self.vigorous_activity_minutes
+    }
+
+    /// Intensity minutes where each vigorous minute counts double
+    pub fn intensity_minutes(&self) -> f64 {
+        self.moderate_activity_minutes + 2.0 * self.vigorous_activity_minutes
+    }
+
+    /// Average of the heart rate readings in bpm
+    pub fn avg_heart_rate(&self) -> Option<f64> {
+        if self.heart_rates.is_empty() {
+            None
+        } else {
+            Some(self.heart_rates.iter().sum::<f64>() / self.heart_rates.len() as f64)
+        }
+    }
+
+    /// Lowest heart rate reading in bpm
+    pub fn min_heart_rate(&self) -> Option<f64> {
+        self.heart_rates.iter().copied().reduce(f64::min)
+    }
+
+    /// Highest heart rate reading in bpm
+    pub fn max_heart_rate(&self) -> Option<f64> {
+        self.heart_rates.iter().copied().reduce(f64::max)
+    }
+
+    /// Resting heart rate in bpm from the last `MonitoringHrData` message in the interval
+    pub fn resting_heart_rate(&self) -> Option<f64> {
+        self.resting_heart_rate
+    }
+}
+
+/// Last value of each cumulative counter of an activity type
+#[derive(Default)]
+struct Counters {
+    day: i64,
+    cycles: Option<f64>,
+    distance: Option<f64>,
+    calories: Option<f64>,
+    active_time: Option<f64>,
+}
+
+/// Return the increase of a cumulative counter since the previous reading, a value lower than
+/// the previous one means the counter was reset.
+fn counter_delta(previous: &mut Option<f64>, value: Option<f64>) -> f64 {
+    let value = match value {
+        Some(val) => val,
+        None => return 0.0,
+    };
+    match previous.replace(value) {
+        Some(prev) if value >= prev => value - prev,
+        _ => value,
+    }
+}
+
+/// Aggregate the `Monitoring` and `MonitoringHrData` messages into totals for each interval that
+/// contains data, ordered by time.
+///
+/// Summary messages that report the totals for a whole day (those with a `duration_min` field)
+/// are skipped since they repeat the cumulative readings. When a file starts part way through a
+/// day the first reading of each activity type includes everything counted since midnight.
+/// Distance and calories are derived from the cycle count using the `MonitoringInfo` ratios if
+/// the device didn't record them directly.
+pub fn aggregate(records: &[FitDataRecord], interval: Interval) -> Vec<MonitoringSummary> {
+    let timestamps = resolve_timestamps(records);
+    let info = records.iter().find(|r| r.kind() == MesgNum::MonitoringInfo);
+    let utc_offset = info.and_then(local_offset);
+    let ratios = info.map(cycle_ratios).unwrap_or_default();
+    let resting_metabolic_rate = info.and_then(|r| field_f64(r, &["resting_metabolic_rate"]));
+
+    let mut buckets: BTreeMap<i64, MonitoringSummary> = BTreeMap::new();
+    let mut counters: HashMap<Option<ActivityType>, Counters> = HashMap::new();
+    for (record, timestamp) in records.iter().zip(timestamps) {
+        let timestamp = match timestamp {
+            Some(ts) => ts,
+            None => continue,
+        };
+        let offset = utc_offset.unwrap_or_else(|| timestamp.offset().local_minus_utc() as i64);
+        let local = fit_seconds(timestamp) + offset;
+        let key = local - local.rem_euclid(interval.seconds()) - offset;
+        let bucket = || {
+            let start = fit_datetime(key);
+            let mut summary =
+                MonitoringSummary::new(start, start + Duration::seconds(interval.seconds()));
+            summary.resting_calories = resting_metabolic_rate
+                .map(|rmr| rmr * interval.seconds() as f64 / SECONDS_PER_DAY as f64);
+            summary
+        };
+
+        match record.kind() {
+            MesgNum::Monitoring if record.field("duration_min").is_none() => {
+                let summary = buckets.entry(key).or_insert_with(bucket);
+                let activity_type = enum_value::<ActivityType>(record, "activity_type");
+                let cycles = field_f64(record, &["cycles", "strokes"])
+                    .or_else(|| field_f64(record, &["steps"]).map(|v| v / 2.0));
+                let ratio = activity_type.and_then(|t| ratios.get(&t));
+                let distance =
+                    field_f64(record, &["distance"]).or_else(|| Some(cycles? * ratio?.0));
+                let calories =
+                    field_f64(record, &["active_calories"]).or_else(|| Some(cycles? * ratio?.1));
+
+                let day = local.div_euclid(SECONDS_PER_DAY);
+                let prev = counters.entry(activity_type).or_default();
+                if prev.day != day {
+                    *prev = Counters {
+                        day,
+                        ..Counters::default()
+                    };
+                }
+                // walking and running cycles are strides of two steps
+                let cycles = counter_delta(&mut prev.cycles, cycles);
+                if matches!(
+                    activity_type,
+                    Some(ActivityType::Walking) | Some(ActivityType::Running)
+                ) {
+                    summary.steps += (cycles * 2.0).round() as u64;
+                }
+                summary.distance += counter_delta(&mut prev.distance, distance);
+                summary.active_calories += counter_delta(&mut prev.calories, calories);
+                summary.active_time +=
+                    counter_delta(&mut prev.active_time, field_f64(record, &["active_time"]));
+                summary.moderate_activity_minutes +=
+                    field_f64(record, &["moderate_activity_minutes"]).unwrap_or(0.0);
+                summary.vigorous_activity_minutes +=
+                    field_f64(record, &["vigorous_activity_minutes"]).unwrap_or(0.0);
+                if let Some(hr) = field_f64(record, &["heart_rate"]).filter(|v| *v > 0.0) {
+                    summary.heart_rates.push(hr);
+                }
+            }
+            MesgNum::MonitoringHrData => {
+                let resting = field_f64(
+                    record,
+                    &["current_day_resting_heart_rate", "resting_heart_rate"],
+                );
+                if resting.is_some() {
+                    buckets.entry(key).or_insert_with(bucket).resting_heart_rate = resting;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    buckets.into_values().collect()
+}
+
+/// Return the timestamp of each record, resolving the compressed `timestamp_16` and
+/// `timestamp_min_8` fields against the most recent full timestamp in the file. Records without
+/// a timestamp or preceding the first full timestamp get None.
+pub fn resolve_timestamps(records: &[FitDataRecord]) -> Vec<Option<DateTime<Local>>> {
+    let mut last: Option<i64> = None;
+    records
+        .iter()
+        .map(|record| {
+            if let Some(timestamp) = record.timestamp() {
+                last = Some(fit_seconds(timestamp));
+                return Some(timestamp);
+            }
+            let base = last?;
+            let resolved = if let Some(ts16) = field_f64(record, &["timestamp_16"]) {
+                base + ((ts16 as i64 - base) & 0xFFFF)
+            } else if let Some(min8) = field_f64(record, &["timestamp_min_8"]) {
+                let minutes = base.div_euclid(60);
+                (minutes + ((min8 as i64 - minutes) & 0xFF)) * 60
+            } else {
+                return None;
+            };
+            last = Some(resolved);
+            Some(fit_datetime(resolved))
+        })
+        .collect()
+}
+
+/// Seconds since the FIT reference date
+fn fit_seconds(timestamp: DateTime<Local>) -> i64 {
+    TimestampField::from(timestamp).as_i64()
+}
+
+/// Convert seconds since the FIT reference date into a timestamp
+fn fit_datetime(seconds: i64) -> DateTime<Local> {
+    match Value::from(TimestampField::Utc(seconds)) {
+        Value::Timestamp(timestamp) => timestamp,
+        _ => unreachable!(),
+    }
+}
+
+/// Offset of the device's local time from UTC in seconds
+fn local_offset(info: &FitDataRecord) -> Option<i64> {
+    let local = match info.field("local_timestamp")?.value() {
+        Value::Timestamp(val) => *val,
+        _ => return None,
+    };
+    // local timestamps are decoded relative to the reference date in the system's time zone
+    let local_ref = match Value::from(TimestampField::Local(0)) {
+        Value::Timestamp(val) => val,
+        _ => return None,
+    };
+    Some((local - local_ref).num_seconds() - fit_seconds(info.timestamp()?))
+}
+
+/// Map each activity type to its (meters, kcal) per cycle ratios
+fn cycle_ratios(info: &FitDataRecord) -> HashMap<ActivityType, (f64, f64)> {
+    let values = |name: &str| -> Vec<Value> {
+        match info.field(name).map(|f| f.value()) {
+            Some(Value::Array(vals)) => vals.clone(),
+            Some(val) => vec![val.clone()],
+            None => Vec::new(),
+        }
+    };
+    let types = values("activity_type");
+    let distance = values("cycles_to_distance");
+    let calories = values("cycles_to_calories");
+    types
+        .iter()
+        .zip(distance.iter().zip(calories.iter()))
+        .filter_map(|(kind, (dist, cal))| {
+            let kind = match kind {
+                Value::String(val) => ActivityType::from(val.as_str()),
+                val => {
+                    let val: i64 = val.try_into().ok()?;
+                    ActivityType::from(val)
+                }
+            };
+            Some((kind, (dist.try_into().ok()?, cal.try_into().ok()?)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_bytes;
+
+    #[test]
+    fn monitoring_file_daily_totals() {
+        let data = include_bytes!("../../tests/fixtures/MonitoringFile.fit").to_vec();
+        let records = from_bytes(&data).unwrap();
+
+        let timestamps = resolve_timestamps(&records);
+        let resolved: Vec<_> = records
+            .iter()
+            .zip(&timestamps)
+            .filter(|(r, _)| r.kind() == MesgNum::Monitoring)
+            .map(|(_, ts)| ts.unwrap())
+            .collect();
+        assert!(resolved.windows(2).all(|w| w[0] <= w[1]));
+
+        // the day starts at local midnight, 06:00 UTC
+        let days = aggregate(&records, Interval::Day);
+        assert_eq!(days.len(), 2);
+        let info = records
+            .iter()
+            .find(|r| r.kind() == MesgNum::MonitoringInfo)
+            .unwrap();
+        assert_eq!(local_offset(info), Some(-6 * 3600));
+        assert_eq!(Some(days[0].start()), info.timestamp());
+        assert_eq!(days[0].end(), days[1].start());
+        // matches the walking and running steps in the daily summary messages
+        assert_eq!(days[0].steps(), 3142 + 31);
+        assert!((days[0].distance() - (1571.0 * 1.512 + 15.5 * 1.944)).abs() < 1e-6);
+        assert!((days[0].active_calories() - (1571.0 * 0.0438 + 15.5 * 0.1186)).abs() < 1e-6);
+        assert_eq!(days[0].resting_calories(), Some(2042.0));
+        assert_eq!(days[1].steps(), 0);
+
+        let hours = aggregate(&records, Interval::Hour);
+        assert!(hours.len() > 2);
+        assert_eq!(hours.iter().map(|h| h.steps()).sum::<u64>(), 3173);
+        assert_eq!(hours[0].resting_calories(), Some(2042.0 / 24.0));
+    }
+
+    #[test]
+    fn counter_reset() {
+        let mut prev = None;
+        assert_eq!(counter_delta(&mut prev, Some(100.0)), 100.0);
+        assert_eq!(counter_delta(&mut prev, None), 0.0);
+        assert_eq!(counter_delta(&mut prev, Some(150.0)), 50.0);
+        assert_eq!(counter_delta(&mut prev, Some(20.0)), 20.0);
+    }
+}
@@ -0,0 +1,241 @@
+//! Cycling power analytics computed from the `power` field of `Record` messages: normalized
+//! power, variability index, intensity factor, training stress score and a mean-maximal power
+//! curve.
+//!
+//! All calculations work on a 1 Hz power series that only covers the time the activity timer
+//! was running, see [`power_series`]. Pauses are therefore dropped instead of being treated as
+//! periods of zero power.
+use super::{field_f64, TimerPeriods};
+use crate::profile::MesgNum;
+use crate::FitDataRecord;
+use chrono::{DateTime, Duration, Local};
+
+/// Durations in seconds commonly reported on a power curve
+pub const STANDARD_DURATIONS: [usize; 14] = [
+    1, 5, 10, 15, 30, 60, 120, 300, 600, 1200, 1800, 3600, 5400, 7200,
+];
+
+/// Length in seconds of the rolling average used when computing normalized power
+const NORMALIZED_POWER_WINDOW: usize = 30;
+
+/// Summary of the power data recorded in an activity
+#[derive(Clone, Debug)]
+pub struct PowerMetrics {
+    duration: usize,
+    avg_power: f64,
+    max_power: f64,
+    normalized_power: Option<f64>,
+    functional_threshold_power: Option<f64>,
+}
+
+impl PowerMetrics {
+    /// Compute the metrics from a 1 Hz power series, the FTP is needed for the intensity
+    /// factor and training stress score. None is returned if the series is empty.
+    pub fn from_series(series: &[f64], ftp: Option<f64>) -> Option<Self> {
+        if series.is_empty() {
+            return None;
+        }
+        Some(PowerMetrics {
+            duration: series.len(),
+            avg_power: series.iter().sum::<f64>() / series.len() as f64,
+            max_power: series.iter().copied().fold(f64::MIN, f64::max),
+            normalized_power: normalized_power(series),
+            functional_threshold_power: ftp.filter(|v| *v > 0.0),
+        })
+    }
+
+    /// Number of seconds of power data the metrics were computed from
+    pub fn duration(&self) -> usize {
+        self.duration
+    }
+
+    /// Average power in watts
+    pub fn avg_power(&self) -> f64 {
+        self.avg_power
+    }
+
+    /// Maximum power in watts
+    pub fn max_power(&self) -> f64 {
+        self.max_power
+    }
+
+    /// Normalized power in watts, None if there is less than 30 seconds of data
+    pub fn normalized_power(&self) -> Option<f64> {
+        self.normalized_power
+    }
+
+    /// Functional threshold power in watts used for the IF and TSS calculations
+    pub fn functional_threshold_power(&self) -> Option<f64> {
+        self.functional_threshold_power
+    }
+
+    /// Ratio of normalized power to average power
+    pub fn variability_index(&self) -> Option<f64> {
+        if self.avg_power > 0.0 {
+            Some(self.normalized_power? / self.avg_power)
+        } else {
+            None
+        }
+    }
+
+    /// Ratio of normalized power to the functional threshold power
+    pub fn intensity_factor(&self) -> Option<f64> {
+        Some(self.normalized_power? / self.functional_threshold_power?)
+    }
+
+    /// Training stress score, 100 points equals one hour at FTP
+    pub fn training_stress_score(&self) -> Option<f64> {
+        let intensity_factor = self.intensity_factor()?;
+        Some(self.duration as f64 * intensity_factor * intensity_factor / 3600.0 * 100.0)
+    }
+}
+
+/// Compute the power metrics for an activity. If `ftp` is None the value is read from the
+/// `functional_threshold_power` field of the file's `ZonesTarget` message if one exists.
+pub fn analyze(records: &[FitDataRecord], ftp: Option<f64>) -> Option<PowerMetrics> {
+    let ftp = ftp.or_else(|| functional_threshold_power(records));
+    PowerMetrics::from_series(&power_series(records), ftp)
+}
+
+/// Return the FTP stored in the `ZonesTarget` message
+pub fn functional_threshold_power(records: &[FitDataRecord]) -> Option<f64> {
+    records
+        .iter()
+        .filter(|r| r.kind() == MesgNum::ZonesTarget)
+        .find_map(|r| field_f64(r, &["functional_threshold_power"]))
+}
+
+/// Build a 1 Hz power series from the `Record` messages covering every second the timer was
+/// running. Devices using smart recording don't store a record every second so the last
+/// recorded power is held until the next record, seconds before the first power value of a
+/// timer period are skipped.
+pub fn power_series(records: &[FitDataRecord]) -> Vec<f64> {
+    let timer = TimerPeriods::from_records(records);
+    let mut samples: Vec<(DateTime<Local>, f64)> = records
+        .iter()
+        .filter(|r| r.kind() == MesgNum::Record)
+        .filter_map(|r| Some((r.timestamp()?, field_f64(r, &["power"])?)))
+        .collect();
+    samples.sort_by_key(|(ts, _)| *ts);
+
+    let mut series = Vec::new();
+    for (start, stop) in timer.periods() {
+        let mut idx = samples.partition_point(|(ts, _)| ts < start);
+        let mut current: Option<f64> = None;
+        let mut time = *start;
+        while time < *stop {
+            while idx < samples.len() && samples[idx].0 <= time {
+                current = Some(samples[idx].1);
+                idx += 1;
+            }
+            if let Some(watts) = current {
+                series.push(watts);
+            }
+            time += Duration::seconds(1);
+        }
+    }
+
+    series
+}
+
+/// Compute normalized power from a 1 Hz series by raising the 30 second rolling average to the
+/// fourth power, averaging and taking the fourth root. None is returned if the series is
+/// shorter than the rolling window.
+pub fn normalized_power(series: &[f64]) -> Option<f64> {
+    if series.len() < NORMALIZED_POWER_WINDOW {
+        return None;
+    }
+    let mut window_sum: f64 = series[..NORMALIZED_POWER_WINDOW].iter().sum();
+    let mut total = (window_sum / NORMALIZED_POWER_WINDOW as f64).powi(4);
+    for idx in NORMALIZED_POWER_WINDOW..series.len() {
+        window_sum += series[idx] - series[idx - NORMALIZED_POWER_WINDOW];
+        total += (window_sum / NORMALIZED_POWER_WINDOW as f64).powi(4);
+    }
+    let count = series.len() - NORMALIZED_POWER_WINDOW + 1;
+    Some((total / count as f64).powf(0.25))
+}
+
+/// Compute the mean-maximal power for each duration (in seconds), the best average power
+/// sustained over any window of that length. Durations longer than the series are skipped.
+pub fn power_curve(series: &[f64], durations: &[usize]) -> Vec<(usize, f64)> {
+    let mut prefix = Vec::with_capacity(series.len() + 1);
+    prefix.push(0.0);
+    for watts in series {
+        prefix.push(prefix[prefix.len() - 1] + watts);
+    }
+
+    durations
+        .iter()
+        .filter(|d| **d > 0 && **d <= series.len())
+        .map(|d| {
+            let best = (*d..prefix.len())
+                .map(|end| prefix[end] - prefix[end - d])
+                .fold(f64::MIN, f64::max);
+            (*d, best / *d as f64)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::message_at;
+    use crate::{from_bytes, Value};
+
+    #[test]
+    fn series_skips_timer_pauses() {
+        // one minute at 200W, a one minute pause, then one minute at 300W recorded every 2s
+        let timer = |event_type: &str| {
+            vec![
+                ("event", Value::String("timer".to_string())),
+                ("event_type", Value::String(event_type.to_string())),
+            ]
+        };
+        let power = |watts: u16| vec![("power", Value::UInt16(watts))];
+        let mut records = vec![message_at(MesgNum::Event, 0, timer("start"))];
+        records.extend((0..60).map(|t| message_at(MesgNum::Record, t, power(200))));
+        records.push(message_at(MesgNum::Event, 60, timer("stop_all")));
+        records.extend((60..120).map(|t| message_at(MesgNum::Record, t, power(1000))));
+        records.push(message_at(MesgNum::Event, 120, timer("start")));
+        records.extend(
+            (120..180)
+                .step_by(2)
+                .map(|t| message_at(MesgNum::Record, t, power(300))),
+        );
+        records.push(message_at(MesgNum::Event, 180, timer("stop_all")));
+
+        let series = power_series(&records);
+        assert_eq!(series.len(), 120);
+        assert!(series[..60].iter().all(|w| *w == 200.0));
+        assert!(series[60..].iter().all(|w| *w == 300.0));
+
+        let metrics = analyze(&records, Some(250.0)).unwrap();
+        assert_eq!(metrics.avg_power(), 250.0);
+        assert_eq!(metrics.max_power(), 300.0);
+        let np = metrics.normalized_power().unwrap();
+        assert!(np > 250.0 && np < 300.0);
+        assert!(metrics.variability_index().unwrap() > 1.0);
+        assert!((metrics.intensity_factor().unwrap() - np / 250.0).abs() < 1e-9);
+
+        let curve = power_curve(&series, &STANDARD_DURATIONS);
+        assert_eq!(curve[0], (1, 300.0));
+        assert_eq!(curve.last(), Some(&(120, 250.0)));
+    }
+
+    #[test]
+    fn constant_power_tss() {
+        let series = vec![250.0; 3600];
+        let metrics = PowerMetrics::from_series(&series, Some(250.0)).unwrap();
+        assert!((metrics.normalized_power().unwrap() - 250.0).abs() < 1e-9);
+        assert!((metrics.training_stress_score().unwrap() - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn ftp_from_zones_target() {
+        let data = include_bytes!("../../tests/fixtures/garmin-fenix-5-bike.fit").to_vec();
+        let records = from_bytes(&data).unwrap();
+        assert_eq!(functional_threshold_power(&records), Some(400.0));
+        // the file has no power data
+        assert!(analyze(&records, None).is_none());
+    }
+}
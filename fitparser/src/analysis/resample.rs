@@ -0,0 +1,183 @@
+//! Resample the irregular `Record` stream onto a uniform time grid so the data can be plotted or
+//! used as model input without having to deal with smart recording, missing seconds or sparse
+//! developer fields.
+//! ```
+//! use chrono::Duration;
+//! use fitparser::analysis::resample::{resample, FillPolicy};
+//! use std::fs::File;
+//!
+//! let mut fp = File::open("tests/fixtures/garmin-fenix-5-bike.fit")?;
+//! let records = fitparser::from_reader(&mut fp)?;
+//! let series = resample(&records, &["heart_rate", "enhanced_speed"], Duration::seconds(1), FillPolicy::Linear);
+//! assert_eq!(series.timestamps().len(), series.column("heart_rate").unwrap().len());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::profile::MesgNum;
+use crate::FitDataRecord;
+use chrono::{DateTime, Duration, Local};
+use std::convert::TryInto;
+
+/// Defines how grid points that don't line up with a recorded value are filled in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FillPolicy {
+    /// Only grid points with a value recorded at exactly that time are populated
+    None,
+    /// Use the last recorded value at or before the grid point
+    ForwardFill,
+    /// Linearly interpolate between the recorded values surrounding the grid point
+    Linear,
+}
+
+/// Column oriented data sampled on a uniform time grid
+#[derive(Clone, Debug, Default)]
+pub struct ResampledSeries {
+    timestamps: Vec<DateTime<Local>>,
+    columns: Vec<(String, Vec<Option<f64>>)>,
+}
+
+impl ResampledSeries {
+    /// Times of each grid point
+    pub fn timestamps(&self) -> &[DateTime<Local>] {
+        &self.timestamps
+    }
+
+    /// Values of the named field at each grid point, None if the field wasn't requested
+    pub fn column(&self, name: &str) -> Option<&[Option<f64>]> {
+        self.columns
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    /// Names and values of every column in the order they were requested
+    pub fn columns(&self) -> &[(String, Vec<Option<f64>>)] {
+        &self.columns
+    }
+}
+
+/// Resample the numeric fields of every `Record` message onto a grid spanning the first to the
+/// last record timestamp with the given step. Field names are matched against both profile and
+/// developer fields; values that can't be represented as a number are ignored.
+///
+/// # Panics
+/// If the step is not a positive duration.
+pub fn resample(
+    records: &[FitDataRecord],
+    fields: &[&str],
+    step: Duration,
+    fill: FillPolicy,
+) -> ResampledSeries {
+    assert!(step > Duration::zero(), "resampling step must be positive");
+    let mut samples: Vec<(DateTime<Local>, &FitDataRecord)> = records
+        .iter()
+        .filter(|r| r.kind() == MesgNum::Record)
+        .filter_map(|r| r.timestamp().map(|ts| (ts, r)))
+        .collect();
+    samples.sort_by_key(|(ts, _)| *ts);
+    let (start, end) = match (samples.first(), samples.last()) {
+        (Some((start, _)), Some((end, _))) => (*start, *end),
+        _ => {
+            return ResampledSeries {
+                timestamps: Vec::new(),
+                columns: fields.iter().map(|f| (f.to_string(), Vec::new())).collect(),
+            }
+        }
+    };
+
+    let mut timestamps = Vec::new();
+    let mut time = start;
+    while time <= end {
+        timestamps.push(time);
+        time += step;
+    }
+
+    let columns = fields
+        .iter()
+        .map(|name| {
+            let values: Vec<(DateTime<Local>, f64)> = samples
+                .iter()
+                .filter_map(|(ts, r)| Some((*ts, r.field(name)?.value().try_into().ok()?)))
+                .collect();
+            let column = timestamps
+                .iter()
+                .map(|t| sample_at(&values, *t, fill))
+                .collect();
+            (name.to_string(), column)
+        })
+        .collect();
+
+    ResampledSeries {
+        timestamps,
+        columns,
+    }
+}
+
+/// Compute the value at the given time from the time sorted samples
+fn sample_at(
+    values: &[(DateTime<Local>, f64)],
+    time: DateTime<Local>,
+    fill: FillPolicy,
+) -> Option<f64> {
+    // index of the first sample after the time, the sample before it is at or before the time
+    let idx = values.partition_point(|(ts, _)| *ts <= time);
+    let prev = idx.checked_sub(1).map(|i| values[i]);
+    match fill {
+        FillPolicy::None => prev.filter(|(ts, _)| *ts == time).map(|(_, v)| v),
+        FillPolicy::ForwardFill => prev.map(|(_, v)| v),
+        FillPolicy::Linear => {
+            let (t0, v0) = prev?;
+            if t0 == time {
+                return Some(v0);
+            }
+            let (t1, v1) = values.get(idx).copied()?;
+            let frac = (time - t0).num_milliseconds() as f64 / (t1 - t0).num_milliseconds() as f64;
+            Some(v0 + (v1 - v0) * frac)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_bytes;
+
+    #[test]
+    fn resample_fill_policies() {
+        // records are written every 1-6 seconds using smart recording
+        let data = include_bytes!("../../tests/fixtures/garmin-fenix-5-bike.fit").to_vec();
+        let records = from_bytes(&data).unwrap();
+        let fields = ["heart_rate", "distance", "power"];
+
+        let none = resample(&records, &fields, Duration::seconds(1), FillPolicy::None);
+        let ffill = resample(
+            &records,
+            &fields,
+            Duration::seconds(1),
+            FillPolicy::ForwardFill,
+        );
+        let linear = resample(&records, &fields, Duration::seconds(1), FillPolicy::Linear);
+        assert_eq!(none.timestamps().len(), ffill.timestamps().len());
+        let len = none.timestamps().len();
+        let recorded = records
+            .iter()
+            .filter(|r| r.kind() == MesgNum::Record)
+            .count();
+        assert!(len > recorded);
+
+        let hr = none.column("heart_rate").unwrap();
+        assert_eq!(hr.iter().filter(|v| v.is_some()).count(), recorded);
+        assert!(ffill
+            .column("heart_rate")
+            .unwrap()
+            .iter()
+            .all(|v| v.is_some()));
+        assert!(none.column("power").unwrap().iter().all(|v| v.is_none()));
+        assert!(none.column("cadence").is_none());
+
+        // records at 16:09:23 (16.15m) and 16:09:25 (24.27m)
+        assert_eq!(none.column("distance").unwrap()[2], None);
+        assert_eq!(ffill.column("distance").unwrap()[2], Some(16.15));
+        let mid = linear.column("distance").unwrap()[2].unwrap();
+        assert!((mid - 20.21).abs() < 1e-9);
+    }
+}
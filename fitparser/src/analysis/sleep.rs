@@ -0,0 +1,427 @@
+//! Build sleep sessions from the `SleepLevel` and `SleepAssessment` messages of wellness files,
+//! with the `Spo2Data`, `RespirationRate` and `StressLevel` readings taken during the night
+//! overlaid on the stage timeline.
+//! ```
+//! use fitparser::analysis::sleep::sleep_sessions;
+//! use fitparser::profile::field_types::SleepLevel;
+//! use std::fs::File;
+//!
+//! let mut fp = File::open("tests/fixtures/MonitoringFile.fit")?;
+//! let records = fitparser::from_reader(&mut fp)?;
+//! for session in sleep_sessions(&records) {
+//!     println!("deep sleep: {}", session.stage_duration(SleepLevel::Deep));
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use super::{enum_value, field_f64, field_timestamp};
+use crate::profile::field_types::SleepLevel;
+use crate::profile::MesgNum;
+use crate::FitDataRecord;
+use chrono::{DateTime, Duration, Local};
+
+/// A gap between sleep level readings longer than this many seconds starts a new session
+const SESSION_GAP: i64 = 2 * 3600;
+
+/// A period spent in one sleep stage
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SleepStage {
+    level: SleepLevel,
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+}
+
+impl SleepStage {
+    /// Sleep level of the stage
+    pub fn level(&self) -> SleepLevel {
+        self.level
+    }
+
+    /// Start of the stage
+    pub fn start(&self) -> DateTime<Local> {
+        self.start
+    }
+
+    /// End of the stage
+    pub fn end(&self) -> DateTime<Local> {
+        self.end
+    }
+
+    /// Length of the stage
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+}
+
+/// Scores reported by the device in the `SleepAssessment` message
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SleepScores {
+    overall_sleep_score: Option<f64>,
+    sleep_quality_score: Option<f64>,
+    sleep_duration_score: Option<f64>,
+    sleep_recovery_score: Option<f64>,
+    deep_sleep_score: Option<f64>,
+    light_sleep_score: Option<f64>,
+    rem_sleep_score: Option<f64>,
+    awake_time_score: Option<f64>,
+    combined_awake_score: Option<f64>,
+    awakenings_count_score: Option<f64>,
+    awakenings_count: Option<f64>,
+    interruptions_score: Option<f64>,
+    sleep_restlessness_score: Option<f64>,
+    average_stress_during_sleep: Option<f64>,
+}
+
+impl SleepScores {
+    fn from_record(record: &FitDataRecord) -> Self {
+        let score = |name: &str| field_f64(record, &[name]);
+        SleepScores {
+            overall_sleep_score: score("overall_sleep_score"),
+            sleep_quality_score: score("sleep_quality_score"),
+            sleep_duration_score: score("sleep_duration_score"),
+            sleep_recovery_score: score("sleep_recovery_score"),
+            deep_sleep_score: score("deep_sleep_score"),
+            light_sleep_score: score("light_sleep_score"),
+            rem_sleep_score: score("rem_sleep_score"),
+            awake_time_score: score("awake_time_score"),
+            combined_awake_score: score("combined_awake_score"),
+            awakenings_count_score: score("awakenings_count_score"),
+            awakenings_count: score("awakenings_count"),
+            interruptions_score: score("interruptions_score"),
+            sleep_restlessness_score: score("sleep_restlessness_score"),
+            average_stress_during_sleep: score("average_stress_during_sleep"),
+        }
+    }
+
+    /// Overall sleep score
+    pub fn overall_sleep_score(&self) -> Option<f64> {
+        self.overall_sleep_score
+    }
+
+    /// Sleep quality score
+    pub fn sleep_quality_score(&self) -> Option<f64> {
+        self.sleep_quality_score
+    }
+
+    /// Sleep duration score
+    pub fn sleep_duration_score(&self) -> Option<f64> {
+        self.sleep_duration_score
+    }
+
+    /// Sleep recovery score
+    pub fn sleep_recovery_score(&self) -> Option<f64> {
+        self.sleep_recovery_score
+    }
+
+    /// Deep sleep score
+    pub fn deep_sleep_score(&self) -> Option<f64> {
+        self.deep_sleep_score
+    }
+
+    /// Light sleep score
+    pub fn light_sleep_score(&self) -> Option<f64> {
+        self.light_sleep_score
+    }
+
+    /// REM sleep score
+    pub fn rem_sleep_score(&self) -> Option<f64> {
+        self.rem_sleep_score
+    }
+
+    /// Awake time score
+    pub fn awake_time_score(&self) -> Option<f64> {
+        self.awake_time_score
+    }
+
+    /// Combined awake score
+    pub fn combined_awake_score(&self) -> Option<f64> {
+        self.combined_awake_score
+    }
+
+    /// Awakenings count score
+    pub fn awakenings_count_score(&self) -> Option<f64> {
+        self.awakenings_count_score
+    }
+
+    /// Number of times the sleeper woke up
+    pub fn awakenings_count(&self) -> Option<f64> {
+        self.awakenings_count
+    }
+
+    /// Interruptions score
+    pub fn interruptions_score(&self) -> Option<f64> {
+        self.interruptions_score
+    }
+
+    /// Sleep restlessness score
+    pub fn sleep_restlessness_score(&self) -> Option<f64> {
+        self.sleep_restlessness_score
+    }
+
+    /// Average stress level while sleeping
+    pub fn average_stress_during_sleep(&self) -> Option<f64> {
+        self.average_stress_during_sleep
+    }
+}
+
+/// A night of sleep made up of consecutive sleep stages
+#[derive(Clone, Debug)]
+pub struct SleepSession {
+    stages: Vec<SleepStage>,
+    scores: Option<SleepScores>,
+    spo2: Vec<(DateTime<Local>, f64)>,
+    respiration_rate: Vec<(DateTime<Local>, f64)>,
+    stress_level: Vec<(DateTime<Local>, f64)>,
+}
+
+impl SleepSession {
+    /// Start of the first stage
+    pub fn start(&self) -> DateTime<Local> {
+        self.stages[0].start
+    }
+
+    /// End of the last stage
+    pub fn end(&self) -> DateTime<Local> {
+        self.stages[self.stages.len() - 1].end
+    }
+
+    /// Time between the start and end of the session
+    pub fn duration(&self) -> Duration {
+        self.end() - self.start()
+    }
+
+    /// Stage timeline, consecutive readings of the same level are merged into one stage
+    pub fn stages(&self) -> &[SleepStage] {
+        &self.stages
+    }
+
+    /// Total time spent in the given sleep level
+    pub fn stage_duration(&self, level: SleepLevel) -> Duration {
+        self.stages
+            .iter()
+            .filter(|s| s.level == level)
+            .fold(Duration::zero(), |acc, s| acc + s.duration())
+    }
+
+    /// Time spent in the light, deep and REM stages
+    pub fn time_asleep(&self) -> Duration {
+        self.stage_duration(SleepLevel::Light)
+            + self.stage_duration(SleepLevel::Deep)
+            + self.stage_duration(SleepLevel::Rem)
+    }
+
+    /// Scores from the `SleepAssessment` message, if the device recorded one
+    pub fn scores(&self) -> Option<&SleepScores> {
+        self.scores.as_ref()
+    }
+
+    /// Blood oxygen saturation readings in percent taken during the session
+    pub fn spo2(&self) -> &[(DateTime<Local>, f64)] {
+        &self.spo2
+    }
+
+    /// Respiration rate readings in breaths/min taken during the session
+    pub fn respiration_rate(&self) -> &[(DateTime<Local>, f64)] {
+        &self.respiration_rate
+    }
+
+    /// Stress level readings taken during the session
+    pub fn stress_level(&self) -> &[(DateTime<Local>, f64)] {
+        &self.stress_level
+    }
+
+    /// Sleep level at the given time, None if it's outside of the session
+    pub fn level_at(&self, timestamp: DateTime<Local>) -> Option<SleepLevel> {
+        self.stages
+            .iter()
+            .find(|s| s.start <= timestamp && timestamp < s.end)
+            .map(|s| s.level)
+    }
+}
+
+/// Build the sleep sessions present in the records, ordered by time.
+///
+/// Each `SleepLevel` message marks the start of a stage which lasts until the next reading, so
+/// the last reading of a session only marks its end. Readings more than two hours apart are
+/// split into separate sessions. A `SleepAssessment` message is attached to the session it
+/// follows in the file. SpO2, respiration and stress readings without a valid measurement
+/// (negative or zero values) are skipped.
+pub fn sleep_sessions(records: &[FitDataRecord]) -> Vec<SleepSession> {
+    let mut sessions: Vec<SleepSession> = Vec::new();
+    let mut readings: Vec<(DateTime<Local>, SleepLevel)> = Vec::new();
+    let mut pending_scores: Option<SleepScores> = None;
+
+    let close = |readings: &mut Vec<(DateTime<Local>, SleepLevel)>,
+                 sessions: &mut Vec<SleepSession>,
+                 scores: Option<SleepScores>| {
+        if let Some(session) = build_session(readings, scores) {
+            sessions.push(session);
+        }
+        readings.clear();
+    };
+
+    for record in records {
+        match record.kind() {
+            MesgNum::SleepLevel => {
+                let (timestamp, level) = match (
+                    record.timestamp(),
+                    enum_value::<SleepLevel>(record, "sleep_level"),
+                ) {
+                    (Some(ts), Some(level)) => (ts, level),
+                    _ => continue,
+                };
+                if let Some((last, _)) = readings.last() {
+                    if (timestamp - *last).num_seconds() > SESSION_GAP {
+                        close(&mut readings, &mut sessions, pending_scores.take());
+                    }
+                }
+                readings.push((timestamp, level));
+            }
+            MesgNum::SleepAssessment => {
+                let scores = SleepScores::from_record(record);
+                if readings.len() > 1 {
+                    close(&mut readings, &mut sessions, Some(scores));
+                } else {
+                    pending_scores = Some(scores);
+                }
+            }
+            _ => {}
+        }
+    }
+    close(&mut readings, &mut sessions, pending_scores.take());
+
+    for session in sessions.iter_mut() {
+        let (start, end) = (session.start(), session.end());
+        let within = |ts: &DateTime<Local>| start <= *ts && *ts <= end;
+        for record in records {
+            let reading = match record.kind() {
+                MesgNum::Spo2Data => record.timestamp().zip(field_f64(record, &["reading_spo2"])),
+                MesgNum::RespirationRate => record
+                    .timestamp()
+                    .zip(field_f64(record, &["respiration_rate"])),
+                MesgNum::StressLevel => field_timestamp(record, "stress_level_time")
+                    .zip(field_f64(record, &["stress_level_value"])),
+                _ => None,
+            };
+            let (timestamp, value) = match reading {
+                Some((ts, val)) if within(&ts) && val > 0.0 => (ts, val),
+                _ => continue,
+            };
+            match record.kind() {
+                MesgNum::Spo2Data => session.spo2.push((timestamp, value)),
+                MesgNum::RespirationRate => session.respiration_rate.push((timestamp, value)),
+                _ => session.stress_level.push((timestamp, value)),
+            }
+        }
+        session.spo2.sort_by_key(|(ts, _)| *ts);
+        session.respiration_rate.sort_by_key(|(ts, _)| *ts);
+        session.stress_level.sort_by_key(|(ts, _)| *ts);
+    }
+
+    sessions
+}
+
+/// Turn the level readings into a session, None if there are fewer than two readings
+fn build_session(
+    readings: &mut [(DateTime<Local>, SleepLevel)],
+    scores: Option<SleepScores>,
+) -> Option<SleepSession> {
+    readings.sort_by_key(|(ts, _)| *ts);
+    let mut stages: Vec<SleepStage> = Vec::new();
+    for window in readings.windows(2) {
+        let ((start, level), (end, _)) = (window[0], window[1]);
+        match stages.last_mut() {
+            Some(stage) if stage.level == level => stage.end = end,
+            _ => stages.push(SleepStage { level, start, end }),
+        }
+    }
+    if stages.is_empty() {
+        return None;
+    }
+
+    Some(SleepSession {
+        stages,
+        scores,
+        spo2: Vec::new(),
+        respiration_rate: Vec::new(),
+        stress_level: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{field, message_at};
+    use crate::Value;
+
+    fn level(offset: i64, level: &str) -> FitDataRecord {
+        let value = Value::String(level.to_string());
+        message_at(MesgNum::SleepLevel, offset, vec![("sleep_level", value)])
+    }
+
+    #[test]
+    fn session_from_levels() {
+        let minute = 60;
+        let mut records = vec![
+            level(0, "awake"),
+            level(10 * minute, "light"),
+            level(20 * minute, "light"),
+            level(40 * minute, "deep"),
+            message_at(
+                MesgNum::Spo2Data,
+                45 * minute,
+                vec![("reading_spo2", Value::UInt8(94))],
+            ),
+            message_at(
+                MesgNum::RespirationRate,
+                50 * minute,
+                vec![("respiration_rate", Value::Float64(14.5))],
+            ),
+            message_at(
+                MesgNum::RespirationRate,
+                55 * minute,
+                vec![("respiration_rate", Value::Float64(-2.0))],
+            ),
+            level(70 * minute, "rem"),
+            level(90 * minute, "awake"),
+        ];
+        let mut assessment = FitDataRecord::new(MesgNum::SleepAssessment);
+        assessment.push(field("overall_sleep_score", 1, Value::UInt8(81)));
+        records.push(assessment);
+        // a nap in the afternoon
+        records.push(level(10 * 3600, "light"));
+        records.push(level(10 * 3600 + 20 * minute, "awake"));
+
+        let sessions = sleep_sessions(&records);
+        assert_eq!(sessions.len(), 2);
+        let night = &sessions[0];
+        let levels: Vec<_> = night.stages().iter().map(|s| s.level()).collect();
+        assert_eq!(
+            levels,
+            vec![
+                SleepLevel::Awake,
+                SleepLevel::Light,
+                SleepLevel::Deep,
+                SleepLevel::Rem
+            ]
+        );
+        assert_eq!(night.duration(), Duration::minutes(90));
+        assert_eq!(
+            night.stage_duration(SleepLevel::Light),
+            Duration::minutes(30)
+        );
+        assert_eq!(night.time_asleep(), Duration::minutes(80));
+        assert_eq!(night.scores().unwrap().overall_sleep_score(), Some(81.0));
+        assert_eq!(night.spo2().len(), 1);
+        assert_eq!(
+            night.respiration_rate()[..],
+            [(night.start() + Duration::minutes(50), 14.5)]
+        );
+        assert_eq!(
+            night.level_at(night.start() + Duration::minutes(45)),
+            Some(SleepLevel::Deep)
+        );
+
+        assert!(sessions[1].scores().is_none());
+        assert!(sessions[1].spo2().is_empty());
+    }
+}
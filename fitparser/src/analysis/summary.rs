@@ -0,0 +1,433 @@
+//! Compute session and lap summaries from the `Record` stream instead of relying on the totals
+//! stored in the `Session` and `Lap` messages, which may be missing or incorrect for files
+//! produced by third-party devices or edited after the fact.
+//! ```
+//! use fitparser::analysis::summary;
+//! use std::fs::File;
+//!
+//! let mut fp = File::open("tests/fixtures/Activity.fit")?;
+//! let records = fitparser::from_reader(&mut fp)?;
+//! let stored = records.iter().filter(|r| r.kind() == fitparser::profile::MesgNum::Session);
+//! for (computed, stored) in summary::session_summaries(&records).iter().zip(stored) {
+//!     for cmp in computed.compare(stored) {
+//!         println!("{}: {:?} (stored: {:?})", cmp.name(), cmp.computed(), cmp.stored());
+//!     }
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use super::{field_f64, field_timestamp, TimerPeriods};
+use crate::geo::{distance, position};
+use crate::profile::MesgNum;
+use crate::FitDataRecord;
+use chrono::{DateTime, Local};
+
+/// Summary values computed from the `Record` messages that fall within a session or lap.
+///
+/// Averages and maximums only consider records where the timer was running. Calories are
+/// estimated from the mechanical work when power data is available, assuming the common
+/// approximation of 1 kcal burned per 1 kJ of work. Ascent and descent are the sum of all
+/// positive and negative altitude changes without any smoothing applied.
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    start_time: Option<DateTime<Local>>,
+    total_elapsed_time: f64,
+    total_timer_time: f64,
+    total_distance: Option<f64>,
+    total_ascent: Option<f64>,
+    total_descent: Option<f64>,
+    avg_speed: Option<f64>,
+    max_speed: Option<f64>,
+    avg_heart_rate: Option<f64>,
+    max_heart_rate: Option<f64>,
+    avg_cadence: Option<f64>,
+    max_cadence: Option<f64>,
+    avg_power: Option<f64>,
+    max_power: Option<f64>,
+    total_work: Option<f64>,
+    total_calories: Option<f64>,
+}
+
+/// Running mean and maximum of a series of values
+#[derive(Default)]
+struct Stat {
+    sum: f64,
+    count: usize,
+    max: Option<f64>,
+}
+
+impl Stat {
+    fn push(&mut self, value: Option<f64>) {
+        if let Some(value) = value {
+            self.sum += value;
+            self.count += 1;
+            self.max = Some(self.max.map_or(value, |m| m.max(value)));
+        }
+    }
+
+    fn avg(&self) -> Option<f64> {
+        if self.count > 0 {
+            Some(self.sum / self.count as f64)
+        } else {
+            None
+        }
+    }
+}
+
+impl Summary {
+    /// Compute a summary from the `Record` messages in the slice. The start time is used as the
+    /// reference for the elapsed time and falls back to the first record's timestamp if None. The
+    /// cumulative distance of the record preceding the slice (if any) is used as the baseline
+    /// for the distance covered.
+    fn compute(
+        records: &[&FitDataRecord],
+        timer: &TimerPeriods,
+        start_time: Option<DateTime<Local>>,
+        preceding: Option<&FitDataRecord>,
+    ) -> Self {
+        let samples: Vec<(DateTime<Local>, &FitDataRecord)> = records
+            .iter()
+            .filter(|r| r.kind() == MesgNum::Record)
+            .filter_map(|r| r.timestamp().map(|ts| (ts, *r)))
+            .collect();
+        let start_time = start_time.or_else(|| samples.first().map(|(ts, _)| *ts));
+        let mut summary = Summary {
+            start_time,
+            ..Default::default()
+        };
+        let (first, last) = match (start_time, samples.last()) {
+            (Some(first), Some((last, _))) => (first, *last),
+            _ => return summary,
+        };
+        summary.total_elapsed_time = ((last - first).num_milliseconds() as f64 / 1000.0).max(0.0);
+        summary.total_timer_time = timer.running_time(first, last);
+
+        let mut speed = Stat::default();
+        let mut heart_rate = Stat::default();
+        let mut cadence = Stat::default();
+        let mut power = Stat::default();
+        let mut work: Option<f64> = None;
+        let mut ascent: Option<f64> = None;
+        let mut descent: Option<f64> = None;
+        let mut last_altitude: Option<f64> = None;
+        let mut last_position: Option<(f64, f64)> = None;
+        let mut gps_distance: Option<f64> = None;
+        let mut last_distance: Option<f64> = None;
+
+        for (idx, (timestamp, record)) in samples.iter().enumerate() {
+            if let Some(dist) = field_f64(record, &["distance"]) {
+                last_distance = Some(dist);
+            }
+            if let Some(pos) = position(record, "position_lat", "position_long") {
+                if let Some(prev) = last_position {
+                    *gps_distance.get_or_insert(0.0) += distance(prev, pos);
+                }
+                last_position = Some(pos);
+            }
+            if let Some(alt) = field_f64(record, &["enhanced_altitude", "altitude"]) {
+                if let Some(prev) = last_altitude {
+                    *ascent.get_or_insert(0.0) += (alt - prev).max(0.0);
+                    *descent.get_or_insert(0.0) += (prev - alt).max(0.0);
+                }
+                last_altitude = Some(alt);
+            }
+
+            if !timer.is_running(*timestamp) {
+                continue;
+            }
+            speed.push(field_f64(record, &["enhanced_speed", "speed"]));
+            heart_rate.push(field_f64(record, &["heart_rate"]));
+            cadence.push(field_f64(record, &["cadence"]));
+            let watts = field_f64(record, &["power"]);
+            power.push(watts);
+            if let (Some(watts), Some((next, _))) = (watts, samples.get(idx + 1)) {
+                *work.get_or_insert(0.0) += watts * timer.running_time(*timestamp, *next);
+            }
+        }
+
+        // distance fields are cumulative so the value reached before the summary started is
+        // subtracted from the final value
+        let baseline = preceding.and_then(|p| field_f64(p, &["distance"]));
+        summary.total_distance = match last_distance {
+            Some(last) => Some(last - baseline.unwrap_or(0.0)),
+            None => gps_distance,
+        };
+        summary.total_ascent = ascent;
+        summary.total_descent = descent;
+        summary.avg_speed = match summary.total_distance {
+            Some(dist) if summary.total_timer_time > 0.0 => Some(dist / summary.total_timer_time),
+            _ => speed.avg(),
+        };
+        summary.max_speed = speed.max;
+        summary.avg_heart_rate = heart_rate.avg();
+        summary.max_heart_rate = heart_rate.max;
+        summary.avg_cadence = cadence.avg();
+        summary.max_cadence = cadence.max;
+        summary.avg_power = power.avg();
+        summary.max_power = power.max;
+        summary.total_work = work;
+        summary.total_calories = work.map(|w| w / 1000.0);
+
+        summary
+    }
+
+    /// Time the summary starts at
+    pub fn start_time(&self) -> Option<DateTime<Local>> {
+        self.start_time
+    }
+
+    /// Seconds between the start time and the last record
+    pub fn total_elapsed_time(&self) -> f64 {
+        self.total_elapsed_time
+    }
+
+    /// Seconds the timer was running between the start time and the last record
+    pub fn total_timer_time(&self) -> f64 {
+        self.total_timer_time
+    }
+
+    /// Distance covered in meters, taken from the `distance` field or computed from the
+    /// positions if the records don't store a distance
+    pub fn total_distance(&self) -> Option<f64> {
+        self.total_distance
+    }
+
+    /// Total elevation gain in meters
+    pub fn total_ascent(&self) -> Option<f64> {
+        self.total_ascent
+    }
+
+    /// Total elevation loss in meters
+    pub fn total_descent(&self) -> Option<f64> {
+        self.total_descent
+    }
+
+    /// Average speed in m/s over the timer time
+    pub fn avg_speed(&self) -> Option<f64> {
+        self.avg_speed
+    }
+
+    /// Maximum recorded speed in m/s
+    pub fn max_speed(&self) -> Option<f64> {
+        self.max_speed
+    }
+
+    /// Average heart rate in bpm
+    pub fn avg_heart_rate(&self) -> Option<f64> {
+        self.avg_heart_rate
+    }
+
+    /// Maximum heart rate in bpm
+    pub fn max_heart_rate(&self) -> Option<f64> {
+        self.max_heart_rate
+    }
+
+    /// Average cadence in rpm
+    pub fn avg_cadence(&self) -> Option<f64> {
+        self.avg_cadence
+    }
+
+    /// Maximum cadence in rpm
+    pub fn max_cadence(&self) -> Option<f64> {
+        self.max_cadence
+    }
+
+    /// Average power in watts
+    pub fn avg_power(&self) -> Option<f64> {
+        self.avg_power
+    }
+
+    /// Maximum power in watts
+    pub fn max_power(&self) -> Option<f64> {
+        self.max_power
+    }
+
+    /// Mechanical work in joules
+    pub fn total_work(&self) -> Option<f64> {
+        self.total_work
+    }
+
+    /// Estimated energy expenditure in kcal
+    pub fn total_calories(&self) -> Option<f64> {
+        self.total_calories
+    }
+
+    /// Compare the computed values to those stored in a `Session` or `Lap` message, a comparison
+    /// is returned for every summary value even if one side of it is missing.
+    pub fn compare(&self, stored: &FitDataRecord) -> Vec<FieldComparison> {
+        let computed = [
+            ("total_elapsed_time", Some(self.total_elapsed_time)),
+            ("total_timer_time", Some(self.total_timer_time)),
+            ("total_distance", self.total_distance),
+            ("total_ascent", self.total_ascent),
+            ("total_descent", self.total_descent),
+            ("avg_speed", self.avg_speed),
+            ("max_speed", self.max_speed),
+            ("avg_heart_rate", self.avg_heart_rate),
+            ("max_heart_rate", self.max_heart_rate),
+            ("avg_cadence", self.avg_cadence),
+            ("max_cadence", self.max_cadence),
+            ("avg_power", self.avg_power),
+            ("max_power", self.max_power),
+            ("total_work", self.total_work),
+            ("total_calories", self.total_calories),
+        ];
+        computed
+            .iter()
+            .map(|(name, value)| {
+                // speed values are often only stored in the enhanced fields
+                let enhanced = format!("enhanced_{}", name);
+                FieldComparison {
+                    name,
+                    computed: *value,
+                    stored: field_f64(stored, &[name, &enhanced]),
+                }
+            })
+            .collect()
+    }
+}
+
+/// A computed summary value alongside the value stored in the FIT file
+#[derive(Clone, Debug)]
+pub struct FieldComparison {
+    name: &'static str,
+    computed: Option<f64>,
+    stored: Option<f64>,
+}
+
+impl FieldComparison {
+    /// Name of the field in the `Session` and `Lap` messages
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    /// Value computed from the records
+    pub fn computed(&self) -> Option<f64> {
+        self.computed
+    }
+
+    /// Value stored in the FIT file
+    pub fn stored(&self) -> Option<f64> {
+        self.stored
+    }
+
+    /// Difference between the computed and stored values if both exist
+    pub fn difference(&self) -> Option<f64> {
+        Some(self.computed? - self.stored?)
+    }
+}
+
+/// Compute a summary for the entire set of records
+pub fn summarize(records: &[FitDataRecord]) -> Summary {
+    let timer = TimerPeriods::from_records(records);
+    let records: Vec<&FitDataRecord> = records.iter().collect();
+    Summary::compute(&records, &timer, None, None)
+}
+
+/// Compute a summary for each `Session` message in the file. If the file has no sessions a
+/// single summary of all records is returned.
+pub fn session_summaries(records: &[FitDataRecord]) -> Vec<Summary> {
+    summaries_for(records, MesgNum::Session)
+}
+
+/// Compute a summary for each `Lap` message in the file. If the file has no laps a single
+/// summary of all records is returned.
+pub fn lap_summaries(records: &[FitDataRecord]) -> Vec<Summary> {
+    summaries_for(records, MesgNum::Lap)
+}
+
+/// Split the records into the time ranges defined by the `start_time` and `timestamp` fields of
+/// the given message kind and summarize each range. A range ends at the start of the next one or
+/// at its own timestamp, whichever comes first.
+fn summaries_for(records: &[FitDataRecord], kind: MesgNum) -> Vec<Summary> {
+    let timer = TimerPeriods::from_records(records);
+    let groups: Vec<&FitDataRecord> = records.iter().filter(|r| r.kind() == kind).collect();
+    if groups.is_empty() {
+        return vec![summarize(records)];
+    }
+    let starts: Vec<Option<DateTime<Local>>> = groups
+        .iter()
+        .map(|g| field_timestamp(g, "start_time"))
+        .collect();
+
+    groups
+        .iter()
+        .enumerate()
+        .map(|(idx, group)| {
+            let start = starts[idx];
+            let next_start = starts.get(idx + 1).copied().flatten();
+            let end = group.timestamp();
+            let members: Vec<&FitDataRecord> = records
+                .iter()
+                .filter(|r| r.kind() == MesgNum::Record)
+                .filter(|r| match r.timestamp() {
+                    Some(ts) => {
+                        start.is_none_or(|s| ts >= s)
+                            && next_start.is_none_or(|n| ts < n)
+                            && end.is_none_or(|e| ts <= e)
+                    }
+                    None => false,
+                })
+                .collect();
+            let preceding = start.and_then(|s| {
+                records
+                    .iter()
+                    .filter(|r| r.kind() == MesgNum::Record)
+                    .take_while(|r| r.timestamp().is_none_or(|ts| ts < s))
+                    .last()
+            });
+            Summary::compute(&members, &timer, start, preceding)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_bytes;
+
+    #[test]
+    fn activity_lap_summary() {
+        let data = include_bytes!("../../tests/fixtures/Activity.fit").to_vec();
+        let records = from_bytes(&data).unwrap();
+        let laps = lap_summaries(&records);
+        assert_eq!(laps.len(), 1);
+        let lap = &laps[0];
+        assert_eq!(lap.total_elapsed_time(), 13.0);
+        assert_eq!(lap.total_timer_time(), 13.0);
+        assert!((lap.total_distance().unwrap() - 5.73).abs() < 1e-9);
+        assert!((lap.max_speed().unwrap() - 0.368).abs() < 1e-9);
+        assert_eq!(lap.total_ascent(), Some(0.0));
+        assert_eq!(lap.avg_heart_rate(), None);
+
+        let stored = records.iter().find(|r| r.kind() == MesgNum::Lap).unwrap();
+        let comparison = lap.compare(stored);
+        let distance = comparison
+            .iter()
+            .find(|c| c.name() == "total_distance")
+            .unwrap();
+        assert!(distance.difference().unwrap().abs() < 1e-9);
+        let max_speed = comparison.iter().find(|c| c.name() == "max_speed").unwrap();
+        assert_eq!(max_speed.stored(), Some(0.368));
+    }
+
+    #[test]
+    fn timer_periods_from_events() {
+        let data = include_bytes!("../../tests/fixtures/garmin-fenix-5-bike.fit").to_vec();
+        let records = from_bytes(&data).unwrap();
+        let timer = TimerPeriods::from_records(&records);
+        assert_eq!(timer.periods().len(), 1);
+        let (start, stop) = timer.periods()[0];
+        assert_eq!((stop - start).num_seconds(), 61);
+
+        let session = &session_summaries(&records)[0];
+        let stored = records
+            .iter()
+            .find(|r| r.kind() == MesgNum::Session)
+            .unwrap();
+        for cmp in session.compare(stored) {
+            if cmp.name() == "max_heart_rate" {
+                assert_eq!(cmp.difference(), Some(0.0));
+            }
+        }
+    }
+}
@@ -0,0 +1,362 @@
+//! Length and interval summaries for pool swims built from the `Length` messages.
+//!
+//! Each `Length` is either an active length swum with strokes or an idle length recorded while
+//! resting at the wall. Consecutive active lengths form a swim interval and consecutive idle
+//! lengths a rest interval. SWOLF (seconds plus strokes per length) and the pace per 100 pool
+//! units use the `pool_length` and `pool_length_unit` of the `Session`, so the pace of a pool
+//! measured in yards is given per 100 yd.
+//! ```
+//! use fitparser::analysis::swim::pool_swim;
+//! # let records = Vec::new();
+//!
+//! if let Some(swim) = pool_swim(&records) {
+//!     for interval in swim.intervals().iter().filter(|i| !i.is_rest()) {
+//!         println!("{} m, {:?} s/100", interval.distance(), interval.pace());
+//!     }
+//! }
+//! ```
+use super::{enum_value, field_f64, field_timestamp};
+use crate::profile::field_types::{DisplayMeasure, LengthType, SwimStroke};
+use crate::profile::MesgNum;
+use crate::FitDataRecord;
+use chrono::{DateTime, Local};
+
+/// Meters in a yard
+const METERS_PER_YARD: f64 = 0.9144;
+
+/// A single pool length
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SwimLength {
+    start: Option<DateTime<Local>>,
+    duration: f64,
+    active: bool,
+    stroke: Option<SwimStroke>,
+    strokes: Option<u32>,
+    cadence: Option<f64>,
+    pool_length: f64,
+    pool_units: f64,
+}
+
+impl SwimLength {
+    /// Start of the length
+    pub fn start(&self) -> Option<DateTime<Local>> {
+        self.start
+    }
+
+    /// Time spent on the length in seconds
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// Check if the length was swum, idle lengths are rest at the wall
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Stroke used for the length
+    pub fn stroke(&self) -> Option<SwimStroke> {
+        self.stroke
+    }
+
+    /// Number of strokes taken
+    pub fn strokes(&self) -> Option<u32> {
+        self.strokes
+    }
+
+    /// Average stroke rate in strokes/min
+    pub fn cadence(&self) -> Option<f64> {
+        self.cadence
+    }
+
+    /// Distance covered in meters, 0 for idle lengths
+    pub fn distance(&self) -> f64 {
+        if self.active {
+            self.pool_length
+        } else {
+            0.0
+        }
+    }
+
+    /// Seconds plus strokes for the length, only available for active lengths with a stroke
+    /// count
+    pub fn swolf(&self) -> Option<f64> {
+        if !self.active {
+            return None;
+        }
+        self.strokes.map(|s| self.duration + s as f64)
+    }
+
+    /// Seconds per 100 pool units (meters or yards)
+    pub fn pace(&self) -> Option<f64> {
+        let units = if self.active { self.pool_units } else { 0.0 };
+        pace(self.duration, units)
+    }
+}
+
+/// A run of consecutive active lengths or a rest made of idle lengths
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwimInterval {
+    lengths: Vec<SwimLength>,
+}
+
+impl SwimInterval {
+    /// Lengths of the interval
+    pub fn lengths(&self) -> &[SwimLength] {
+        &self.lengths
+    }
+
+    /// Check if the interval is a rest
+    pub fn is_rest(&self) -> bool {
+        self.lengths.iter().all(|l| !l.active)
+    }
+
+    /// Start of the interval
+    pub fn start(&self) -> Option<DateTime<Local>> {
+        self.lengths.first().and_then(|l| l.start)
+    }
+
+    /// Duration of the interval in seconds
+    pub fn duration(&self) -> f64 {
+        self.lengths.iter().map(|l| l.duration).sum()
+    }
+
+    /// Distance of the interval in meters
+    pub fn distance(&self) -> f64 {
+        self.lengths.iter().map(|l| l.distance()).sum()
+    }
+
+    /// Total number of strokes, None if no length has a stroke count
+    pub fn strokes(&self) -> Option<u32> {
+        self.lengths
+            .iter()
+            .filter_map(|l| l.strokes)
+            .fold(None, |acc, s| Some(acc.unwrap_or(0) + s))
+    }
+
+    /// Stroke used for the interval, mixed if the lengths used different strokes
+    pub fn stroke(&self) -> Option<SwimStroke> {
+        let mut strokes = self.lengths.iter().filter_map(|l| l.stroke);
+        let first = strokes.next()?;
+        if strokes.all(|s| s == first) {
+            Some(first)
+        } else {
+            Some(SwimStroke::Mixed)
+        }
+    }
+
+    /// Average SWOLF of the lengths that have one
+    pub fn avg_swolf(&self) -> Option<f64> {
+        mean(self.lengths.iter().filter_map(|l| l.swolf()))
+    }
+
+    /// Seconds per 100 pool units (meters or yards)
+    pub fn pace(&self) -> Option<f64> {
+        let units = self
+            .lengths
+            .iter()
+            .filter(|l| l.active)
+            .map(|l| l.pool_units)
+            .sum();
+        pace(self.duration(), units)
+    }
+}
+
+/// Lengths and intervals of a pool swim
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoolSwim {
+    pool_length: f64,
+    pool_length_unit: DisplayMeasure,
+    lengths: Vec<SwimLength>,
+    intervals: Vec<SwimInterval>,
+}
+
+impl PoolSwim {
+    /// Length of the pool in meters
+    pub fn pool_length(&self) -> f64 {
+        self.pool_length
+    }
+
+    /// Unit the pool length was entered in, yards for statute
+    pub fn pool_length_unit(&self) -> DisplayMeasure {
+        self.pool_length_unit
+    }
+
+    /// All lengths in order
+    pub fn lengths(&self) -> &[SwimLength] {
+        &self.lengths
+    }
+
+    /// Swim and rest intervals in order
+    pub fn intervals(&self) -> &[SwimInterval] {
+        &self.intervals
+    }
+
+    /// Number of active lengths
+    pub fn active_lengths(&self) -> usize {
+        self.lengths.iter().filter(|l| l.active).count()
+    }
+
+    /// Total distance swum in meters
+    pub fn distance(&self) -> f64 {
+        self.lengths.iter().map(|l| l.distance()).sum()
+    }
+
+    /// Time spent swimming in seconds, excluding rests
+    pub fn swim_time(&self) -> f64 {
+        self.lengths
+            .iter()
+            .filter(|l| l.active)
+            .map(|l| l.duration)
+            .sum()
+    }
+
+    /// Time spent resting in seconds
+    pub fn rest_time(&self) -> f64 {
+        self.lengths
+            .iter()
+            .filter(|l| !l.active)
+            .map(|l| l.duration)
+            .sum()
+    }
+
+    /// Average SWOLF of all active lengths
+    pub fn avg_swolf(&self) -> Option<f64> {
+        mean(self.lengths.iter().filter_map(|l| l.swolf()))
+    }
+
+    /// Seconds per 100 pool units (meters or yards) of swim time
+    pub fn pace(&self) -> Option<f64> {
+        let pool_units = match self.pool_length_unit {
+            DisplayMeasure::Statute => self.pool_length / METERS_PER_YARD,
+            _ => self.pool_length,
+        };
+        pace(self.swim_time(), self.active_lengths() as f64 * pool_units)
+    }
+}
+
+/// Build the length and interval summaries of a pool swim. The pool length is taken from the
+/// first `Session` that has one, None is returned if the records don't describe a pool swim.
+pub fn pool_swim(records: &[FitDataRecord]) -> Option<PoolSwim> {
+    let session = records
+        .iter()
+        .filter(|r| r.kind() == MesgNum::Session)
+        .find(|r| field_f64(r, &["pool_length"]).is_some_and(|l| l > 0.0))?;
+    let pool_length = field_f64(session, &["pool_length"])?;
+    let pool_length_unit =
+        enum_value::<DisplayMeasure>(session, "pool_length_unit").unwrap_or(DisplayMeasure::Metric);
+    let pool_units = match pool_length_unit {
+        DisplayMeasure::Statute => pool_length / METERS_PER_YARD,
+        _ => pool_length,
+    };
+
+    let lengths: Vec<SwimLength> = records
+        .iter()
+        .filter(|r| r.kind() == MesgNum::Length)
+        .map(|r| SwimLength {
+            start: field_timestamp(r, "start_time").or_else(|| r.timestamp()),
+            duration: field_f64(r, &["total_timer_time", "total_elapsed_time"]).unwrap_or(0.0),
+            active: enum_value::<LengthType>(r, "length_type") != Some(LengthType::Idle),
+            stroke: enum_value::<SwimStroke>(r, "swim_stroke"),
+            strokes: field_f64(r, &["total_strokes"]).map(|s| s as u32),
+            cadence: field_f64(r, &["avg_swimming_cadence"]),
+            pool_length,
+            pool_units,
+        })
+        .collect();
+
+    let mut intervals: Vec<SwimInterval> = Vec::new();
+    for length in &lengths {
+        match intervals.last_mut() {
+            Some(interval) if interval.lengths[0].active == length.active => {
+                interval.lengths.push(*length)
+            }
+            _ => intervals.push(SwimInterval {
+                lengths: vec![*length],
+            }),
+        }
+    }
+
+    Some(PoolSwim {
+        pool_length,
+        pool_length_unit,
+        lengths,
+        intervals,
+    })
+}
+
+fn pace(duration: f64, units: f64) -> Option<f64> {
+    if units > 0.0 {
+        Some(duration / units * 100.0)
+    } else {
+        None
+    }
+}
+
+fn mean<I: Iterator<Item = f64>>(values: I) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    if count > 0 {
+        Some(sum / count as f64)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::message;
+    use crate::Value;
+
+    fn length(length_type: &str, time: f64, stroke: &str, strokes: u16) -> FitDataRecord {
+        message(
+            MesgNum::Length,
+            vec![
+                ("total_timer_time", Value::Float64(time)),
+                ("length_type", Value::String(length_type.to_string())),
+                ("swim_stroke", Value::String(stroke.to_string())),
+                ("total_strokes", Value::UInt16(strokes)),
+            ],
+        )
+    }
+
+    #[test]
+    fn intervals_swolf_and_pace() {
+        let mut records = vec![
+            length("active", 20.0, "freestyle", 15),
+            length("active", 22.0, "freestyle", 16),
+            length("idle", 30.0, "freestyle", 0),
+            length("active", 25.0, "breaststroke", 12),
+            length("active", 24.0, "freestyle", 16),
+        ];
+        records.push(message(
+            MesgNum::Session,
+            vec![
+                ("pool_length", Value::Float64(22.86)),
+                ("pool_length_unit", Value::String("statute".to_string())),
+            ],
+        ));
+
+        let swim = pool_swim(&records).unwrap();
+        assert_eq!(swim.active_lengths(), 4);
+        assert!((swim.distance() - 4.0 * 22.86).abs() < 1e-9);
+        assert_eq!(swim.rest_time(), 30.0);
+        assert_eq!(swim.swim_time(), 91.0);
+        // 25 yd pool, 100 yd in 91 s
+        assert!((swim.pace().unwrap() - 91.0).abs() < 1e-9);
+        assert_eq!(swim.avg_swolf(), Some((35.0 + 38.0 + 37.0 + 40.0) / 4.0));
+
+        let intervals = swim.intervals();
+        assert_eq!(intervals.len(), 3);
+        assert!(!intervals[0].is_rest());
+        assert!(intervals[1].is_rest());
+        assert_eq!(intervals[0].stroke(), Some(SwimStroke::Freestyle));
+        assert_eq!(intervals[0].strokes(), Some(31));
+        assert_eq!(intervals[0].avg_swolf(), Some(36.5));
+        assert!((intervals[0].pace().unwrap() - 84.0).abs() < 1e-9);
+        assert_eq!(intervals[1].pace(), None);
+        assert_eq!(intervals[2].stroke(), Some(SwimStroke::Mixed));
+        assert_eq!(swim.lengths()[2].swolf(), None);
+
+        assert!(pool_swim(&records[..4]).is_none());
+    }
+}
@@ -0,0 +1,517 @@
+//! Strip personal information from FIT files before sharing them.
+//!
+//! The anonymizer edits the fields of the original messages in place, so definitions, developer
+//! data and compressed timestamp headers are kept, and writes a new file with the header data
+//! size and CRC values recomputed. By default serial numbers of the `FileId` and `DeviceInfo`
+//! messages and the name, age, height, weight and global ID of the `UserProfile` are removed.
+//! Positions inside privacy zones can be masked or their `Record` messages dropped and all
+//! timestamps can be shifted by a fixed offset.
+//! ```
+//! use chrono::Duration;
+//! use fitparser::anonymize::Anonymizer;
+//!
+//! let data = std::fs::read("tests/fixtures/Activity.fit")?;
+//! let mut anonymizer = Anonymizer::new();
+//! anonymizer.push_privacy_zone(47.3769, 8.5417, 500.0);
+//! anonymizer.set_time_offset(Duration::days(-3));
+//! let anonymized = anonymizer.anonymize(&data)?;
+//!
+//! let records = fitparser::from_bytes(&anonymized)?;
+//! assert!(records[0].field("serial_number").is_none());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::de::{FieldMap, FitDefinitionMessage, FitStreamProcessor};
+use crate::error::{ErrorKind, Result};
+use crate::geo::{distance, semicircles_to_degrees};
+use crate::profile::field_types::FitBaseType;
+use crate::profile::MesgNum;
+use crate::raw::{self, RawObject};
+use crate::repair::write_file;
+use crate::{FitDataRecord, Value};
+use chrono::Duration;
+use nom::number::Endianness;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Timestamps below this value are relative to the device power on and are never shifted
+const MIN_ABSOLUTE_TIMESTAMP: u32 = 0x1000_0000;
+
+/// `UserProfile` fields removed when stripping the user profile
+const USER_PROFILE_FIELDS: [&str; 5] = ["friendly_name", "age", "height", "weight", "global_id"];
+
+/// How serial numbers are anonymized
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SerialNumbers {
+    /// Keep the serial numbers
+    Keep,
+    /// Remove the serial numbers
+    Remove,
+    /// Replace each serial number with a value derived from it and the seed, so the same device
+    /// gets the same replacement in every file anonymized with the seed
+    Fuzz(u64),
+}
+
+/// What happens to positions inside a privacy zone
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrivacyAction {
+    /// Remove the position fields and keep the rest of the message
+    Mask,
+    /// Drop `Record` messages entirely, positions of other messages are masked. Records are
+    /// masked instead if the next message uses a compressed timestamp header that depends on
+    /// their timestamp.
+    Remove,
+}
+
+/// A circular area whose positions are hidden
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PrivacyZone {
+    latitude: f64,
+    longitude: f64,
+    radius: f64,
+}
+
+impl PrivacyZone {
+    /// Latitude of the center in degrees
+    pub fn latitude(&self) -> f64 {
+        self.latitude
+    }
+
+    /// Longitude of the center in degrees
+    pub fn longitude(&self) -> f64 {
+        self.longitude
+    }
+
+    /// Radius in meters
+    pub fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    /// Check if the position given in degrees is inside the zone
+    pub fn contains(&self, position: (f64, f64)) -> bool {
+        distance((self.latitude, self.longitude), position) <= self.radius
+    }
+}
+
+/// Anonymizes FIT files using the configured options
+#[derive(Clone, Debug, PartialEq)]
+pub struct Anonymizer {
+    serial_numbers: SerialNumbers,
+    strip_user_profile: bool,
+    privacy_zones: Vec<PrivacyZone>,
+    privacy_action: PrivacyAction,
+    time_offset: Duration,
+}
+
+impl Default for Anonymizer {
+    fn default() -> Self {
+        Anonymizer {
+            serial_numbers: SerialNumbers::Remove,
+            strip_user_profile: true,
+            privacy_zones: Vec::new(),
+            privacy_action: PrivacyAction::Mask,
+            time_offset: Duration::zero(),
+        }
+    }
+}
+
+impl Anonymizer {
+    /// Create an anonymizer that removes serial numbers and the user profile
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How serial numbers are anonymized, defaults to removing them
+    pub fn serial_numbers(&self) -> SerialNumbers {
+        self.serial_numbers
+    }
+
+    /// Set how serial numbers are anonymized
+    pub fn set_serial_numbers(&mut self, serial_numbers: SerialNumbers) {
+        self.serial_numbers = serial_numbers;
+    }
+
+    /// Check if personal `UserProfile` fields are removed, defaults to true
+    pub fn strip_user_profile(&self) -> bool {
+        self.strip_user_profile
+    }
+
+    /// Set if personal `UserProfile` fields are removed
+    pub fn set_strip_user_profile(&mut self, strip_user_profile: bool) {
+        self.strip_user_profile = strip_user_profile;
+    }
+
+    /// Privacy zones in the order they were added
+    pub fn privacy_zones(&self) -> &[PrivacyZone] {
+        &self.privacy_zones
+    }
+
+    /// Add a privacy zone centered on the position in degrees with a radius in meters
+    pub fn push_privacy_zone(&mut self, latitude: f64, longitude: f64, radius: f64) {
+        self.privacy_zones.push(PrivacyZone {
+            latitude,
+            longitude,
+            radius,
+        });
+    }
+
+    /// What happens to positions inside a privacy zone, defaults to masking them
+    pub fn privacy_action(&self) -> PrivacyAction {
+        self.privacy_action
+    }
+
+    /// Set what happens to positions inside a privacy zone
+    pub fn set_privacy_action(&mut self, privacy_action: PrivacyAction) {
+        self.privacy_action = privacy_action;
+    }
+
+    /// Offset added to all timestamps, defaults to zero
+    pub fn time_offset(&self) -> Duration {
+        self.time_offset
+    }
+
+    /// Set the offset added to all timestamps, only whole seconds are used
+    pub fn set_time_offset(&mut self, time_offset: Duration) {
+        self.time_offset = time_offset;
+    }
+
+    /// Anonymize the FIT data and return the bytes of the new file
+    pub fn anonymize(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let objects = raw::from_bytes(data)?;
+        let mut processor = FitStreamProcessor::new();
+        let mut definitions: HashMap<u8, Arc<FitDefinitionMessage>> = HashMap::new();
+        let mut output = Vec::with_capacity(data.len());
+        let mut header: &[u8] = &[];
+        let mut messages = Vec::new();
+
+        for (idx, obj) in objects.iter().enumerate() {
+            match obj {
+                RawObject::Header(raw_header) => header = raw_header.bytes(),
+                RawObject::DefinitionMessage(msg) => {
+                    definitions.insert(msg.local_message_number(), Arc::clone(msg.message()));
+                    messages.extend_from_slice(msg.bytes());
+                }
+                RawObject::DataMessage(msg) => {
+                    let definition = definitions.get(&msg.local_message_number()).ok_or(
+                        ErrorKind::MissingDefinitionMessage(msg.local_message_number(), idx),
+                    )?;
+                    let record = processor.decode_message(msg.message().clone())?;
+                    let mut bytes = msg.bytes().to_vec();
+                    let mut editor = MessageEditor::new(&mut bytes, definition);
+                    if msg.is_compressed_timestamp() {
+                        editor.shift_time_offset(self.time_offset.num_seconds());
+                    }
+                    if self.edit_message(&mut editor, msg.message().fields(), &record)?
+                        && !next_is_compressed_timestamp(&objects[idx + 1..])
+                    {
+                        continue;
+                    }
+                    messages.extend_from_slice(&bytes);
+                }
+                RawObject::Crc(_) => {
+                    write_file(header, &messages, &mut output);
+                    messages.clear();
+                    definitions.clear();
+                    processor.reset();
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Anonymize the fields of a single message, returns true if the message is a `Record` that
+    /// should be removed
+    fn edit_message(
+        &self,
+        editor: &mut MessageEditor,
+        fields: &FieldMap,
+        record: &FitDataRecord,
+    ) -> Result<bool> {
+        let kind = record.kind();
+        let mut shifted = HashSet::new();
+        let mut positions: HashMap<&str, (Option<u8>, Option<u8>)> = HashMap::new();
+        let offset = self.time_offset.num_seconds();
+
+        for field in record.fields() {
+            let number = field.number();
+            match (field.value(), fields.get(&number)) {
+                (Value::Timestamp(_), Some(Value::UInt32(value)))
+                    if offset != 0
+                        && *value >= MIN_ABSOLUTE_TIMESTAMP
+                        && *value != u32::MAX
+                        && shifted.insert(number) =>
+                {
+                    let value = u32::try_from(*value as i64 + offset)
+                        .ok()
+                        .filter(|v| *v >= MIN_ABSOLUTE_TIMESTAMP && *v != u32::MAX)
+                        .ok_or_else(|| {
+                            ErrorKind::ValueError(format!(
+                                "{} of {} can't be shifted by {} s",
+                                field.name(),
+                                kind,
+                                offset
+                            ))
+                        })?;
+                    editor.write_u32(number, value);
+                }
+                (_, Some(Value::UInt16(value)))
+                    if field.name() == "timestamp_16" && shifted.insert(number) =>
+                {
+                    editor.write_u16(number, value.wrapping_add(offset as u16));
+                }
+                (_, Some(Value::UInt32z(value)))
+                    if field.name() == "serial_number"
+                        && (kind == MesgNum::FileId || kind == MesgNum::DeviceInfo) =>
+                {
+                    match self.serial_numbers {
+                        SerialNumbers::Keep => {}
+                        SerialNumbers::Remove => editor.invalidate(number),
+                        SerialNumbers::Fuzz(seed) => {
+                            editor.write_u32(number, fuzz_serial_number(*value, seed))
+                        }
+                    }
+                }
+                _ if kind == MesgNum::UserProfile
+                    && self.strip_user_profile
+                    && USER_PROFILE_FIELDS.contains(&field.name()) =>
+                {
+                    editor.invalidate(number)
+                }
+                _ if field.units() == "semicircles" => {
+                    if let Some(prefix) = field.name().strip_suffix("_lat") {
+                        positions.entry(prefix).or_default().0 = Some(number);
+                    } else if let Some(prefix) = field.name().strip_suffix("_long") {
+                        positions.entry(prefix).or_default().1 = Some(number);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut remove = false;
+        for (prefix, numbers) in positions {
+            let (lat, long) = match numbers {
+                (Some(lat), Some(long)) => (lat, long),
+                _ => continue,
+            };
+            let position = match (fields.get(&lat), fields.get(&long)) {
+                (Some(Value::SInt32(lat)), Some(Value::SInt32(long)))
+                    if *lat != i32::MAX && *long != i32::MAX =>
+                {
+                    (
+                        semicircles_to_degrees(*lat as i64),
+                        semicircles_to_degrees(*long as i64),
+                    )
+                }
+                _ => continue,
+            };
+            if !self.privacy_zones.iter().any(|z| z.contains(position)) {
+                continue;
+            }
+            if kind == MesgNum::Record
+                && prefix == "position"
+                && self.privacy_action == PrivacyAction::Remove
+            {
+                remove = true;
+            }
+            editor.invalidate(lat);
+            editor.invalidate(long);
+        }
+
+        Ok(remove)
+    }
+}
+
+/// Writes new field values into the bytes of a data message
+struct MessageEditor<'a> {
+    bytes: &'a mut [u8],
+    byte_order: Endianness,
+    /// Field number to (offset, size, base type)
+    fields: HashMap<u8, (usize, usize, FitBaseType)>,
+}
+
+impl<'a> MessageEditor<'a> {
+    fn new(bytes: &'a mut [u8], definition: &FitDefinitionMessage) -> Self {
+        // start at one to skip the message header
+        let mut offset = 1;
+        let mut fields = HashMap::new();
+        for field in definition.field_definitions() {
+            let size = field.size() as usize;
+            fields.insert(
+                field.field_definition_number(),
+                (offset, size, field.base_type()),
+            );
+            offset += size;
+        }
+        MessageEditor {
+            bytes,
+            byte_order: definition.byte_order(),
+            fields,
+        }
+    }
+
+    /// Shift the time offset of a compressed timestamp header along with the full timestamps,
+    /// the difference to the last full timestamp stays the same
+    fn shift_time_offset(&mut self, offset: i64) {
+        let time_offset = (self.bytes[0] & 0x1F) as i64 + offset;
+        self.bytes[0] = (self.bytes[0] & 0xE0) | time_offset.rem_euclid(32) as u8;
+    }
+
+    fn field_bytes(&mut self, number: u8) -> Option<(&mut [u8], FitBaseType)> {
+        let (offset, size, base_type) = *self.fields.get(&number)?;
+        Some((&mut self.bytes[offset..(offset + size)], base_type))
+    }
+
+    fn write_u16(&mut self, number: u8, value: u16) {
+        let bytes = match self.byte_order {
+            Endianness::Big => value.to_be_bytes(),
+            _ => value.to_le_bytes(),
+        };
+        if let Some((field, _)) = self.field_bytes(number).filter(|(f, _)| f.len() == 2) {
+            field.copy_from_slice(&bytes);
+        }
+    }
+
+    fn write_u32(&mut self, number: u8, value: u32) {
+        let bytes = match self.byte_order {
+            Endianness::Big => value.to_be_bytes(),
+            _ => value.to_le_bytes(),
+        };
+        if let Some((field, _)) = self.field_bytes(number).filter(|(f, _)| f.len() == 4) {
+            field.copy_from_slice(&bytes);
+        }
+    }
+
+    /// Overwrite every element of the field with the invalid value of its base type
+    fn invalidate(&mut self, number: u8) {
+        let byte_order = self.byte_order;
+        let (field, base_type) = match self.field_bytes(number) {
+            Some(field) => field,
+            None => return,
+        };
+        let (size, invalid): (usize, u64) = match base_type {
+            FitBaseType::Sint8 => (1, 0x7F),
+            FitBaseType::Sint16 => (2, 0x7FFF),
+            FitBaseType::Sint32 => (4, 0x7FFF_FFFF),
+            FitBaseType::Sint64 => (8, 0x7FFF_FFFF_FFFF_FFFF),
+            FitBaseType::String
+            | FitBaseType::Uint8z
+            | FitBaseType::Uint16z
+            | FitBaseType::Uint32z
+            | FitBaseType::Uint64z => (1, 0),
+            _ => (1, 0xFF),
+        };
+        let element = match byte_order {
+            Endianness::Big => invalid.to_be_bytes()[(8 - size)..].to_vec(),
+            _ => invalid.to_le_bytes()[..size].to_vec(),
+        };
+        for chunk in field.chunks_mut(size) {
+            let len = chunk.len();
+            chunk.copy_from_slice(&element[..len]);
+        }
+    }
+}
+
+/// Check if the next data message of the file uses a compressed timestamp header
+fn next_is_compressed_timestamp(objects: &[RawObject]) -> bool {
+    for obj in objects {
+        match obj {
+            RawObject::DataMessage(msg) => return msg.is_compressed_timestamp(),
+            RawObject::Crc(_) => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Derive a valid serial number from the serial number and seed using the SplitMix64 finalizer
+fn fuzz_serial_number(serial_number: u32, seed: u64) -> u32 {
+    let mut z = (seed ^ serial_number as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    ((z ^ (z >> 31)) as u32).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_bytes;
+    use crate::geo::position;
+
+    #[test]
+    fn strip_serial_numbers_and_mask_positions() {
+        let data = include_bytes!("../tests/fixtures/Activity.fit");
+        let records = from_bytes(data).unwrap();
+        let track: Vec<&FitDataRecord> = records
+            .iter()
+            .filter(|r| r.kind() == MesgNum::Record)
+            .collect();
+        let (lat, long) = position(track[0], "position_lat", "position_long").unwrap();
+
+        let mut anonymizer = Anonymizer::new();
+        anonymizer.push_privacy_zone(lat, long, 0.5);
+        anonymizer.set_time_offset(Duration::hours(-25));
+        let anonymized = from_bytes(&anonymizer.anonymize(data).unwrap()).unwrap();
+        assert_eq!(anonymized.len(), records.len());
+        assert!(anonymized[0].field("serial_number").is_none());
+
+        for (before, after) in records.iter().zip(&anonymized) {
+            assert_eq!(
+                after.timestamp(),
+                before.timestamp().map(|t| t - Duration::hours(25))
+            );
+        }
+        let masked: Vec<bool> = anonymized
+            .iter()
+            .filter(|r| r.kind() == MesgNum::Record)
+            .map(|r| r.field("position_lat").is_none())
+            .collect();
+        assert!(masked[0]);
+        assert!(!masked[masked.len() - 1]);
+        let session = anonymized.iter().find(|r| r.kind() == MesgNum::Session);
+        assert!(session.unwrap().field("start_position_lat").is_none());
+
+        let data = include_bytes!("../tests/fixtures/Settings.fit");
+        let anonymized = from_bytes(&anonymizer.anonymize(data).unwrap()).unwrap();
+        let mut names: Vec<&str> = anonymized[1].fields().iter().map(|f| f.name()).collect();
+        names.sort_unstable();
+        assert_eq!(names, ["gender", "language"]);
+    }
+
+    #[test]
+    fn fuzz_serial_numbers_and_remove_records() {
+        let data = include_bytes!("../tests/fixtures/Activity.fit");
+        let records = from_bytes(data).unwrap();
+        let track: Vec<&FitDataRecord> = records
+            .iter()
+            .filter(|r| r.kind() == MesgNum::Record)
+            .collect();
+        let (lat, long) = position(track[0], "position_lat", "position_long").unwrap();
+
+        let mut anonymizer = Anonymizer::new();
+        anonymizer.set_serial_numbers(SerialNumbers::Fuzz(42));
+        anonymizer.push_privacy_zone(lat, long, 0.5);
+        anonymizer.set_privacy_action(PrivacyAction::Remove);
+        let anonymized = from_bytes(&anonymizer.anonymize(data).unwrap()).unwrap();
+
+        let serial = |records: &[FitDataRecord]| {
+            records[0].field("serial_number").map(|f| f.value().clone())
+        };
+        let fuzzed = serial(&anonymized).unwrap();
+        assert_ne!(Some(&fuzzed), serial(&records).as_ref());
+        assert_eq!(
+            serial(&from_bytes(&anonymizer.anonymize(data).unwrap()).unwrap()),
+            Some(fuzzed)
+        );
+
+        let removed = records.len() - anonymized.len();
+        assert!(removed > 0);
+        assert_eq!(
+            anonymized
+                .iter()
+                .filter(|r| r.kind() == MesgNum::Record)
+                .count(),
+            track.len() - removed
+        );
+    }
+}
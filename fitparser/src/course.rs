@@ -0,0 +1,495 @@
+//! Build `File::Course` FIT files from a GPS track so a route can be followed on a device.
+//!
+//! A course is a list of track points with optional course points (turn cues, summits, water
+//! stops, ...). The encoded file contains the `FileId`, `Course` and `Lap` messages, a timer
+//! start `Event`, one `Record` per track point with the cumulative distance, the `CoursePoint`
+//! messages and a final timer stop `Event`. Devices use the record timestamps to drive the
+//! virtual partner, they are synthesized from the course start time and a constant speed.
+//! ```
+//! use fitparser::course::Course;
+//! use fitparser::profile::field_types::CoursePoint;
+//!
+//! let mut course = Course::new("Lunch loop");
+//! course.push_point(47.3769, 8.5417, Some(408.0));
+//! course.push_point(47.3780, 8.5430, Some(411.0));
+//! course.push_point(47.3791, 8.5402, None);
+//! course.push_course_point(47.3780, 8.5430, CoursePoint::Left, "Turn left");
+//! let bytes = course.to_bytes()?;
+//!
+//! let records = fitparser::from_bytes(&bytes)?;
+//! assert_eq!(records[0].field("type").unwrap().to_string(), "course");
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::de::FitDataMessage;
+use crate::edit::timer_event;
+use crate::error::{ErrorKind, Result};
+use crate::geo::{degrees_to_semicircles, distance};
+use crate::profile::field_types::{
+    CourseCapabilities, CoursePoint, Event, EventType, File, Manufacturer, Sport,
+};
+use crate::profile::MesgNum;
+use crate::ser::Serializer;
+use crate::Value;
+use chrono::{DateTime, Duration, Local};
+use std::collections::HashMap;
+
+/// A point of the course track
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrackPoint {
+    latitude: f64,
+    longitude: f64,
+    elevation: Option<f64>,
+}
+
+impl TrackPoint {
+    /// Latitude in degrees
+    pub fn latitude(&self) -> f64 {
+        self.latitude
+    }
+
+    /// Longitude in degrees
+    pub fn longitude(&self) -> f64 {
+        self.longitude
+    }
+
+    /// Elevation in meters
+    pub fn elevation(&self) -> Option<f64> {
+        self.elevation
+    }
+}
+
+/// A point of interest along the course, e.g. a turn cue
+#[derive(Clone, Debug, PartialEq)]
+pub struct CourseCue {
+    latitude: f64,
+    longitude: f64,
+    kind: CoursePoint,
+    name: String,
+}
+
+impl CourseCue {
+    /// Latitude in degrees
+    pub fn latitude(&self) -> f64 {
+        self.latitude
+    }
+
+    /// Longitude in degrees
+    pub fn longitude(&self) -> f64 {
+        self.longitude
+    }
+
+    /// Type of the course point, e.g. left or summit
+    pub fn kind(&self) -> CoursePoint {
+        self.kind
+    }
+
+    /// Name displayed for the course point
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// A route made up of track points and course points
+#[derive(Clone, Debug, PartialEq)]
+pub struct Course {
+    name: String,
+    sport: Option<Sport>,
+    speed: f64,
+    start_time: DateTime<Local>,
+    manufacturer: Manufacturer,
+    product: u16,
+    points: Vec<TrackPoint>,
+    cues: Vec<CourseCue>,
+}
+
+impl Course {
+    /// Create an empty course starting now with a virtual partner speed of 5 m/s
+    pub fn new(name: &str) -> Self {
+        Course {
+            name: name.to_string(),
+            sport: None,
+            speed: 5.0,
+            start_time: Local::now(),
+            manufacturer: Manufacturer::Development,
+            product: 0,
+            points: Vec::new(),
+            cues: Vec::new(),
+        }
+    }
+
+    /// Name of the course
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sport the course is for
+    pub fn sport(&self) -> Option<Sport> {
+        self.sport
+    }
+
+    /// Set the sport the course is for
+    pub fn set_sport(&mut self, sport: Sport) {
+        self.sport = Some(sport);
+    }
+
+    /// Constant speed in m/s used to synthesize the record timestamps
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Set the speed in m/s used to synthesize the record timestamps
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+    }
+
+    /// Timestamp of the first track point
+    pub fn start_time(&self) -> DateTime<Local> {
+        self.start_time
+    }
+
+    /// Set the timestamp of the first track point
+    pub fn set_start_time(&mut self, start_time: DateTime<Local>) {
+        self.start_time = start_time;
+    }
+
+    /// Manufacturer written to the `FileId` message
+    pub fn manufacturer(&self) -> Manufacturer {
+        self.manufacturer
+    }
+
+    /// Set the manufacturer written to the `FileId` message
+    pub fn set_manufacturer(&mut self, manufacturer: Manufacturer) {
+        self.manufacturer = manufacturer;
+    }
+
+    /// Product ID written to the `FileId` message
+    pub fn product(&self) -> u16 {
+        self.product
+    }
+
+    /// Set the product ID written to the `FileId` message
+    pub fn set_product(&mut self, product: u16) {
+        self.product = product;
+    }
+
+    /// Points of the track in order
+    pub fn points(&self) -> &[TrackPoint] {
+        &self.points
+    }
+
+    /// Append a point to the track, latitude and longitude are given in degrees and the
+    /// elevation in meters
+    pub fn push_point(&mut self, latitude: f64, longitude: f64, elevation: Option<f64>) {
+        self.points.push(TrackPoint {
+            latitude,
+            longitude,
+            elevation,
+        });
+    }
+
+    /// Course points in order along the track
+    pub fn course_points(&self) -> &[CourseCue] {
+        &self.cues
+    }
+
+    /// Append a course point. Course points must be added in the order they are reached, each
+    /// one is placed at the closest track point following the previous course point so routes
+    /// that pass the same spot twice are handled.
+    pub fn push_course_point(
+        &mut self,
+        latitude: f64,
+        longitude: f64,
+        kind: CoursePoint,
+        name: &str,
+    ) {
+        self.cues.push(CourseCue {
+            latitude,
+            longitude,
+            kind,
+            name: name.to_string(),
+        });
+    }
+
+    /// Cumulative distance in meters at each track point
+    pub fn distances(&self) -> Vec<f64> {
+        let mut total = 0.0;
+        let mut prev = None;
+        self.points
+            .iter()
+            .map(|p| {
+                let pos = (p.latitude, p.longitude);
+                if let Some(prev) = prev {
+                    total += distance(prev, pos);
+                }
+                prev = Some(pos);
+                total
+            })
+            .collect()
+    }
+
+    /// Check that the course can be stored: the track needs at least two points with valid
+    /// coordinates and the speed must be positive.
+    pub fn validate(&self) -> Result<()> {
+        if self.points.len() < 2 {
+            return Err(invalid_course(format!(
+                "track has {} points, at least 2 are required",
+                self.points.len()
+            )));
+        }
+        if !(self.speed.is_finite() && self.speed > 0.0) {
+            return Err(invalid_course(format!(
+                "speed must be positive, got {}",
+                self.speed
+            )));
+        }
+        let coords = self
+            .points
+            .iter()
+            .map(|p| (p.latitude, p.longitude))
+            .chain(self.cues.iter().map(|c| (c.latitude, c.longitude)));
+        for (lat, long) in coords {
+            if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&long) {
+                return Err(invalid_course(format!(
+                    "position ({}, {}) is out of range",
+                    lat, long
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate the course and encode it into a FIT course file
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.validate()?;
+        let distances = self.distances();
+        let timestamps: Vec<DateTime<Local>> = distances
+            .iter()
+            .map(|d| self.start_time + Duration::seconds((d / self.speed).round() as i64))
+            .collect();
+        let (first, last) = (self.points[0], self.points[self.points.len() - 1]);
+        let (start, end) = (timestamps[0], timestamps[timestamps.len() - 1]);
+        let total_distance = distances[distances.len() - 1];
+        let elapsed = (end - start).num_seconds() as f64;
+
+        let mut messages = Vec::new();
+        let mut file_id = HashMap::new();
+        file_id.insert(0, Value::Enum(File::Course.as_i64() as u8));
+        file_id.insert(1, Value::UInt16(self.manufacturer.as_u16()));
+        file_id.insert(2, Value::UInt16(self.product));
+        file_id.insert(4, Value::Timestamp(start));
+        messages.push(message(MesgNum::FileId, file_id));
+
+        let capabilities = CourseCapabilities::Time.as_u32()
+            | CourseCapabilities::Distance.as_u32()
+            | CourseCapabilities::Position.as_u32();
+        let mut course = HashMap::new();
+        if let Some(sport) = self.sport {
+            course.insert(4, Value::Enum(sport.as_u8()));
+        }
+        course.insert(5, Value::String(self.name.clone()));
+        course.insert(6, Value::UInt32z(capabilities));
+        messages.push(message(MesgNum::Course, course));
+
+        let (ascent, descent) = self.ascent_descent();
+        let mut lap = HashMap::new();
+        lap.insert(253, Value::Timestamp(end));
+        lap.insert(0, Value::Enum(Event::Lap.as_u8()));
+        lap.insert(1, Value::Enum(EventType::Stop.as_u8()));
+        lap.insert(2, Value::Timestamp(start));
+        lap.insert(3, Value::SInt32(degrees_to_semicircles(first.latitude)));
+        lap.insert(4, Value::SInt32(degrees_to_semicircles(first.longitude)));
+        lap.insert(5, Value::SInt32(degrees_to_semicircles(last.latitude)));
+        lap.insert(6, Value::SInt32(degrees_to_semicircles(last.longitude)));
+        lap.insert(7, Value::UInt32((elapsed * 1000.0) as u32));
+        lap.insert(8, Value::UInt32((elapsed * 1000.0) as u32));
+        lap.insert(9, Value::UInt32((total_distance * 100.0).round() as u32));
+        if let Some(ascent) = ascent {
+            lap.insert(21, Value::UInt16(ascent.round() as u16));
+            lap.insert(22, Value::UInt16(descent.round() as u16));
+        }
+        messages.push(message(MesgNum::Lap, lap));
+
+        messages.push(timer_event(start, EventType::Start));
+        for ((point, dist), timestamp) in self.points.iter().zip(&distances).zip(&timestamps) {
+            let mut record = HashMap::new();
+            record.insert(253, Value::Timestamp(*timestamp));
+            record.insert(0, Value::SInt32(degrees_to_semicircles(point.latitude)));
+            record.insert(1, Value::SInt32(degrees_to_semicircles(point.longitude)));
+            if let Some(elevation) = point.elevation {
+                // altitude is stored with a scale of 5 and an offset of 500 m
+                let raw = ((elevation + 500.0) * 5.0).round();
+                if (0.0..f64::from(u16::MAX)).contains(&raw) {
+                    record.insert(2, Value::UInt16(raw as u16));
+                } else {
+                    record.insert(78, Value::UInt32(raw.max(0.0) as u32));
+                }
+            }
+            record.insert(5, Value::UInt32((dist * 100.0).round() as u32));
+            messages.push(message(MesgNum::Record, record));
+        }
+
+        for (index, (cue, point)) in self.cues.iter().zip(self.cue_points()).enumerate() {
+            let mut course_point = HashMap::new();
+            course_point.insert(254, Value::UInt16(index as u16));
+            course_point.insert(1, Value::Timestamp(timestamps[point]));
+            course_point.insert(2, Value::SInt32(degrees_to_semicircles(cue.latitude)));
+            course_point.insert(3, Value::SInt32(degrees_to_semicircles(cue.longitude)));
+            course_point.insert(4, Value::UInt32((distances[point] * 100.0).round() as u32));
+            course_point.insert(5, Value::Enum(cue.kind.as_u8()));
+            course_point.insert(6, Value::String(cue.name.clone()));
+            messages.push(message(MesgNum::CoursePoint, course_point));
+        }
+        messages.push(timer_event(end, EventType::StopDisableAll));
+
+        let mut serializer = Serializer::new();
+        for msg in &messages {
+            serializer.serialize(msg)?;
+        }
+        Ok(serializer.finish())
+    }
+
+    /// Index of the track point each course point is placed at
+    fn cue_points(&self) -> Vec<usize> {
+        let mut start = 0;
+        self.cues
+            .iter()
+            .map(|cue| {
+                let pos = (cue.latitude, cue.longitude);
+                let closest = (start..self.points.len())
+                    .min_by(|a, b| {
+                        let da =
+                            distance(pos, (self.points[*a].latitude, self.points[*a].longitude));
+                        let db =
+                            distance(pos, (self.points[*b].latitude, self.points[*b].longitude));
+                        da.total_cmp(&db)
+                    })
+                    .unwrap_or(start);
+                start = closest;
+                closest
+            })
+            .collect()
+    }
+
+    /// Total ascent and descent in meters, None if the track has no elevation data
+    fn ascent_descent(&self) -> (Option<f64>, f64) {
+        let elevations: Vec<f64> = self.points.iter().filter_map(|p| p.elevation).collect();
+        if elevations.is_empty() {
+            return (None, 0.0);
+        }
+        let (mut ascent, mut descent) = (0.0, 0.0);
+        for pair in elevations.windows(2) {
+            let delta = pair[1] - pair[0];
+            if delta > 0.0 {
+                ascent += delta;
+            } else {
+                descent -= delta;
+            }
+        }
+        (Some(ascent), descent)
+    }
+}
+
+fn message(kind: MesgNum, fields: HashMap<u8, Value>) -> FitDataMessage {
+    FitDataMessage::new(kind.as_u16(), fields)
+}
+
+fn invalid_course(message: String) -> crate::Error {
+    ErrorKind::InvalidCourse(message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_bytes;
+    use chrono::TimeZone;
+
+    fn kinds(records: &[crate::FitDataRecord]) -> Vec<MesgNum> {
+        records.iter().map(|r| r.kind()).collect()
+    }
+
+    #[test]
+    fn course_file_messages() {
+        let mut course = Course::new("Test course");
+        course.set_sport(Sport::Cycling);
+        course.set_speed(10.0);
+        course.set_start_time(Local.timestamp_opt(1_600_000_000, 0).unwrap());
+        // roughly 111 m between points along the meridian
+        course.push_point(45.000, 7.0, Some(200.0));
+        course.push_point(45.001, 7.0, Some(210.0));
+        course.push_point(45.002, 7.0, Some(205.0));
+        course.push_point(45.001, 7.0, Some(210.0));
+        course.push_course_point(45.002, 7.0, CoursePoint::UTurn, "Turn around");
+        course.push_course_point(45.001, 7.0, CoursePoint::Generic, "Back");
+
+        let records = from_bytes(&course.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            kinds(&records),
+            vec![
+                MesgNum::FileId,
+                MesgNum::Course,
+                MesgNum::Lap,
+                MesgNum::Event,
+                MesgNum::Record,
+                MesgNum::Record,
+                MesgNum::Record,
+                MesgNum::Record,
+                MesgNum::CoursePoint,
+                MesgNum::CoursePoint,
+                MesgNum::Event,
+            ]
+        );
+        assert_eq!(records[1].field("name").unwrap().to_string(), "Test course");
+        assert_eq!(records[3].field("event_type").unwrap().to_string(), "start");
+        assert_eq!(
+            records[10].field("event_type").unwrap().to_string(),
+            "stop_disable_all"
+        );
+
+        let distance = |r: &crate::FitDataRecord| -> f64 {
+            r.field("distance").unwrap().value().try_into().unwrap()
+        };
+        let total = course.distances()[3];
+        assert!((total - 333.6).abs() < 0.5, "{}", total);
+        assert!((distance(&records[7]) - total).abs() < 0.01);
+        let lap_distance: f64 = records[2]
+            .field("total_distance")
+            .unwrap()
+            .value()
+            .try_into()
+            .unwrap();
+        assert!((lap_distance - total).abs() < 0.01);
+        assert_eq!(
+            records[2].field("total_ascent").unwrap().value(),
+            &Value::UInt16(15)
+        );
+        assert_eq!(
+            records[2].field("total_descent").unwrap().value(),
+            &Value::UInt16(5)
+        );
+
+        // the second cue is placed on the way back, not at the first pass
+        assert!((distance(&records[8]) - course.distances()[2]).abs() < 0.01);
+        assert!((distance(&records[9]) - total).abs() < 0.01);
+        assert_eq!(
+            records[9].field("timestamp").unwrap().value(),
+            records[7].field("timestamp").unwrap().value()
+        );
+        assert_eq!(records[8].field("type").unwrap().to_string(), "u_turn");
+        let altitude: f64 = records[4]
+            .field("enhanced_altitude")
+            .unwrap()
+            .value()
+            .try_into()
+            .unwrap();
+        assert!((altitude - 200.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn invalid_courses() {
+        let mut course = Course::new("Too short");
+        course.push_point(45.0, 7.0, None);
+        assert!(course.to_bytes().is_err());
+        course.push_point(95.0, 7.0, None);
+        assert!(course.validate().is_err());
+    }
+}
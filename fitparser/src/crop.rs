@@ -0,0 +1,354 @@
+//! Crop an activity to a time range or split it in two at a timestamp.
+//!
+//! `Record` and `Event` messages outside the range are dropped, `Lap` and `Session` messages are
+//! kept if they overlap the range and their start and end times are clipped to it. Timer start
+//! and stop events are added at the range boundaries if the timer was running there and the
+//! record distances are rebased to start from zero. The lap, session and activity totals are then
+//! recomputed from the remaining records using [`analysis::summary`](crate::analysis::summary),
+//! fields the summaries don't cover are kept as they are. All other messages are copied
+//! unchanged.
+//! ```
+//! use chrono::Duration;
+//! use fitparser::crop;
+//!
+//! let data = std::fs::read("tests/fixtures/garmin-fenix-5-bike.fit")?;
+//! let records = fitparser::from_bytes(&data)?;
+//! let start = records.iter().find_map(|r| r.timestamp()).unwrap();
+//! let (first, second) = crop::split(&data, start + Duration::seconds(30))?;
+//! let cropped = crop::crop(&data, start, start + Duration::seconds(10))?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::analysis::summary::{lap_summaries, session_summaries};
+use crate::analysis::{field_timestamp, TimerPeriods};
+use crate::de::FitDataMessage;
+use crate::edit::{
+    decode, index_value, set_activity, set_field, set_number, set_positions, set_summary,
+    timer_event, with_timestamp, RECORD_DISTANCE_FIELD,
+};
+use crate::error::{ErrorKind, Result};
+use crate::profile::field_types::EventType;
+use crate::profile::MesgNum;
+use crate::ser::{self, TIMESTAMP_FIELD};
+use crate::{FitDataRecord, Value};
+use chrono::{DateTime, Local};
+
+/// Crop the activity to the records with a timestamp from `start` up to but excluding `end`
+pub fn crop(data: &[u8], start: DateTime<Local>, end: DateTime<Local>) -> Result<Vec<u8>> {
+    if start >= end {
+        return Err(invalid_crop(format!(
+            "start {} is not before end {}",
+            start, end
+        )));
+    }
+    let (messages, records) = decode(data)?;
+    crop_messages(&messages, &records, Some(start), Some(end))
+}
+
+/// Split the activity into the records before `at` and the records from `at` onwards
+pub fn split(data: &[u8], at: DateTime<Local>) -> Result<(Vec<u8>, Vec<u8>)> {
+    let (messages, records) = decode(data)?;
+    Ok((
+        crop_messages(&messages, &records, None, Some(at))?,
+        crop_messages(&messages, &records, Some(at), None)?,
+    ))
+}
+
+fn crop_messages(
+    messages: &[FitDataMessage],
+    records: &[FitDataRecord],
+    start: Option<DateTime<Local>>,
+    end: Option<DateTime<Local>>,
+) -> Result<Vec<u8>> {
+    let in_range = |timestamp: DateTime<Local>| {
+        start.is_none_or(|s| timestamp >= s) && end.is_none_or(|e| timestamp < e)
+    };
+    let kept: Vec<usize> = (0..records.len())
+        .filter(|idx| {
+            let kind = records[*idx].kind();
+            (kind == MesgNum::Record || kind == MesgNum::Event)
+                && records[*idx].timestamp().is_some_and(in_range)
+        })
+        .collect();
+    if !kept
+        .iter()
+        .any(|idx| records[*idx].kind() == MesgNum::Record)
+    {
+        return Err(invalid_crop(
+            "the time range doesn't contain any records".to_string(),
+        ));
+    }
+    let (first_kept, last_kept) = (kept[0], kept[kept.len() - 1]);
+
+    // the timer has to be restarted if it was running when the range starts and stopped if it
+    // is still running at the end
+    let timer = TimerPeriods::from_records(records);
+    let running_at = |t: DateTime<Local>| timer.periods().iter().any(|(a, b)| *a < t && t <= *b);
+    let timer_start = start.filter(|s| running_at(*s));
+    let timer_stop = end.filter(|e| running_at(*e));
+
+    // record distances are cumulative and start over at the beginning of the range
+    let baseline = start.and_then(|s| {
+        (0..records.len())
+            .filter(|idx| records[*idx].kind() == MesgNum::Record)
+            .take_while(|idx| records[*idx].timestamp().is_none_or(|t| t < s))
+            .filter_map(
+                |idx| match messages[idx].fields().get(&RECORD_DISTANCE_FIELD) {
+                    Some(Value::UInt32(dist)) => Some(*dist),
+                    _ => None,
+                },
+            )
+            .last()
+    });
+
+    let mut output = Vec::with_capacity(messages.len());
+    // (output position, source position, start time, end time)
+    let mut laps = Vec::new();
+    let mut sessions = Vec::new();
+    let mut activity = None;
+    for (idx, (message, record)) in messages.iter().zip(records).enumerate() {
+        let timestamp = record.timestamp();
+        let mut message = with_timestamp(message, timestamp);
+        match record.kind() {
+            MesgNum::Record | MesgNum::Event if timestamp.is_some() => {
+                if !timestamp.is_some_and(in_range) {
+                    continue;
+                }
+                if idx == first_kept {
+                    if let Some(s) = timer_start {
+                        output.push(timer_event(s, EventType::Start));
+                    }
+                }
+                if let (Some(baseline), Some(Value::UInt32(dist))) = (
+                    baseline,
+                    message.fields_mut().get_mut(&RECORD_DISTANCE_FIELD),
+                ) {
+                    *dist = dist.saturating_sub(baseline);
+                }
+                output.push(message);
+                if idx == last_kept {
+                    if let Some(e) = timer_stop {
+                        output.push(timer_event(e, EventType::StopAll));
+                    }
+                }
+                continue;
+            }
+            MesgNum::Lap | MesgNum::Session => {
+                let lap_end = match timestamp {
+                    Some(ts) => ts,
+                    None => continue,
+                };
+                let lap_start = field_timestamp(record, "start_time").unwrap_or(lap_end);
+                if start.is_some_and(|s| lap_end < s) || end.is_some_and(|e| lap_start >= e) {
+                    continue;
+                }
+                let clipped = (
+                    start.map_or(lap_start, |s| lap_start.max(s)),
+                    end.map_or(lap_end, |e| lap_end.min(e)),
+                );
+                set_field(
+                    &mut message,
+                    record,
+                    "start_time",
+                    Value::Timestamp(clipped.0),
+                );
+                message
+                    .fields_mut()
+                    .insert(TIMESTAMP_FIELD, Value::Timestamp(clipped.1));
+                let group = (output.len(), idx, clipped.0, clipped.1);
+                if record.kind() == MesgNum::Lap {
+                    laps.push(group);
+                } else {
+                    sessions.push(group);
+                }
+            }
+            MesgNum::Activity => activity = Some((output.len(), idx)),
+            _ => {}
+        }
+        output.push(message);
+    }
+
+    // summaries are computed from the cropped file so they only see the remaining records
+    let cropped = crate::from_bytes(&ser::to_bytes(&output)?)?;
+    let lap_totals = lap_summaries(&cropped);
+    let session_totals = session_summaries(&cropped);
+    for (index, (pos, source, lap_start, lap_end)) in laps.iter().enumerate() {
+        let message = &mut output[*pos];
+        set_summary(message, &records[*source], &lap_totals[index]);
+        set_positions(message, &records[*source], &cropped, *lap_start, *lap_end);
+        set_field(
+            message,
+            &records[*source],
+            "message_index",
+            index_value(index),
+        );
+    }
+    let mut timer_time = 0.0;
+    for (index, (pos, source, session_start, session_end)) in sessions.iter().enumerate() {
+        let message = &mut output[*pos];
+        set_summary(message, &records[*source], &session_totals[index]);
+        set_positions(
+            message,
+            &records[*source],
+            &cropped,
+            *session_start,
+            *session_end,
+        );
+        set_field(
+            message,
+            &records[*source],
+            "message_index",
+            index_value(index),
+        );
+        let session_laps: Vec<usize> = laps
+            .iter()
+            .enumerate()
+            .filter(|(_, lap)| lap.2 >= *session_start && lap.2 <= *session_end)
+            .map(|(idx, _)| idx)
+            .collect();
+        if let Some(first) = session_laps.first() {
+            set_number(message, &records[*source], "first_lap_index", *first as f64);
+            set_number(
+                message,
+                &records[*source],
+                "num_laps",
+                session_laps.len() as f64,
+            );
+        }
+        timer_time += session_totals[index].total_timer_time();
+    }
+    if let Some((pos, source)) = activity {
+        let end_time = sessions
+            .iter()
+            .map(|s| s.3)
+            .chain(cropped.iter().filter_map(|r| r.timestamp()))
+            .max();
+        let totals = if sessions.is_empty() {
+            None
+        } else {
+            Some((timer_time, sessions.len()))
+        };
+        set_activity(&mut output[pos], &records[source], end_time, totals);
+    }
+
+    ser::to_bytes(&output)
+}
+
+fn invalid_crop(message: String) -> crate::Error {
+    ErrorKind::InvalidCrop(message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::field_f64;
+    use chrono::Duration;
+
+    fn find(records: &[FitDataRecord], kind: MesgNum) -> &FitDataRecord {
+        records.iter().find(|r| r.kind() == kind).unwrap()
+    }
+
+    #[test]
+    fn crop_recomputes_summaries() {
+        let data = include_bytes!("../tests/fixtures/garmin-fenix-5-bike.fit");
+        let records = crate::from_bytes(data).unwrap();
+        let track: Vec<&FitDataRecord> = records
+            .iter()
+            .filter(|r| r.kind() == MesgNum::Record)
+            .collect();
+        let start = track[0].timestamp().unwrap() + Duration::seconds(10);
+        let end = start + Duration::seconds(20);
+
+        let cropped = crate::from_bytes(&crop(data, start, end).unwrap()).unwrap();
+        let timestamps: Vec<DateTime<Local>> = cropped
+            .iter()
+            .filter(|r| r.kind() == MesgNum::Record)
+            .filter_map(|r| r.timestamp())
+            .collect();
+        assert!(timestamps.iter().all(|t| *t >= start && *t < end));
+        assert_eq!(
+            timestamps.len(),
+            track
+                .iter()
+                .filter(|r| r.timestamp().is_some_and(|t| t >= start && t < end))
+                .count()
+        );
+
+        let timer = TimerPeriods::from_records(&cropped);
+        assert_eq!(timer.periods(), &[(start, end)]);
+        let session = find(&cropped, MesgNum::Session);
+        assert_eq!(field_timestamp(session, "start_time"), Some(start));
+        assert_eq!(session.timestamp(), Some(end));
+        // summaries end at the last record
+        let timer_time = (*timestamps.last().unwrap() - start).num_seconds() as f64;
+        assert_eq!(field_f64(session, &["total_timer_time"]), Some(timer_time));
+        let lap = find(&cropped, MesgNum::Lap);
+        assert_eq!(field_f64(lap, &["total_timer_time"]), Some(timer_time));
+        let distance = field_f64(lap, &["total_distance"]).unwrap();
+        let first = field_f64(
+            cropped
+                .iter()
+                .find(|r| r.kind() == MesgNum::Record)
+                .unwrap(),
+            &["distance"],
+        );
+        assert!(first.unwrap() < distance);
+        let activity = find(&cropped, MesgNum::Activity);
+        assert_eq!(field_f64(activity, &["total_timer_time"]), Some(timer_time));
+
+        assert!(crate::validate::validate(&cropped)[0].is_valid());
+
+        assert!(crop(data, end, start).is_err());
+        assert!(crop(data, end + Duration::days(1), end + Duration::days(2)).is_err());
+    }
+
+    #[test]
+    fn crop_keeps_totals_without_records() {
+        // the file has no power data so the calories can't be recomputed from the records
+        let data = include_bytes!("../tests/fixtures/garmin-fenix-5-bike.fit");
+        let records = crate::from_bytes(data).unwrap();
+        let session = find(&records, MesgNum::Session);
+        assert!(records.iter().all(|r| r.field("power").is_none()));
+        let calories = field_f64(session, &["total_calories"]).unwrap();
+        let timer_time = field_f64(session, &["total_timer_time"]).unwrap();
+        let start = field_timestamp(session, "start_time").unwrap();
+        let end = start + Duration::seconds(timer_time as i64 / 2);
+
+        let cropped = crate::from_bytes(&crop(data, start, end).unwrap()).unwrap();
+        let session = find(&cropped, MesgNum::Session);
+        let fraction = field_f64(session, &["total_timer_time"]).unwrap() / timer_time;
+        let cropped_calories = field_f64(session, &["total_calories"]).unwrap();
+        assert!((cropped_calories - calories * fraction).abs() <= 0.5);
+        assert!(session.field("avg_heart_rate").is_some());
+    }
+
+    #[test]
+    fn split_into_two_files() {
+        let data = include_bytes!("../tests/fixtures/garmin-fenix-5-bike.fit");
+        let records = crate::from_bytes(data).unwrap();
+        let count = |records: &[FitDataRecord]| {
+            records
+                .iter()
+                .filter(|r| r.kind() == MesgNum::Record)
+                .count()
+        };
+        let at = find(&records, MesgNum::Record).timestamp().unwrap() + Duration::seconds(30);
+
+        let (first, second) = split(data, at).unwrap();
+        let first = crate::from_bytes(&first).unwrap();
+        let second = crate::from_bytes(&second).unwrap();
+        assert_eq!(count(&first) + count(&second), count(&records));
+        assert_eq!(find(&first, MesgNum::Session).timestamp(), Some(at));
+        assert_eq!(
+            field_timestamp(find(&second, MesgNum::Session), "start_time"),
+            Some(at)
+        );
+        let total = |records: &[FitDataRecord]| {
+            field_f64(find(records, MesgNum::Session), &["total_timer_time"]).unwrap()
+        };
+        // the time between the last record of the first part and the split is lost
+        let difference = total(&records) - total(&first) - total(&second);
+        assert!((0.0..10.0).contains(&difference), "{}", difference);
+        assert_eq!(first[0].kind(), MesgNum::FileId);
+        assert_eq!(second[0].kind(), MesgNum::FileId);
+    }
+}
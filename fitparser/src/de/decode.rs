@@ -1,10 +1,10 @@
 //! Helper functions and structures needed to decode a FIT file using the defined profile.
-use super::parser::FitDataMessage;
-use super::DecodeOption;
+use super::parser::{FieldMap, FitDataMessage};
+use super::{DecodeFilter, DecodeOption};
 use crate::error::Result;
 use crate::profile::{data_field_with_info, FieldDataType, MesgNum, TimestampField};
 use crate::{DeveloperFieldDescription, ErrorKind, FitDataField, FitDataRecord, Value};
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{HashMap, HashSet};
 use std::convert::{From, TryInto};
 
 /// Decodes a raw FitDataMessage using the defined profile. Additional logic is used to handle
@@ -34,20 +34,22 @@ impl Decoder {
         self.developer_field_descriptions = HashMap::new();
     }
 
-    /// Decode a raw FIT data message by applying the defined profile
+    /// Decode a raw FIT data message by applying the defined profile, only the fields selected by
+    /// the filter are kept
     pub fn decode_message(
         &mut self,
         mut message: FitDataMessage,
         options: &HashSet<DecodeOption>,
+        filter: &DecodeFilter,
     ) -> Result<FitDataRecord> {
         let mesg_num = MesgNum::from(message.global_message_number());
         let mut record = FitDataRecord::new(mesg_num);
-
-        // check if we have a real timestamp field to set the reference
-        // field id 253 always appears to be a timestamp with the type
-        // FieldDataType::DateTime
-        if let Some(value) = message.fields().get(&253) {
-            self.base_timestamp = TimestampField::Utc(value.clone().try_into().unwrap_or(0));
+        self.update_base_timestamp(&message);
+        // field descriptions are always decoded in full since later messages depend on them
+        if let Some(names) = filter.fields() {
+            if mesg_num != MesgNum::FieldDescription {
+                retain_selected_fields(mesg_num, &mut message.fields, names);
+            }
         }
 
         // process raw data
@@ -65,23 +67,69 @@ impl Decoder {
                 description,
             );
         }
+        // drop the source fields and component fields that weren't selected
+        if filter.fields().is_some() {
+            fields.retain(|f| filter.is_field_selected(f.name()));
+        }
         record.extend(fields);
-        self.decode_developer_fields(&mut record, &message.developer_fields, options)?;
+        self.decode_developer_fields(&mut record, &message.developer_fields, options, filter)?;
 
         // Add a timestamp field if we have a time offset
         if let Some(time_offset) = message.time_offset() {
-            record.push(FitDataField::new(
-                String::from("timestamp"),
-                253,
-                None,
-                self.update_timestamp(time_offset),
-                String::new(),
-            ));
+            let value = self.update_timestamp(time_offset);
+            if filter.is_field_selected("timestamp") {
+                record.push(FitDataField::new("timestamp", 253, None, value, ""));
+            }
         }
 
         Ok(record)
     }
 
+    /// Skip a raw FIT data message that isn't needed, only doing the work required to decode the
+    /// messages following it correctly
+    pub fn skip_message(
+        &mut self,
+        message: FitDataMessage,
+        options: &HashSet<DecodeOption>,
+    ) -> Result<()> {
+        let mesg_num = MesgNum::from(message.global_message_number());
+        // developer field descriptions and accumulated values are needed by later messages
+        if mesg_num == MesgNum::FieldDescription
+            || message.fields().keys().any(|nr| mesg_num.accumulates(*nr))
+        {
+            self.decode_message(message, options, &DecodeFilter::default())?;
+            return Ok(());
+        }
+        self.update_base_timestamp(&message);
+        if let Some(time_offset) = message.time_offset() {
+            self.update_timestamp(time_offset);
+        }
+        Ok(())
+    }
+
+    /// Check if we have a real timestamp field to set the reference, field id 253 always appears
+    /// to be a timestamp with the type FieldDataType::DateTime
+    fn update_base_timestamp(&mut self, message: &FitDataMessage) {
+        if let Some(value) = message.fields().get(&253) {
+            self.base_timestamp = TimestampField::Utc(value.clone().try_into().unwrap_or(0));
+        }
+    }
+
+    /// Set the reference timestamp used to expand compressed timestamps
+    pub fn set_timestamp_base(&mut self, timestamp: u32) {
+        self.base_timestamp = TimestampField::Utc(timestamp as i64);
+    }
+
+    /// Values accumulated across messages so far
+    pub fn accumulated_values(&self) -> &HashMap<u32, Value> {
+        &self.accumulate_fields
+    }
+
+    /// Replace the values accumulated across messages
+    pub fn set_accumulated_values(&mut self, values: HashMap<u32, Value>) {
+        self.accumulate_fields = values;
+    }
+
     /// Update the timestamp with a new offset and return the value
     fn update_timestamp(&mut self, offset: u8) -> Value {
         let offset: i64 = offset as i64;
@@ -114,25 +162,25 @@ impl Decoder {
         record: &mut FitDataRecord,
         developer_data_map: &HashMap<(u8, u8), Value>,
         options: &HashSet<DecodeOption>,
+        filter: &DecodeFilter,
     ) -> Result<()> {
-        let mut entries: VecDeque<((u8, u8), Value)> = developer_data_map
-            .iter()
-            .map(|(k, v)| (*k, v.clone()))
-            .collect();
-        while let Some(((dev_data_idx, field_nr), value)) = entries.pop_front() {
+        for (&(dev_data_idx, field_nr), value) in developer_data_map {
             let dev_definition = self
                 .developer_field_descriptions
                 .get(&(dev_data_idx, field_nr))
                 .ok_or(ErrorKind::MissingDeveloperDefinitionMessage())?;
+            if !filter.is_field_selected(&dev_definition.field_name) {
+                continue;
+            }
             record.push(data_field_with_info(
                 dev_definition.field_definition_number,
                 Some(dev_definition.developer_data_index),
-                &dev_definition.field_name,
+                dev_definition.field_name.clone(),
                 FieldDataType::Byte,
                 dev_definition.scale,
                 dev_definition.offset,
-                &dev_definition.units,
-                value,
+                dev_definition.units.clone(),
+                value.clone(),
                 options,
             )?);
         }
@@ -140,3 +188,25 @@ impl Decoder {
         Ok(())
     }
 }
+
+/// Drop the raw fields that don't decode to any of the selected names before decoding the
+/// message. Fields unknown to the profile are kept for the decoded names to be checked, as well
+/// as fields accumulated across messages and the fields selecting the subfields of kept fields.
+fn retain_selected_fields(mesg_num: MesgNum, fields: &mut FieldMap, names: &HashSet<String>) {
+    let selected: Vec<u8> = fields
+        .keys()
+        .copied()
+        .filter(|nr| {
+            let decoded = mesg_num.decoded_field_names(*nr);
+            decoded.is_empty()
+                || mesg_num.accumulates(*nr)
+                || decoded.iter().any(|name| names.contains(*name))
+        })
+        .collect();
+    let references: Vec<u8> = selected
+        .iter()
+        .flat_map(|nr| mesg_num.subfield_references(*nr))
+        .copied()
+        .collect();
+    fields.retain(|nr, _| selected.contains(nr) || references.contains(nr));
+}
@@ -2,19 +2,22 @@
 //! applying the packaged FIT profile to the data.
 use crate::error::{ErrorKind, Result};
 use crate::profile::MesgNum;
-use crate::{DeveloperFieldDescription, FitDataRecord};
+use crate::{DeveloperFieldDescription, FitDataRecord, Value};
 use nom::number::complete::le_u16;
 use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use std::io::Read;
+use std::ops::Range;
 use std::sync::Arc;
 
-mod crc;
+pub(crate) mod crc;
 use crc::{caculate_crc, update_crc};
 mod decode;
 use decode::Decoder;
-mod parser;
-pub use parser::{FitDataMessage, FitDefinitionMessage, FitFileHeader};
+pub(crate) mod parser;
+pub use parser::{
+    FieldMap, FieldMapIter, FitDataMessage, FitDefinitionMessage, FitFileHeader, FitMessageHeader,
+};
 
 /// Decoding options for the deserializer
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord, Hash)]
@@ -36,6 +39,60 @@ pub enum DecodeOption {
     UseGenericSubFieldName,
 }
 
+/// Restricts decoding to a set of message kinds and field names. Messages of other kinds are
+/// skipped without being decoded, apart from the work needed to keep compressed timestamps,
+/// accumulated fields and developer field descriptions correct for the messages that follow.
+/// Fields with other names are dropped from the decoded messages, including the `timestamp`
+/// field if it isn't selected.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DecodeFilter {
+    kinds: Option<HashSet<MesgNum>>,
+    fields: Option<HashSet<String>>,
+}
+
+impl DecodeFilter {
+    /// Create a filter that selects every message and field
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Selected message kinds, None if every kind is selected
+    pub fn kinds(&self) -> Option<&HashSet<MesgNum>> {
+        self.kinds.as_ref()
+    }
+
+    /// Add a message kind to the selection
+    pub fn push_kind(&mut self, kind: MesgNum) {
+        self.kinds.get_or_insert_with(HashSet::new).insert(kind);
+    }
+
+    /// Selected field names, None if every field is selected
+    pub fn fields(&self) -> Option<&HashSet<String>> {
+        self.fields.as_ref()
+    }
+
+    /// Add a field name to the selection
+    pub fn push_field(&mut self, name: &str) {
+        self.fields
+            .get_or_insert_with(HashSet::new)
+            .insert(name.to_string());
+    }
+
+    /// Check if messages of the kind are decoded
+    pub fn is_kind_selected(&self, kind: MesgNum) -> bool {
+        self.kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(&kind))
+    }
+
+    /// Check if fields with the name are kept
+    pub fn is_field_selected(&self, name: &str) -> bool {
+        self.fields
+            .as_ref()
+            .is_none_or(|fields| fields.contains(name))
+    }
+}
+
 /// Stores a FIT file object (header, message or CRC)
 #[derive(Clone, Debug)]
 pub enum FitObject {
@@ -219,6 +276,7 @@ impl Deserializer {
 pub struct FitStreamProcessor {
     decoder: Decoder,
     deserializer: Deserializer,
+    filter: DecodeFilter,
 }
 
 impl Default for FitStreamProcessor {
@@ -226,6 +284,7 @@ impl Default for FitStreamProcessor {
         FitStreamProcessor {
             decoder: Decoder::new(),
             deserializer: Deserializer::new(),
+            filter: DecodeFilter::default(),
         }
     }
 }
@@ -265,42 +324,98 @@ impl FitStreamProcessor {
             .deserialize_next(input, self.decoder.developer_field_descriptions())
     }
 
-    /// Decode a FIT data message into a FIT data record using the defined FIT profile.
+    /// Fetch the filter used to restrict decoding
+    pub fn filter(&self) -> &DecodeFilter {
+        &self.filter
+    }
+
+    /// Set the filter used to restrict decoding
+    pub fn set_filter(&mut self, filter: DecodeFilter) {
+        self.filter = filter;
+    }
+
+    /// Decode a FIT data message into a FIT data record using the defined FIT profile. The
+    /// message is decoded even if its kind isn't selected by the filter, the field selection
+    /// still applies.
     pub fn decode_message(&mut self, msg: FitDataMessage) -> Result<FitDataRecord> {
         self.decoder
-            .decode_message(msg, self.deserializer.options())
+            .decode_message(msg, self.deserializer.options(), &self.filter)
+    }
+
+    /// Decode a FIT data message if its kind is selected by the filter, otherwise skip it while
+    /// keeping the decoder state up to date and return None.
+    pub fn process_message(&mut self, msg: FitDataMessage) -> Result<Option<FitDataRecord>> {
+        let kind = MesgNum::from(msg.global_message_number());
+        if self.filter.is_kind_selected(kind) {
+            self.decode_message(msg).map(Some)
+        } else {
+            self.decoder
+                .skip_message(msg, self.deserializer.options())
+                .map(|_| None)
+        }
+    }
+
+    /// Set the reference timestamp used to expand compressed timestamps
+    pub(crate) fn set_timestamp_base(&mut self, timestamp: u32) {
+        self.decoder.set_timestamp_base(timestamp);
+    }
+
+    /// Values accumulated across messages so far
+    pub(crate) fn accumulated_values(&self) -> &HashMap<u32, Value> {
+        self.decoder.accumulated_values()
+    }
+
+    /// Replace the values accumulated across messages
+    pub(crate) fn set_accumulated_values(&mut self, values: HashMap<u32, Value>) {
+        self.decoder.set_accumulated_values(values);
+    }
+
+    /// Developer field descriptions decoded so far
+    pub(crate) fn developer_field_descriptions(
+        &self,
+    ) -> &HashMap<(u8, u8), DeveloperFieldDescription> {
+        self.decoder.developer_field_descriptions()
     }
 }
 
 /// Deserialize a FIT file stored as an array of bytes and return the decoded data messages,
 /// with additional decode options
 pub fn from_bytes_with_options(
+    buffer: &[u8],
+    options: &HashSet<DecodeOption>,
+) -> Result<Vec<FitDataRecord>> {
+    from_bytes_with_filter(buffer, options, &DecodeFilter::default())
+}
+
+/// Deserialize a FIT file stored as an array of bytes, with additional decode options, only
+/// decoding the message kinds and fields selected by the filter
+pub fn from_bytes_with_filter(
     mut buffer: &[u8],
     options: &HashSet<DecodeOption>,
+    filter: &DecodeFilter,
 ) -> Result<Vec<FitDataRecord>> {
     let mut processor = FitStreamProcessor::new();
     let mut records = Vec::new();
 
     options.iter().for_each(|o| processor.add_option(*o));
+    processor.set_filter(filter.clone());
     while !buffer.is_empty() {
         let (buf, obj) = processor.deserialize_next(buffer)?;
         match obj {
             FitObject::Crc(..) => processor.reset(),
             FitObject::Header(..) => {}
             FitObject::DataMessage(msg) => {
-                let rec = processor.decode_message(msg)?;
                 // drop the unknown messages if desired but we still need to
                 // decode them just incase the header contains a time-offset
                 // otherwise we'll get incorrect timestamps down the line
-                if processor
-                    .options()
-                    .contains(&DecodeOption::DropUnknownMessages)
-                {
-                    if MesgNum::is_named_variant(rec.kind().as_i64()) {
+                if let Some(rec) = processor.process_message(msg)? {
+                    if !processor
+                        .options()
+                        .contains(&DecodeOption::DropUnknownMessages)
+                        || MesgNum::is_named_variant(rec.kind().as_i64())
+                    {
                         records.push(rec);
                     }
-                } else {
-                    records.push(rec);
                 }
             }
             FitObject::DefinitionMessage(..) => {}
@@ -316,6 +431,149 @@ pub fn from_bytes(buffer: &[u8]) -> Result<Vec<FitDataRecord>> {
     from_bytes_with_options(buffer, &HashSet::new())
 }
 
+/// Result of checking a CRC value against the bytes it covers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrcStatus {
+    /// The stored value matches the bytes
+    Valid,
+    /// The stored value doesn't match the bytes
+    Invalid {
+        /// Value stored in the file
+        found: u16,
+        /// Value computed from the bytes
+        expected: u16,
+    },
+    /// No CRC is stored, e.g. a 12 byte header or a header CRC of zero
+    Missing,
+}
+
+impl CrcStatus {
+    fn check(found: Option<u16>, expected: u16) -> Self {
+        match found {
+            Some(found) if found == expected => CrcStatus::Valid,
+            Some(found) => CrcStatus::Invalid { found, expected },
+            None => CrcStatus::Missing,
+        }
+    }
+
+    /// Check if the CRC is missing or valid
+    pub fn is_ok(&self) -> bool {
+        !matches!(self, CrcStatus::Invalid { .. })
+    }
+}
+
+/// A single FIT file of a chained FIT stream with its decoded data messages
+#[derive(Clone, Debug)]
+pub struct FitFile {
+    header: FitFileHeader,
+    header_crc: CrcStatus,
+    data_crc: CrcStatus,
+    range: Range<usize>,
+    records: Vec<FitDataRecord>,
+}
+
+impl FitFile {
+    /// Header of the file
+    pub fn header(&self) -> &FitFileHeader {
+        &self.header
+    }
+
+    /// Status of the CRC stored in the header
+    pub fn header_crc(&self) -> CrcStatus {
+        self.header_crc
+    }
+
+    /// Status of the CRC stored after the data
+    pub fn data_crc(&self) -> CrcStatus {
+        self.data_crc
+    }
+
+    /// Byte range of the file in the stream, from the start of the header to the end of the
+    /// data CRC
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// Decoded data messages of the file
+    pub fn records(&self) -> &[FitDataRecord] {
+        &self.records
+    }
+
+    /// Take the decoded data messages of the file
+    pub fn into_records(self) -> Vec<FitDataRecord> {
+        self.records
+    }
+}
+
+/// Deserialize a FIT stream stored as an array of bytes into its chained files, with additional
+/// decode options. A file with an invalid CRC is only returned if the matching skip validation
+/// option is set.
+pub fn files_from_bytes_with_options(
+    data: &[u8],
+    options: &HashSet<DecodeOption>,
+) -> Result<Vec<FitFile>> {
+    let mut processor = FitStreamProcessor::new();
+    let mut files: Vec<FitFile> = Vec::new();
+    let mut buffer = data;
+
+    options.iter().for_each(|o| processor.add_option(*o));
+    while !buffer.is_empty() {
+        let position = data.len() - buffer.len();
+        let (buf, obj) = processor.deserialize_next(buffer)?;
+        match obj {
+            FitObject::Header(header) => {
+                let header_size = header.header_size() as usize;
+                let header_crc = CrcStatus::check(
+                    header.crc().filter(|crc| *crc > 0),
+                    caculate_crc(&buffer[0..header_size - 2]),
+                );
+                files.push(FitFile {
+                    header,
+                    header_crc,
+                    data_crc: CrcStatus::Missing,
+                    range: position..position,
+                    records: Vec::new(),
+                });
+            }
+            FitObject::Crc(crc) => {
+                processor.reset();
+                if let Some(file) = files.last_mut() {
+                    // a header without its own CRC is included in the data CRC
+                    let data_start = if file.header_crc == CrcStatus::Missing {
+                        file.range.start
+                    } else {
+                        file.range.start + file.header.header_size() as usize
+                    };
+                    file.data_crc =
+                        CrcStatus::check(Some(crc), caculate_crc(&data[data_start..position]));
+                }
+            }
+            FitObject::DataMessage(msg) => {
+                let rec = processor.decode_message(msg)?;
+                let keep = !processor
+                    .options()
+                    .contains(&DecodeOption::DropUnknownMessages)
+                    || MesgNum::is_named_variant(rec.kind().as_i64());
+                if let (true, Some(file)) = (keep, files.last_mut()) {
+                    file.records.push(rec);
+                }
+            }
+            FitObject::DefinitionMessage(..) => {}
+        }
+        if let Some(file) = files.last_mut() {
+            file.range.end = data.len() - buf.len();
+        }
+        buffer = buf;
+    }
+
+    Ok(files)
+}
+
+/// Deserialize a FIT stream stored as an array of bytes into its chained files.
+pub fn files_from_bytes(data: &[u8]) -> Result<Vec<FitFile>> {
+    files_from_bytes_with_options(data, &HashSet::new())
+}
+
 /// Deserialize a FIT file stored in a source that implements io::Read, with additional decode options
 pub fn from_reader_with_options<T: Read>(
     source: &mut T,
@@ -326,6 +584,18 @@ pub fn from_reader_with_options<T: Read>(
     from_bytes_with_options(&buffer, options)
 }
 
+/// Deserialize a FIT file stored in a source that implements io::Read, with additional decode
+/// options, only decoding the message kinds and fields selected by the filter
+pub fn from_reader_with_filter<T: Read>(
+    source: &mut T,
+    options: &HashSet<DecodeOption>,
+    filter: &DecodeFilter,
+) -> Result<Vec<FitDataRecord>> {
+    let mut buffer = Vec::new();
+    source.read_to_end(&mut buffer)?;
+    from_bytes_with_filter(&buffer, options, filter)
+}
+
 /// Deserialize a FIT file stored in a source that implements io::Read.
 pub fn from_reader<T: Read>(source: &mut T) -> Result<Vec<FitDataRecord>> {
     from_reader_with_options(source, &HashSet::new())
@@ -12,6 +12,7 @@ use nom::sequence::tuple;
 use nom::{Err, IResult, Needed};
 use std::collections::HashMap;
 use std::convert::From;
+use std::fmt;
 use std::sync::Arc;
 
 /// Define an is_valid function needed for parsing here, this function is not needed for normal use
@@ -108,13 +109,40 @@ enum FitMessageType {
 /// The value of the bits inside is different for the two message header types but for simplicity
 /// we treat them the same here and make the time_offset optionnal.
 #[derive(Clone, Debug)]
-struct FitMessageHeader {
+pub struct FitMessageHeader {
     contains_developer_data: bool,
     local_message_number: u8,
     message_type: FitMessageType,
     time_offset: Option<u8>,
 }
 
+impl FitMessageHeader {
+    /// Return true if the definition message includes developer field definitions
+    pub fn contains_developer_data(&self) -> bool {
+        self.contains_developer_data
+    }
+
+    /// Return the local message number the header refers to
+    pub fn local_message_number(&self) -> u8 {
+        self.local_message_number
+    }
+
+    /// Return true if the header starts a definition message
+    pub fn is_definition(&self) -> bool {
+        self.message_type == FitMessageType::Definition
+    }
+
+    /// Return true if the header is a compressed timestamp header
+    pub fn is_compressed_timestamp(&self) -> bool {
+        self.time_offset.is_some()
+    }
+
+    /// Return the time offset in seconds of a compressed timestamp header
+    pub fn time_offset(&self) -> Option<u8> {
+        self.time_offset
+    }
+}
+
 /// Enum used to return either a data message or a new definition message when parsing the body of
 /// the FIT file.
 pub enum FitMessage {
@@ -191,6 +219,23 @@ pub struct FieldDefinition {
     base_type: FitBaseType,
 }
 
+impl FieldDefinition {
+    /// Return the field number within the global FIT message
+    pub fn field_definition_number(&self) -> u8 {
+        self.field_definition_number
+    }
+
+    /// Return the size of the field in bytes
+    pub fn size(&self) -> u8 {
+        self.size
+    }
+
+    /// Return the base type used to store the field
+    pub fn base_type(&self) -> FitBaseType {
+        self.base_type
+    }
+}
+
 /// Developer data fields allow for files to define the meaning of data without requiring changes to
 /// the FIT profile being used. Rather than having information like Field Name, Units, and Base Type
 /// encoded into the profile this information is included in 2 special global messages that act as
@@ -203,6 +248,171 @@ pub struct DeveloperFieldDefinition {
     developer_data_index: u8,
 }
 
+/// Raw field values of a data message keyed by field definition number.
+///
+/// Messages only carry a handful of fields so the values are stored in a vector in the order they
+/// were inserted, for parsed messages that is the order of the definition message. Lookups scan the
+/// vector which is faster than hashing for this size and a message needs a single allocation.
+#[derive(Clone, Default, PartialEq)]
+pub struct FieldMap(Vec<(u8, Value)>);
+
+impl FieldMap {
+    /// Create an empty map
+    pub fn new() -> Self {
+        FieldMap(Vec::new())
+    }
+
+    /// Create an empty map with space for the given number of fields
+    pub fn with_capacity(capacity: usize) -> Self {
+        FieldMap(Vec::with_capacity(capacity))
+    }
+
+    /// Number of fields stored
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check if no fields are stored
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Return the value of a field
+    pub fn get(&self, number: &u8) -> Option<&Value> {
+        self.0.iter().find(|(n, _)| n == number).map(|(_, v)| v)
+    }
+
+    /// Return a mutable reference to the value of a field
+    pub fn get_mut(&mut self, number: &u8) -> Option<&mut Value> {
+        self.0.iter_mut().find(|(n, _)| n == number).map(|(_, v)| v)
+    }
+
+    /// Return the field number and value stored at a position
+    pub fn get_index(&self, index: usize) -> Option<(u8, &Value)> {
+        self.0.get(index).map(|(n, v)| (*n, v))
+    }
+
+    /// Check if a value is stored for the field
+    pub fn contains_key(&self, number: &u8) -> bool {
+        self.0.iter().any(|(n, _)| n == number)
+    }
+
+    /// Set the value of a field, an existing value keeps its position and is returned
+    pub fn insert(&mut self, number: u8, value: Value) -> Option<Value> {
+        match self.get_mut(&number) {
+            Some(existing) => Some(std::mem::replace(existing, value)),
+            None => {
+                self.0.push((number, value));
+                None
+            }
+        }
+    }
+
+    /// Remove a field and return its value, the order of the remaining fields is kept
+    pub fn remove(&mut self, number: &u8) -> Option<Value> {
+        let index = self.0.iter().position(|(n, _)| n == number)?;
+        Some(self.0.remove(index).1)
+    }
+
+    /// Keep only the fields the predicate returns true for
+    pub fn retain<F: FnMut(&u8, &mut Value) -> bool>(&mut self, mut f: F) {
+        self.0.retain_mut(|(n, v)| f(n, v))
+    }
+
+    /// Iterate over the field numbers
+    pub fn keys(&self) -> impl Iterator<Item = &u8> {
+        self.0.iter().map(|(n, _)| n)
+    }
+
+    /// Iterate over the field values
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.0.iter().map(|(_, v)| v)
+    }
+
+    /// Iterate over the field numbers and values
+    pub fn iter(&self) -> FieldMapIter<'_> {
+        FieldMapIter(self.0.iter())
+    }
+
+    /// Iterate over the field numbers and mutable values
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&u8, &mut Value)> {
+        self.0.iter_mut().map(|(n, v)| (&*n, v))
+    }
+}
+
+impl fmt::Debug for FieldMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl From<HashMap<u8, Value>> for FieldMap {
+    /// Convert a map of field values, the fields are ordered by field number
+    fn from(map: HashMap<u8, Value>) -> Self {
+        let mut fields: Vec<(u8, Value)> = map.into_iter().collect();
+        fields.sort_by_key(|(n, _)| *n);
+        FieldMap(fields)
+    }
+}
+
+impl FromIterator<(u8, Value)> for FieldMap {
+    fn from_iter<I: IntoIterator<Item = (u8, Value)>>(iter: I) -> Self {
+        let mut map = FieldMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl Extend<(u8, Value)> for FieldMap {
+    fn extend<I: IntoIterator<Item = (u8, Value)>>(&mut self, iter: I) {
+        for (number, value) in iter {
+            self.insert(number, value);
+        }
+    }
+}
+
+impl IntoIterator for FieldMap {
+    type Item = (u8, Value);
+    type IntoIter = std::vec::IntoIter<(u8, Value)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a FieldMap {
+    type Item = (&'a u8, &'a Value);
+    type IntoIter = FieldMapIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the field numbers and values of a [`FieldMap`]
+#[derive(Clone, Debug)]
+pub struct FieldMapIter<'a>(std::slice::Iter<'a, (u8, Value)>);
+
+impl<'a> Iterator for FieldMapIter<'a> {
+    type Item = (&'a u8, &'a Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(n, v)| (n, v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for FieldMapIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(n, v)| (n, v))
+    }
+}
+
+impl ExactSizeIterator for FieldMapIter<'_> {}
+
 /// Stores a vector of raw fields described by the preceding Definition message, a Definition message
 /// must come before any Data message. The data here will be transfomed into a FitDataRecord using
 /// the information from its defintion message and the MessageInfo struct from the FIT profile
@@ -211,12 +421,22 @@ pub struct FitDataMessage {
     global_message_number: u16,
     time_offset: Option<u8>,
     /// Data field mapping of <(dev_data_idx, field_number), Value>
-    pub fields: HashMap<u8, Value>, //indexed by field_nr
+    pub fields: FieldMap, //indexed by field_nr
     /// Mutable Data field mapping of <(dev_data_idx, field_number), Value>
     pub developer_fields: HashMap<(u8, u8), Value>, //indexed by (dev_data_idx, field_nr)
 }
 
 impl FitDataMessage {
+    /// Create a data message for the global message number from raw, unscaled field values
+    pub fn new(global_message_number: u16, fields: impl Into<FieldMap>) -> Self {
+        FitDataMessage {
+            global_message_number,
+            time_offset: None,
+            fields: fields.into(),
+            developer_fields: HashMap::new(),
+        }
+    }
+
     /// Global message number defined in the FIT profile, set by definition message
     pub fn global_message_number(&self) -> u16 {
         self.global_message_number
@@ -228,12 +448,12 @@ impl FitDataMessage {
     }
 
     /// Data field mapping of <(dev_data_idx, field_number), Value>
-    pub fn fields(&self) -> &HashMap<u8, Value> {
+    pub fn fields(&self) -> &FieldMap {
         &self.fields
     }
 
     /// Mutable Data field mapping of <(dev_data_idx, field_number), Value>
-    pub fn fields_mut(&mut self) -> &mut HashMap<u8, Value> {
+    pub fn fields_mut(&mut self) -> &mut FieldMap {
         &mut self.fields
     }
 
@@ -355,7 +575,7 @@ pub fn fit_message<'a>(
 }
 
 /// Parse the header of a single FIT message
-fn message_header(input: &[u8]) -> IResult<&[u8], FitMessageHeader> {
+pub fn message_header(input: &[u8]) -> IResult<&[u8], FitMessageHeader> {
     let (input, msg_header_byte) = le_u8(input)?;
     let contains_developer_data: bool;
     let local_message_number: u8;
@@ -477,7 +697,7 @@ fn data_message_fields<'a>(
     input: &'a [u8],
     def_mesg: &FitDefinitionMessage,
     developer_field_descriptions: &HashMap<(u8, u8), DeveloperFieldDescription>,
-) -> IResult<&'a [u8], (HashMap<u8, Value>, HashMap<(u8, u8), Value>)> {
+) -> IResult<&'a [u8], (FieldMap, HashMap<(u8, u8), Value>)> {
     match data_message_fields_impl(input, def_mesg, developer_field_descriptions) {
         Ok(r) => Ok(r),
         Err(Err::Incomplete(_)) => {
@@ -497,8 +717,8 @@ fn data_message_fields_impl<'a>(
     input: &'a [u8],
     def_mesg: &FitDefinitionMessage,
     developer_field_descriptions: &HashMap<(u8, u8), DeveloperFieldDescription>,
-) -> IResult<&'a [u8], (HashMap<u8, Value>, HashMap<(u8, u8), Value>)> {
-    let mut fields = HashMap::new();
+) -> IResult<&'a [u8], (FieldMap, HashMap<(u8, u8), Value>)> {
+    let mut fields = FieldMap::with_capacity(def_mesg.field_definitions.len());
     let mut developer_fields = HashMap::new();
     let mut input = input;
     for field_def in &def_mesg.field_definitions {
@@ -811,4 +1031,30 @@ mod tests {
             "This Value array should be invalid since it contains no valid values"
         );
     }
+
+    #[test]
+    fn field_map_keeps_insertion_order() {
+        let mut fields = FieldMap::new();
+        fields.insert(3, Value::UInt8(1));
+        fields.insert(253, Value::UInt32(100));
+        fields.insert(0, Value::Enum(4));
+        assert_eq!(
+            fields.insert(253, Value::UInt32(200)),
+            Some(Value::UInt32(100))
+        );
+        assert_eq!(fields.keys().copied().collect::<Vec<_>>(), vec![3, 253, 0]);
+        assert_eq!(fields.get(&253), Some(&Value::UInt32(200)));
+        assert_eq!(fields.get_index(2), Some((0, &Value::Enum(4))));
+
+        assert_eq!(fields.remove(&3), Some(Value::UInt8(1)));
+        assert_eq!(fields.remove(&3), None);
+        assert_eq!(fields.keys().copied().collect::<Vec<_>>(), vec![253, 0]);
+        assert!(!fields.contains_key(&3));
+
+        let map: HashMap<u8, Value> = fields.clone().into_iter().collect();
+        assert_eq!(
+            FieldMap::from(map).keys().copied().collect::<Vec<_>>(),
+            vec![0, 253]
+        );
+    }
 }
@@ -0,0 +1,430 @@
+//! Compare two FIT files message by message and field by field.
+//!
+//! Messages of the same kind are matched by their `message_index` field, their timestamp or, if
+//! they have neither, by their order. Messages sharing the same key are matched in the order they
+//! appear. Matched messages are compared by decoded field name and numeric values are considered
+//! equal if they are within the tolerance, regardless of the type used to store them. The field
+//! layout of the definition messages is compared per message kind as well, so changes in the size
+//! or base type used to store a field show up even if the decoded values are the same.
+//! ```
+//! use fitparser::diff::Differ;
+//!
+//! let old = std::fs::read("tests/fixtures/Activity.fit")?;
+//! let new = std::fs::read("tests/fixtures/Activity.fit")?;
+//! let mut differ = Differ::new();
+//! differ.set_tolerance(0.01);
+//! for change in differ.diff(&old, &new)? {
+//!     println!("{}", change);
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::error::Result;
+use crate::profile::field_types::FitBaseType;
+use crate::profile::MesgNum;
+use crate::raw::{self, RawObject};
+use crate::{FitDataRecord, Value};
+use chrono::{DateTime, Local};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::fmt;
+
+/// Size in bytes and base type of a field in a definition message
+pub type FieldLayout = (u8, FitBaseType);
+
+/// Key used to match messages of the same kind between the files
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MessageKey {
+    /// Value of the `message_index` field
+    Index(u16),
+    /// Timestamp of the message
+    Timestamp(DateTime<Local>),
+    /// The message has neither and is matched by its order
+    Order,
+}
+
+/// Identifies a message by its kind, key and the number of earlier messages with the same kind
+/// and key
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MessageId {
+    kind: MesgNum,
+    key: MessageKey,
+    occurrence: usize,
+}
+
+impl MessageId {
+    /// Kind of the message
+    pub fn kind(&self) -> MesgNum {
+        self.kind
+    }
+
+    /// Key used to match the message
+    pub fn key(&self) -> MessageKey {
+        self.key
+    }
+
+    /// Number of earlier messages with the same kind and key
+    pub fn occurrence(&self) -> usize {
+        self.occurrence
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        match self.key {
+            MessageKey::Index(index) => write!(f, "[{}]", index)?,
+            MessageKey::Timestamp(timestamp) => write!(f, "@{}", timestamp.to_rfc3339())?,
+            MessageKey::Order => return write!(f, "#{}", self.occurrence),
+        }
+        if self.occurrence > 0 {
+            write!(f, "#{}", self.occurrence)?;
+        }
+        Ok(())
+    }
+}
+
+/// A difference between the old and the new file
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// The message is only present in the new file
+    MessageAdded(MessageId),
+    /// The message is only present in the old file
+    MessageRemoved(MessageId),
+    /// The field is only present in the new message
+    FieldAdded {
+        /// Message containing the field
+        message: MessageId,
+        /// Decoded field name
+        field: String,
+        /// Value in the new file
+        value: Value,
+    },
+    /// The field is only present in the old message
+    FieldRemoved {
+        /// Message containing the field
+        message: MessageId,
+        /// Decoded field name
+        field: String,
+        /// Value in the old file
+        value: Value,
+    },
+    /// The field value differs between the messages
+    FieldChanged {
+        /// Message containing the field
+        message: MessageId,
+        /// Decoded field name
+        field: String,
+        /// Value in the old file
+        old: Value,
+        /// Value in the new file
+        new: Value,
+    },
+    /// The field is defined with a different layout, None if the field isn't defined in a file
+    DefinitionChanged {
+        /// Kind of the defined message
+        kind: MesgNum,
+        /// Field definition number
+        field: u8,
+        /// Layout in the old file
+        old: Option<FieldLayout>,
+        /// Layout in the new file
+        new: Option<FieldLayout>,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let layout = |layout: &Option<FieldLayout>| match layout {
+            Some((size, base_type)) => format!("{} byte {}", size, base_type),
+            None => "undefined".to_string(),
+        };
+        match self {
+            Change::MessageAdded(id) => write!(f, "+ {}", id),
+            Change::MessageRemoved(id) => write!(f, "- {}", id),
+            Change::FieldAdded {
+                message,
+                field,
+                value,
+            } => write!(f, "  {}.{}: added {}", message, field, value),
+            Change::FieldRemoved {
+                message,
+                field,
+                value,
+            } => write!(f, "  {}.{}: removed {}", message, field, value),
+            Change::FieldChanged {
+                message,
+                field,
+                old,
+                new,
+            } => write!(f, "  {}.{}: {} -> {}", message, field, old, new),
+            Change::DefinitionChanged {
+                kind,
+                field,
+                old,
+                new,
+            } => write!(
+                f,
+                "  definition {} field {}: {} -> {}",
+                kind,
+                field,
+                layout(old),
+                layout(new)
+            ),
+        }
+    }
+}
+
+/// Compares FIT files using an absolute tolerance for numeric values
+#[derive(Clone, Debug)]
+pub struct Differ {
+    tolerance: f64,
+}
+
+impl Default for Differ {
+    fn default() -> Self {
+        Differ { tolerance: 1e-6 }
+    }
+}
+
+impl Differ {
+    /// Create a differ with a tolerance of 1e-6
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Largest difference between numeric values that are considered equal
+    pub fn tolerance(&self) -> f64 {
+        self.tolerance
+    }
+
+    /// Set the largest difference between numeric values that are considered equal
+    pub fn set_tolerance(&mut self, tolerance: f64) {
+        self.tolerance = tolerance;
+    }
+
+    /// Compare the definitions and decoded messages of the FIT files
+    pub fn diff(&self, old: &[u8], new: &[u8]) -> Result<Vec<Change>> {
+        let mut changes = diff_definitions(&layouts(old)?, &layouts(new)?);
+        changes.extend(self.diff_records(&crate::from_bytes(old)?, &crate::from_bytes(new)?));
+        Ok(changes)
+    }
+
+    /// Compare decoded messages, changes to messages in the old records come first in their
+    /// order followed by the messages added in the new records
+    pub fn diff_records(&self, old: &[FitDataRecord], new: &[FitDataRecord]) -> Vec<Change> {
+        let old = identify(old);
+        let new = identify(new);
+        let new_ids: HashMap<MessageId, &FitDataRecord> = new.iter().cloned().collect();
+        let mut changes = Vec::new();
+        for (id, old_record) in &old {
+            match new_ids.get(id) {
+                Some(new_record) => self.diff_fields(*id, old_record, new_record, &mut changes),
+                None => changes.push(Change::MessageRemoved(*id)),
+            }
+        }
+        let old_ids: HashMap<MessageId, &FitDataRecord> = old.into_iter().collect();
+        changes.extend(
+            new.iter()
+                .filter(|(id, _)| !old_ids.contains_key(id))
+                .map(|(id, _)| Change::MessageAdded(*id)),
+        );
+        changes
+    }
+
+    fn diff_fields(
+        &self,
+        message: MessageId,
+        old: &FitDataRecord,
+        new: &FitDataRecord,
+        changes: &mut Vec<Change>,
+    ) {
+        for old_field in old.fields() {
+            let field = old_field.name().to_string();
+            match new.field(old_field.name()) {
+                Some(new_field) if self.matches(old_field.value(), new_field.value()) => {}
+                Some(new_field) => changes.push(Change::FieldChanged {
+                    message,
+                    field,
+                    old: old_field.value().clone(),
+                    new: new_field.value().clone(),
+                }),
+                None => changes.push(Change::FieldRemoved {
+                    message,
+                    field,
+                    value: old_field.value().clone(),
+                }),
+            }
+        }
+        for new_field in new.fields() {
+            if old.field(new_field.name()).is_none() {
+                changes.push(Change::FieldAdded {
+                    message,
+                    field: new_field.name().to_string(),
+                    value: new_field.value().clone(),
+                });
+            }
+        }
+    }
+
+    fn matches(&self, old: &Value, new: &Value) -> bool {
+        match (old, new) {
+            (Value::Array(old), Value::Array(new)) => {
+                old.len() == new.len() && old.iter().zip(new).all(|(a, b)| self.matches(a, b))
+            }
+            _ if old == new => true,
+            _ if is_number(old) && is_number(new) => {
+                let old: Result<f64> = old.try_into();
+                let new: Result<f64> = new.try_into();
+                match (old, new) {
+                    (Ok(old), Ok(new)) => (old - new).abs() <= self.tolerance,
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Compare the FIT files with the default tolerance
+pub fn diff(old: &[u8], new: &[u8]) -> Result<Vec<Change>> {
+    Differ::new().diff(old, new)
+}
+
+/// Numbers are compared by value so a field stored with a different type still matches
+fn is_number(value: &Value) -> bool {
+    !matches!(
+        value,
+        Value::Timestamp(_) | Value::String(_) | Value::Array(_) | Value::Invalid
+    )
+}
+
+/// Pair every message with its id
+fn identify(records: &[FitDataRecord]) -> Vec<(MessageId, &FitDataRecord)> {
+    let mut occurrences: HashMap<(MesgNum, MessageKey), usize> = HashMap::new();
+    records
+        .iter()
+        .map(|record| {
+            let key = match record.field("message_index").map(|f| f.value()) {
+                Some(Value::UInt16(index)) => MessageKey::Index(*index),
+                _ => record
+                    .timestamp()
+                    .map_or(MessageKey::Order, MessageKey::Timestamp),
+            };
+            let occurrence = occurrences.entry((record.kind(), key)).or_insert(0);
+            let id = MessageId {
+                kind: record.kind(),
+                key,
+                occurrence: *occurrence,
+            };
+            *occurrence += 1;
+            (id, record)
+        })
+        .collect()
+}
+
+/// Layout of every field defined for each message kind, the first definition of a field wins
+fn layouts(data: &[u8]) -> Result<BTreeMap<(u16, u8), FieldLayout>> {
+    let mut layouts = BTreeMap::new();
+    for obj in raw::from_bytes(data)? {
+        if let RawObject::DefinitionMessage(definition) = obj {
+            let definition = definition.message();
+            for field in definition.field_definitions() {
+                layouts
+                    .entry((
+                        definition.global_message_number(),
+                        field.field_definition_number(),
+                    ))
+                    .or_insert((field.size(), field.base_type()));
+            }
+        }
+    }
+    Ok(layouts)
+}
+
+fn diff_definitions(
+    old: &BTreeMap<(u16, u8), FieldLayout>,
+    new: &BTreeMap<(u16, u8), FieldLayout>,
+) -> Vec<Change> {
+    let mut keys: Vec<&(u16, u8)> = old.keys().chain(new.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter(|key| old.get(key) != new.get(key))
+        .map(|(number, field)| Change::DefinitionChanged {
+            kind: MesgNum::from(*number),
+            field: *field,
+            old: old.get(&(*number, *field)).copied(),
+            new: new.get(&(*number, *field)).copied(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::de::FitDataMessage;
+
+    #[test]
+    fn identical_files_have_no_changes() {
+        let data = include_bytes!("../tests/fixtures/garmin-fenix-5-bike.fit");
+        assert_eq!(diff(data, data).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn changed_messages_and_definitions() {
+        let record = |timestamp: u32, heart_rate: Value, speed: u16| {
+            let mut fields = HashMap::new();
+            fields.insert(253, Value::UInt32(timestamp));
+            fields.insert(3, heart_rate);
+            fields.insert(6, Value::UInt16(speed));
+            FitDataMessage::new(MesgNum::Record.as_u16(), fields)
+        };
+        let old = crate::ser::to_bytes(&[
+            record(1000, Value::UInt8(120), 5000),
+            record(1001, Value::UInt8(121), 5000),
+        ])
+        .unwrap();
+        let new = crate::ser::to_bytes(&[
+            record(1000, Value::UInt16(120), 5001),
+            record(1002, Value::UInt16(125), 5000),
+        ])
+        .unwrap();
+        let old_records = crate::from_bytes(&old).unwrap();
+        let new_records = crate::from_bytes(&new).unwrap();
+        let id = |records: &[FitDataRecord], idx: usize| MessageId {
+            kind: MesgNum::Record,
+            key: MessageKey::Timestamp(records[idx].timestamp().unwrap()),
+            occurrence: 0,
+        };
+
+        let mut differ = Differ::new();
+        assert_eq!(
+            differ.diff(&old, &new).unwrap(),
+            vec![
+                Change::DefinitionChanged {
+                    kind: MesgNum::Record,
+                    field: 3,
+                    old: Some((1, FitBaseType::Uint8)),
+                    new: Some((2, FitBaseType::Uint16)),
+                },
+                Change::FieldChanged {
+                    message: id(&old_records, 0),
+                    field: "enhanced_speed".to_string(),
+                    old: Value::Float64(5.0),
+                    new: Value::Float64(5.001),
+                },
+                Change::MessageRemoved(id(&old_records, 1)),
+                Change::MessageAdded(id(&new_records, 1)),
+            ]
+        );
+        differ.set_tolerance(0.01);
+        assert_eq!(differ.diff(&old, &new).unwrap().len(), 3);
+        assert_eq!(
+            differ.diff(&old, &new).unwrap()[2].to_string(),
+            format!(
+                "+ record@{}",
+                new_records[1].timestamp().unwrap().to_rfc3339()
+            )
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! Helpers shared by the modules that build or edit raw data messages before encoding them
+use crate::analysis::field_f64;
+use crate::analysis::summary::Summary;
+use crate::de::{DecodeOption, FitDataMessage, FitObject, FitStreamProcessor};
+use crate::error::Result;
+use crate::geo::{degrees_to_semicircles, position};
+use crate::profile::field_types::{Event, EventType};
+use crate::profile::MesgNum;
+use crate::ser::TIMESTAMP_FIELD;
+use crate::{FitDataRecord, Value};
+use chrono::{DateTime, Local};
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+/// Field number of the `Record` distance
+pub(crate) const RECORD_DISTANCE_FIELD: u8 = 5;
+
+/// Field number of the `message_index` field shared by all messages
+pub(crate) const MESSAGE_INDEX_FIELD: u8 = 254;
+
+/// Decode the data keeping the raw messages, composite fields are kept so every raw field can be
+/// found by name
+pub(crate) fn decode(mut buffer: &[u8]) -> Result<(Vec<FitDataMessage>, Vec<FitDataRecord>)> {
+    let mut processor = FitStreamProcessor::new();
+    processor.add_option(DecodeOption::KeepCompositeFields);
+    let mut messages = Vec::new();
+    let mut records = Vec::new();
+    while !buffer.is_empty() {
+        let (remaining, obj) = processor.deserialize_next(buffer)?;
+        match obj {
+            FitObject::Crc(..) => processor.reset(),
+            FitObject::DataMessage(msg) => {
+                records.push(processor.decode_message(msg.clone())?);
+                messages.push(msg);
+            }
+            _ => {}
+        }
+        buffer = remaining;
+    }
+    Ok((messages, records))
+}
+
+/// Create a timer event of the given type for all timers
+pub(crate) fn timer_event(timestamp: DateTime<Local>, event_type: EventType) -> FitDataMessage {
+    let mut fields = HashMap::new();
+    fields.insert(TIMESTAMP_FIELD, Value::Timestamp(timestamp));
+    fields.insert(0, Value::Enum(Event::Timer.as_u8()));
+    fields.insert(1, Value::Enum(event_type.as_u8()));
+    fields.insert(4, Value::UInt8(0));
+    FitDataMessage::new(MesgNum::Event.as_u16(), fields)
+}
+
+/// Copy the message, restoring the timestamp of compressed timestamp messages since the
+/// messages they are relative to may be dropped
+pub(crate) fn with_timestamp(
+    message: &FitDataMessage,
+    timestamp: Option<DateTime<Local>>,
+) -> FitDataMessage {
+    let mut message = message.clone();
+    if let (Some(_), Some(timestamp)) = (message.time_offset(), timestamp) {
+        if !message.fields().contains_key(&TIMESTAMP_FIELD) {
+            message
+                .fields_mut()
+                .insert(TIMESTAMP_FIELD, Value::Timestamp(timestamp));
+        }
+    }
+    message
+}
+
+/// Move the activity to the end time, shifting its local timestamp along, and store the total
+/// timer time in seconds and number of sessions
+pub(crate) fn set_activity(
+    message: &mut FitDataMessage,
+    record: &FitDataRecord,
+    end_time: Option<DateTime<Local>>,
+    totals: Option<(f64, usize)>,
+) {
+    if let (Some(end_time), Some(old_end)) = (end_time, record.timestamp()) {
+        message
+            .fields_mut()
+            .insert(TIMESTAMP_FIELD, Value::Timestamp(end_time));
+        if let Some(Value::Timestamp(local)) = record.field("local_timestamp").map(|f| f.value()) {
+            let local = *local + (end_time - old_end);
+            set_field(message, record, "local_timestamp", Value::Timestamp(local));
+        }
+    }
+    if let Some((timer_time, num_sessions)) = totals {
+        set_number(message, record, "total_timer_time", timer_time * 1000.0);
+        set_number(message, record, "num_sessions", num_sessions as f64);
+    }
+}
+
+/// Number of the raw field decoded under the name, subfields share the number of their field
+pub(crate) fn field_number(
+    message: &FitDataMessage,
+    record: &FitDataRecord,
+    name: &str,
+) -> Option<u8> {
+    record
+        .fields()
+        .iter()
+        .find(|f| f.name() == name && message.fields().contains_key(&f.number()))
+        .map(|f| f.number())
+}
+
+/// Replace the value of a field present in the message
+pub(crate) fn set_field(
+    message: &mut FitDataMessage,
+    record: &FitDataRecord,
+    name: &str,
+    value: Value,
+) {
+    if let Some(number) = field_number(message, record, name) {
+        message.fields_mut().insert(number, value);
+    }
+}
+
+/// Replace the value of a numeric field present in the message using its raw value type,
+/// the field is kept as it is if the value is None
+fn set_optional(
+    message: &mut FitDataMessage,
+    record: &FitDataRecord,
+    name: &str,
+    value: Option<f64>,
+) {
+    let number = match field_number(message, record, name) {
+        Some(number) => number,
+        None => return,
+    };
+    let fields = message.fields_mut();
+    let raw = match (value, fields.get(&number)) {
+        (Some(value), Some(raw)) => {
+            let value = value.round();
+            match raw {
+                Value::UInt8(_) => Value::UInt8(value.clamp(0.0, 254.0) as u8),
+                Value::UInt16(_) => Value::UInt16(value.clamp(0.0, 65534.0) as u16),
+                Value::UInt32(_) => Value::UInt32(value.clamp(0.0, 4294967294.0) as u32),
+                Value::SInt16(_) => Value::SInt16(value.clamp(-32768.0, 32766.0) as i16),
+                Value::SInt32(_) => Value::SInt32(value.clamp(-2147483648.0, 2147483646.0) as i32),
+                _ => return,
+            }
+        }
+        _ => return,
+    };
+    fields.insert(number, raw);
+}
+
+/// Replace the value of a numeric field present in the message using its raw value type
+pub(crate) fn set_number(
+    message: &mut FitDataMessage,
+    record: &FitDataRecord,
+    name: &str,
+    value: f64,
+) {
+    set_optional(message, record, name, Some(value));
+}
+
+/// Raw value of a `message_index` field
+pub(crate) fn index_value(index: usize) -> Value {
+    Value::UInt16(index.try_into().unwrap_or(u16::MAX - 1))
+}
+
+/// Store the recomputed totals in the lap or session message, scaled to their raw values. Totals
+/// that can't be recomputed from the records are scaled by the ratio of the new to the stored
+/// timer time, other values that can't be recomputed are kept.
+pub(crate) fn set_summary(message: &mut FitDataMessage, record: &FitDataRecord, summary: &Summary) {
+    let fraction = field_f64(record, &["total_timer_time"])
+        .filter(|t| *t > 0.0)
+        .map(|t| summary.total_timer_time() / t);
+    let totals = [
+        ("total_distance", summary.total_distance(), 100.0),
+        ("total_ascent", summary.total_ascent(), 1.0),
+        ("total_descent", summary.total_descent(), 1.0),
+        ("total_work", summary.total_work(), 1.0),
+        ("total_calories", summary.total_calories(), 1.0),
+    ];
+    for (name, value, scale) in totals.iter() {
+        let value = value.or_else(|| Some(field_f64(record, &[name])? * fraction?));
+        set_optional(message, record, name, value.map(|v| v * scale));
+    }
+    let fields = [
+        (
+            "total_elapsed_time",
+            Some(summary.total_elapsed_time()),
+            1000.0,
+        ),
+        ("total_timer_time", Some(summary.total_timer_time()), 1000.0),
+        ("avg_speed", summary.avg_speed(), 1000.0),
+        ("max_speed", summary.max_speed(), 1000.0),
+        ("enhanced_avg_speed", summary.avg_speed(), 1000.0),
+        ("enhanced_max_speed", summary.max_speed(), 1000.0),
+        ("avg_heart_rate", summary.avg_heart_rate(), 1.0),
+        ("max_heart_rate", summary.max_heart_rate(), 1.0),
+        ("avg_cadence", summary.avg_cadence(), 1.0),
+        ("max_cadence", summary.max_cadence(), 1.0),
+        ("avg_running_cadence", summary.avg_cadence(), 1.0),
+        ("max_running_cadence", summary.max_cadence(), 1.0),
+        ("avg_power", summary.avg_power(), 1.0),
+        ("max_power", summary.max_power(), 1.0),
+    ];
+    for (name, value, scale) in fields.iter() {
+        set_optional(message, record, name, value.map(|v| v * scale));
+    }
+}
+
+/// Store the first and last record positions between the start and end time
+pub(crate) fn set_positions(
+    message: &mut FitDataMessage,
+    record: &FitDataRecord,
+    records: &[FitDataRecord],
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+) {
+    let positions: Vec<(f64, f64)> = records
+        .iter()
+        .filter(|r| r.kind() == MesgNum::Record)
+        .filter(|r| r.timestamp().is_some_and(|t| t >= start && t <= end))
+        .filter_map(|r| position(r, "position_lat", "position_long"))
+        .collect();
+    let semicircles = |pos: Option<&(f64, f64)>, idx: usize| {
+        pos.map(|p| degrees_to_semicircles(if idx == 0 { p.0 } else { p.1 }) as f64)
+    };
+    set_optional(
+        message,
+        record,
+        "start_position_lat",
+        semicircles(positions.first(), 0),
+    );
+    set_optional(
+        message,
+        record,
+        "start_position_long",
+        semicircles(positions.first(), 1),
+    );
+    set_optional(
+        message,
+        record,
+        "end_position_lat",
+        semicircles(positions.last(), 0),
+    );
+    set_optional(
+        message,
+        record,
+        "end_position_long",
+        semicircles(positions.last(), 1),
+    );
+}
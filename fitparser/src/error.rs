@@ -32,6 +32,16 @@ pub enum ErrorKind {
     ValueError(String),
     /// Developer fields must be defined before they can be mentioned
     MissingDeveloperDefinitionMessage(),
+    /// The workout steps don't form a valid workout, e.g. a repeat references a missing step
+    InvalidWorkout(String),
+    /// The course track or course points can't be stored, e.g. the track has a single point
+    InvalidCourse(String),
+    /// The time range used to crop or split an activity doesn't contain any records
+    InvalidCrop(String),
+    /// The activity files can't be merged, e.g. none of them contain any records
+    InvalidMerge(String),
+    /// A message can't be read using the index, e.g. the position is out of range
+    InvalidIndex(String),
 }
 
 impl StdError for ErrorKind {
@@ -45,6 +55,11 @@ impl StdError for ErrorKind {
             ErrorKind::UnexpectedEof(..) => None,
             ErrorKind::ValueError(..) => None,
             ErrorKind::MissingDeveloperDefinitionMessage(..) => None,
+            ErrorKind::InvalidWorkout(..) => None,
+            ErrorKind::InvalidCourse(..) => None,
+            ErrorKind::InvalidCrop(..) => None,
+            ErrorKind::InvalidMerge(..) => None,
+            ErrorKind::InvalidIndex(..) => None,
         }
     }
 }
@@ -95,6 +110,11 @@ impl fmt::Display for ErrorKind {
             ErrorKind::MissingDeveloperDefinitionMessage() => {
                 write!(fmt, "developer field referenced before being defined")
             }
+            ErrorKind::InvalidWorkout(ref message) => write!(fmt, "invalid workout: {}", message),
+            ErrorKind::InvalidCourse(ref message) => write!(fmt, "invalid course: {}", message),
+            ErrorKind::InvalidCrop(ref message) => write!(fmt, "invalid crop: {}", message),
+            ErrorKind::InvalidMerge(ref message) => write!(fmt, "invalid merge: {}", message),
+            ErrorKind::InvalidIndex(ref message) => write!(fmt, "invalid index: {}", message),
         }
     }
 }
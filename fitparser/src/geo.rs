@@ -0,0 +1,41 @@
+//! Helper functions for working with the positional data stored in FIT files. Positions are
+//! stored as signed 32bit integers in "semicircles" where 2^31 semicircles equals 180 degrees.
+use crate::FitDataRecord;
+use std::convert::TryInto;
+
+/// Number of semicircles that make up 180 degrees
+const SEMICIRCLES_PER_180_DEGREES: f64 = 2_147_483_648.0;
+
+/// Convert a value stored in semicircles into degrees
+pub fn semicircles_to_degrees(value: i64) -> f64 {
+    value as f64 * (180.0 / SEMICIRCLES_PER_180_DEGREES)
+}
+
+/// Convert a value in degrees into semicircles, the inverse of [`semicircles_to_degrees`]
+pub fn degrees_to_semicircles(value: f64) -> i32 {
+    (value * (SEMICIRCLES_PER_180_DEGREES / 180.0))
+        .round()
+        .clamp(i32::MIN as f64, i32::MAX as f64) as i32
+}
+
+/// Extract a (latitude, longitude) pair in degrees from a record using the provided field names,
+/// e.g. `position_lat` and `position_long` for a `Record` message. None is returned if either
+/// value is missing or can't be interpreted as an integer.
+pub fn position(record: &FitDataRecord, lat_field: &str, long_field: &str) -> Option<(f64, f64)> {
+    let lat: i64 = record.field(lat_field)?.value().try_into().ok()?;
+    let long: i64 = record.field(long_field)?.value().try_into().ok()?;
+    Some((semicircles_to_degrees(lat), semicircles_to_degrees(long)))
+}
+
+/// Mean radius of the earth in meters used for distance calculations
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Calculate the great circle distance in meters between two (latitude, longitude) pairs
+/// given in degrees using the haversine formula.
+pub fn distance(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (from.0.to_radians(), to.0.to_radians());
+    let dlat = lat2 - lat1;
+    let dlong = (to.1 - from.1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlong / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
@@ -0,0 +1,348 @@
+//! Export the positional data of decoded FIT records as [GeoJSON](https://geojson.org/) features.
+//!
+//! `Record` positions are converted into one LineString per lap or session and the per-point
+//! data (time, heart rate, power and speed) is attached using the `coordinateProperties`
+//! convention, where each property array lines up with the coordinates of the geometry.
+//! `CoursePoint`, `SegmentPoint` and `Event` messages are exported as Point features. The
+//! returned structures implement Serialize so they can be written out using `serde_json`.
+//! ```
+//! use fitparser::geojson::{self, TrackGrouping};
+//! use std::fs::File;
+//!
+//! let mut fp = File::open("tests/fixtures/Activity.fit")?;
+//! let records = fitparser::from_reader(&mut fp)?;
+//! let collection = geojson::from_records(&records, TrackGrouping::Lap);
+//! let json = serde_json::to_string(&collection)?;
+//! assert!(json.starts_with(r#"{"type":"FeatureCollection","features":[{"type":"Feature""#));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::geo::position;
+use crate::profile::MesgNum;
+use crate::{FitDataRecord, Value};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Defines which message is used to split the `Record` positions into LineString features
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackGrouping {
+    /// Create one LineString per `Lap` message
+    Lap,
+    /// Create one LineString per `Session` message
+    Session,
+}
+
+impl TrackGrouping {
+    fn mesg_num(&self) -> MesgNum {
+        match self {
+            TrackGrouping::Lap => MesgNum::Lap,
+            TrackGrouping::Session => MesgNum::Session,
+        }
+    }
+}
+
+/// A GeoJSON FeatureCollection
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type")]
+pub struct FeatureCollection {
+    features: Vec<Feature>,
+}
+
+impl FeatureCollection {
+    /// Return all features in the collection
+    pub fn features(&self) -> &[Feature] {
+        &self.features
+    }
+}
+
+/// A GeoJSON Feature, a geometry along with the FIT fields it was created from
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type")]
+pub struct Feature {
+    geometry: Geometry,
+    properties: Properties,
+}
+
+impl Feature {
+    /// Return the geometry of the feature
+    pub fn geometry(&self) -> &Geometry {
+        &self.geometry
+    }
+
+    /// Return the value of a property if it exists
+    pub fn property(&self, name: &str) -> Option<&Value> {
+        self.properties.fields.get(name)
+    }
+
+    /// Return the per coordinate values of a property if the feature is a LineString
+    pub fn coordinate_property(&self, name: &str) -> Option<&[Value]> {
+        self.properties
+            .coordinate_properties
+            .as_ref()
+            .and_then(|p| p.get(name))
+            .map(|v| v.as_slice())
+    }
+}
+
+/// GeoJSON geometries, positions are stored as `[longitude, latitude]` in degrees with an
+/// optional third value for the altitude in meters.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type")]
+pub enum Geometry {
+    /// A single position
+    Point {
+        /// Position of the point
+        coordinates: Vec<f64>,
+    },
+    /// An ordered list of two or more positions
+    LineString {
+        /// Positions along the line
+        coordinates: Vec<Vec<f64>>,
+    },
+}
+
+/// Properties of a feature, the FIT fields of the source message are flattened into the
+/// properties object.
+#[derive(Clone, Debug, Serialize)]
+struct Properties {
+    #[serde(flatten)]
+    fields: BTreeMap<String, Value>,
+    #[serde(
+        rename = "coordinateProperties",
+        skip_serializing_if = "Option::is_none"
+    )]
+    coordinate_properties: Option<BTreeMap<String, Vec<Value>>>,
+}
+
+impl Properties {
+    /// Create the properties from a record's fields, position fields are dropped since they
+    /// are already represented by the geometry.
+    fn from_record(kind: &str, record: &FitDataRecord) -> Self {
+        let mut fields: BTreeMap<String, Value> = record
+            .fields()
+            .iter()
+            .filter(|f| !f.name().contains("position_"))
+            .map(|f| (f.name().to_string(), f.value().clone()))
+            .collect();
+        fields.insert("kind".to_string(), Value::String(kind.to_string()));
+        Properties {
+            fields,
+            coordinate_properties: None,
+        }
+    }
+}
+
+/// Per coordinate properties attached to track LineStrings, name of the property and the
+/// `Record` fields checked in order for its value.
+const COORDINATE_PROPERTIES: [(&str, &[&str]); 4] = [
+    ("time", &["timestamp"]),
+    ("heart_rate", &["heart_rate"]),
+    ("power", &["power"]),
+    ("speed", &["enhanced_speed", "speed"]),
+];
+
+/// A single position along the track
+struct TrackPoint<'a> {
+    timestamp: Option<DateTime<Local>>,
+    coordinates: Vec<f64>,
+    record: &'a FitDataRecord,
+}
+
+impl<'a> TrackPoint<'a> {
+    fn new(record: &'a FitDataRecord) -> Option<Self> {
+        let (lat, long) = position(record, "position_lat", "position_long")?;
+        let mut coordinates = vec![long, lat];
+        if let Some(alt) = ["enhanced_altitude", "altitude"]
+            .iter()
+            .find_map(|name| record.field(name))
+            .and_then(|f| f.value().try_into().ok())
+        {
+            coordinates.push(alt);
+        }
+        Some(TrackPoint {
+            timestamp: record.timestamp(),
+            coordinates,
+            record,
+        })
+    }
+
+    fn value(&self, names: &[&str]) -> Value {
+        names
+            .iter()
+            .find_map(|name| self.record.field(name))
+            .map_or(Value::Invalid, |f| f.value().clone())
+    }
+}
+
+/// Build a FeatureCollection from decoded FIT records.
+///
+/// Each `Record` with a position is assigned to the last lap or session (depending on the
+/// grouping) whose `start_time` is at or before the record's timestamp, records preceding the
+/// first start time are assigned to the first group. If the file contains no messages of the
+/// requested grouping a single LineString is created for the entire track. Groups with fewer
+/// than two positions are omitted since they can't form a valid LineString.
+pub fn from_records(records: &[FitDataRecord], grouping: TrackGrouping) -> FeatureCollection {
+    let points: Vec<TrackPoint> = records
+        .iter()
+        .filter(|r| r.kind() == MesgNum::Record)
+        .filter_map(TrackPoint::new)
+        .collect();
+    let groups: Vec<&FitDataRecord> = records
+        .iter()
+        .filter(|r| r.kind() == grouping.mesg_num())
+        .collect();
+
+    let mut features = Vec::new();
+    if groups.is_empty() {
+        let mut fields = BTreeMap::new();
+        fields.insert("kind".to_string(), Value::String("track".to_string()));
+        let properties = Properties {
+            fields,
+            coordinate_properties: None,
+        };
+        let members: Vec<&TrackPoint> = points.iter().collect();
+        features.extend(track_feature(properties, &members));
+    } else {
+        let start_times: Vec<Option<DateTime<Local>>> = groups
+            .iter()
+            .map(|g| match g.field("start_time").map(|f| f.value()) {
+                Some(Value::Timestamp(val)) => Some(*val),
+                _ => None,
+            })
+            .collect();
+        let mut members: Vec<Vec<&TrackPoint>> = vec![Vec::new(); groups.len()];
+        for point in &points {
+            let idx = start_times
+                .iter()
+                .rposition(|start| match (start, point.timestamp) {
+                    (Some(start), Some(ts)) => *start <= ts,
+                    _ => false,
+                })
+                .unwrap_or(0);
+            members[idx].push(point);
+        }
+        for (group, members) in groups.iter().zip(members) {
+            let kind = match grouping {
+                TrackGrouping::Lap => "lap",
+                TrackGrouping::Session => "session",
+            };
+            let properties = Properties::from_record(kind, group);
+            features.extend(track_feature(properties, &members));
+        }
+    }
+
+    for record in records {
+        let (kind, coordinates) = match record.kind() {
+            MesgNum::CoursePoint => ("course_point", point_coordinates(record)),
+            MesgNum::SegmentPoint => ("segment_point", point_coordinates(record)),
+            MesgNum::Event => ("event", event_position(record, &points)),
+            _ => continue,
+        };
+        if let Some(coordinates) = coordinates {
+            features.push(Feature {
+                geometry: Geometry::Point { coordinates },
+                properties: Properties::from_record(kind, record),
+            });
+        }
+    }
+
+    FeatureCollection { features }
+}
+
+/// Create a LineString feature from the track points if there are enough of them
+fn track_feature(mut properties: Properties, points: &[&TrackPoint]) -> Option<Feature> {
+    if points.len() < 2 {
+        return None;
+    }
+    let coordinates = points.iter().map(|p| p.coordinates.clone()).collect();
+    properties.coordinate_properties = Some(
+        COORDINATE_PROPERTIES
+            .iter()
+            .map(|(name, fields)| {
+                let values = points.iter().map(|p| p.value(fields)).collect();
+                (name.to_string(), values)
+            })
+            .collect(),
+    );
+
+    Some(Feature {
+        geometry: Geometry::LineString { coordinates },
+        properties,
+    })
+}
+
+/// Coordinates of a message that stores its own position and optionally an altitude
+fn point_coordinates(record: &FitDataRecord) -> Option<Vec<f64>> {
+    let (lat, long) = position(record, "position_lat", "position_long")?;
+    let mut coordinates = vec![long, lat];
+    if let Some(alt) = record
+        .field("altitude")
+        .and_then(|f| f.value().try_into().ok())
+    {
+        coordinates.push(alt);
+    }
+    Some(coordinates)
+}
+
+/// Events don't store a position so the location of the last track point at or before the
+/// event is used, falling back to the first track point for events preceding the track.
+fn event_position(event: &FitDataRecord, points: &[TrackPoint]) -> Option<Vec<f64>> {
+    let timestamp = event.timestamp()?;
+    points
+        .iter()
+        .rev()
+        .find(|p| p.timestamp.is_some_and(|ts| ts <= timestamp))
+        .or_else(|| points.first())
+        .map(|p| p.coordinates.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_bytes;
+
+    #[test]
+    fn activity_to_geojson() {
+        let data = include_bytes!("../tests/fixtures/Activity.fit").to_vec();
+        let records = from_bytes(&data).unwrap();
+        let collection = from_records(&records, TrackGrouping::Lap);
+
+        // one lap with 14 records followed by 3 event markers
+        assert_eq!(collection.features().len(), 4);
+        let lap = &collection.features()[0];
+        match lap.geometry() {
+            Geometry::LineString { coordinates } => {
+                assert_eq!(coordinates.len(), 14);
+                assert!((coordinates[0][0] - -73.1477).abs() < 1e-3);
+                assert!((coordinates[0][1] - 41.5135).abs() < 1e-3);
+            }
+            _ => panic!("Expected a LineString for the lap"),
+        }
+        assert_eq!(
+            lap.property("kind"),
+            Some(&Value::String("lap".to_string()))
+        );
+        assert_eq!(lap.coordinate_property("time").unwrap().len(), 14);
+        assert_eq!(lap.coordinate_property("power").unwrap()[0], Value::Invalid);
+        assert!(matches!(
+            collection.features()[1].geometry(),
+            Geometry::Point { .. }
+        ));
+    }
+
+    #[test]
+    fn track_without_laps() {
+        let data = include_bytes!("../tests/fixtures/Activity.fit").to_vec();
+        let records: Vec<FitDataRecord> = from_bytes(&data)
+            .unwrap()
+            .into_iter()
+            .filter(|r| r.kind() == MesgNum::Record)
+            .collect();
+        let collection = from_records(&records, TrackGrouping::Session);
+        assert_eq!(collection.features().len(), 1);
+        assert_eq!(
+            collection.features()[0].property("kind"),
+            Some(&Value::String("track".to_string()))
+        );
+    }
+}
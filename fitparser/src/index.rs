@@ -0,0 +1,435 @@
+//! Index the messages of a FIT file for random access.
+//!
+//! Building a [`FitIndex`] walks the file once without decoding the data messages, recording
+//! the byte offset, size, message numbers and active definition of every data message, along
+//! with the reference timestamp that compressed timestamp headers are relative to. An
+//! [`IndexedReader`] then seeks directly to the requested messages and decodes only those,
+//! restoring the reference timestamp and the developer field descriptions of the file before
+//! each message. Messages with fields that accumulate across messages are decoded while indexing
+//! and the accumulated values are restored as well, so every message decodes to the same values
+//! as when decoding the whole file.
+//! ```
+//! use fitparser::index::{FitIndex, IndexedReader};
+//! use fitparser::profile::MesgNum;
+//! use std::fs::File;
+//!
+//! let path = "tests/fixtures/garmin-fenix-5-bike.fit";
+//! let index = FitIndex::from_reader(&mut File::open(path)?)?;
+//! let mut reader = IndexedReader::new(File::open(path)?, index);
+//! let position = reader.index().positions(MesgNum::Record)[10];
+//! let record = reader.read(position)?;
+//! let laps = reader.read_kind(MesgNum::Lap)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::de::parser::{fit_message, message_header, FitMessage};
+use crate::de::{DecodeOption, FitDefinitionMessage, FitObject, FitStreamProcessor};
+use crate::error::{ErrorKind, Result};
+use crate::profile::MesgNum;
+use crate::{FitDataRecord, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+/// Location and decoding state of a data message
+#[derive(Clone, Debug)]
+pub struct IndexEntry {
+    offset: u64,
+    size: usize,
+    file: usize,
+    definition: Arc<FitDefinitionMessage>,
+    time_offset: Option<u8>,
+    timestamp_base: u32,
+    /// Values accumulated by the previous messages, shared by the entries until one changes them
+    accumulated: Arc<HashMap<u32, Value>>,
+}
+
+impl IndexEntry {
+    /// Byte offset of the message header in the stream
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Size of the message in bytes, including the message header
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Index of the chained file containing the message
+    pub fn file(&self) -> usize {
+        self.file
+    }
+
+    /// Local message number of the message
+    pub fn local_message_number(&self) -> u8 {
+        self.definition.local_message_number()
+    }
+
+    /// Global message number of the message
+    pub fn global_message_number(&self) -> u16 {
+        self.definition.global_message_number()
+    }
+
+    /// Kind of the message
+    pub fn kind(&self) -> MesgNum {
+        MesgNum::from(self.definition.global_message_number())
+    }
+
+    /// Definition message active for the message
+    pub fn definition(&self) -> &Arc<FitDefinitionMessage> {
+        &self.definition
+    }
+
+    /// Time offset of a compressed timestamp header
+    pub fn time_offset(&self) -> Option<u8> {
+        self.time_offset
+    }
+
+    /// Raw timestamp a compressed timestamp is relative to, the last timestamp before the message
+    pub fn timestamp_base(&self) -> u32 {
+        self.timestamp_base
+    }
+}
+
+/// Index of every data message in a FIT stream
+#[derive(Clone, Debug, Default)]
+pub struct FitIndex {
+    entries: Vec<IndexEntry>,
+}
+
+impl FitIndex {
+    /// Index a FIT stream stored as an array of bytes, with additional decode options. Only the
+    /// CRC validation options have an effect.
+    ///
+    /// Field descriptions and messages with fields that accumulate across messages are decoded,
+    /// all other data messages are only parsed.
+    pub fn from_bytes_with_options(data: &[u8], options: &HashSet<DecodeOption>) -> Result<Self> {
+        let mut processor = FitStreamProcessor::new();
+        let mut entries = Vec::new();
+        let mut file = 0;
+        let mut timestamp_base = 0;
+        let mut accumulated = Arc::new(HashMap::new());
+        let mut definitions = HashMap::new();
+        let mut buffer = data;
+
+        options.iter().for_each(|o| processor.add_option(*o));
+        while !buffer.is_empty() {
+            let offset = (data.len() - buffer.len()) as u64;
+            let (remaining, obj) = processor.deserialize_next(buffer)?;
+            let size = buffer.len() - remaining.len();
+            match obj {
+                FitObject::Crc(..) => {
+                    processor.reset();
+                    definitions.clear();
+                    file += 1;
+                    timestamp_base = 0;
+                    accumulated = Arc::new(HashMap::new());
+                }
+                FitObject::DataMessage(message) => {
+                    let local_message_number = message_header(buffer)
+                        .map(|(_, header)| header.local_message_number())
+                        .unwrap_or(0);
+                    let definition = definitions
+                        .get(&local_message_number)
+                        .map(Arc::clone)
+                        .ok_or(ErrorKind::MissingDefinitionMessage(
+                            local_message_number,
+                            offset as usize,
+                        ))?;
+                    let entry = IndexEntry {
+                        offset,
+                        size,
+                        file,
+                        definition,
+                        time_offset: message.time_offset(),
+                        timestamp_base,
+                        accumulated: Arc::clone(&accumulated),
+                    };
+                    if let Some(Value::UInt32(timestamp)) = message.fields().get(&253) {
+                        timestamp_base = *timestamp;
+                    } else if let Some(time_offset) = message.time_offset() {
+                        timestamp_base = expand_time_offset(timestamp_base, time_offset);
+                    }
+                    // developer field descriptions have to be decoded so later messages using
+                    // the developer fields can be parsed, accumulated values so later messages
+                    // can be decoded without their predecessors
+                    let kind = MesgNum::from(message.global_message_number());
+                    let accumulates = message.fields().keys().any(|nr| kind.accumulates(*nr));
+                    if kind == MesgNum::FieldDescription || accumulates {
+                        processor.decode_message(message)?;
+                    }
+                    if accumulates {
+                        accumulated = Arc::new(processor.accumulated_values().clone());
+                    }
+                    entries.push(entry);
+                }
+                FitObject::DefinitionMessage(definition) => {
+                    definitions.insert(definition.local_message_number(), definition);
+                }
+                FitObject::Header(..) => {}
+            }
+            buffer = remaining;
+        }
+
+        Ok(FitIndex { entries })
+    }
+
+    /// Index a FIT stream stored as an array of bytes
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        Self::from_bytes_with_options(data, &HashSet::new())
+    }
+
+    /// Index a FIT stream stored in a source that implements io::Read, with additional decode
+    /// options
+    pub fn from_reader_with_options<T: Read>(
+        source: &mut T,
+        options: &HashSet<DecodeOption>,
+    ) -> Result<Self> {
+        let mut buffer = Vec::new();
+        source.read_to_end(&mut buffer)?;
+        Self::from_bytes_with_options(&buffer, options)
+    }
+
+    /// Index a FIT stream stored in a source that implements io::Read
+    pub fn from_reader<T: Read>(source: &mut T) -> Result<Self> {
+        Self::from_reader_with_options(source, &HashSet::new())
+    }
+
+    /// Entries of every data message in the order they appear
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// Number of data messages
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the stream has no data messages
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Positions of the entries of the messages of one kind
+    pub fn positions(&self, kind: MesgNum) -> Vec<usize> {
+        let number = kind.as_u16();
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.global_message_number() == number)
+            .map(|(position, _)| position)
+            .collect()
+    }
+}
+
+/// Decodes single data messages of an indexed FIT stream
+pub struct IndexedReader<R> {
+    source: R,
+    index: FitIndex,
+    processor: FitStreamProcessor,
+    /// Chained file whose developer field descriptions are loaded
+    file: Option<usize>,
+}
+
+impl<R: Read + Seek> IndexedReader<R> {
+    /// Create a reader for the source the index was built from
+    pub fn new(source: R, index: FitIndex) -> Self {
+        IndexedReader {
+            source,
+            index,
+            processor: FitStreamProcessor::new(),
+            file: None,
+        }
+    }
+
+    /// Index used by the reader
+    pub fn index(&self) -> &FitIndex {
+        &self.index
+    }
+
+    /// Add a decoding option used for the decoded messages
+    pub fn add_option(&mut self, opt: DecodeOption) {
+        self.processor.add_option(opt);
+    }
+
+    /// Take the source back
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+    /// Decode the message at the position in the index
+    pub fn read(&mut self, position: usize) -> Result<FitDataRecord> {
+        let entry = self.index.entries.get(position).cloned().ok_or_else(|| {
+            ErrorKind::InvalidIndex(format!(
+                "position {} is out of range for {} messages",
+                position,
+                self.index.len()
+            ))
+        })?;
+        self.load_file(entry.file)?;
+        self.decode(&entry)
+    }
+
+    /// Decode every message of one kind
+    pub fn read_kind(&mut self, kind: MesgNum) -> Result<Vec<FitDataRecord>> {
+        self.index
+            .positions(kind)
+            .into_iter()
+            .map(|position| self.read(position))
+            .collect()
+    }
+
+    /// Reset the decoder for another chained file and decode its developer field descriptions
+    fn load_file(&mut self, file: usize) -> Result<()> {
+        if self.file == Some(file) {
+            return Ok(());
+        }
+        self.processor.reset();
+        let descriptions: Vec<IndexEntry> = self
+            .index
+            .entries
+            .iter()
+            .filter(|e| {
+                e.file == file && e.global_message_number() == MesgNum::FieldDescription.as_u16()
+            })
+            .cloned()
+            .collect();
+        for entry in &descriptions {
+            self.decode(entry)?;
+        }
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn decode(&mut self, entry: &IndexEntry) -> Result<FitDataRecord> {
+        let mut bytes = vec![0; entry.size];
+        self.source.seek(SeekFrom::Start(entry.offset))?;
+        self.source.read_exact(&mut bytes)?;
+
+        let mut definitions = HashMap::new();
+        definitions.insert(entry.local_message_number(), Arc::clone(&entry.definition));
+        let message = match fit_message(
+            &bytes,
+            &definitions,
+            self.processor.developer_field_descriptions(),
+        ) {
+            Ok((_, FitMessage::Data(message))) => message,
+            _ => {
+                return Err(ErrorKind::InvalidIndex(format!(
+                    "no data message at offset {}",
+                    entry.offset
+                ))
+                .into())
+            }
+        };
+        self.processor.set_timestamp_base(entry.timestamp_base);
+        self.processor
+            .set_accumulated_values(entry.accumulated.as_ref().clone());
+        self.processor.decode_message(message)
+    }
+}
+
+/// Expand a compressed timestamp time offset using the reference timestamp
+fn expand_time_offset(base: u32, offset: u8) -> u32 {
+    let offset = offset as u32;
+    let mut value = offset + (base & !31);
+    // account for rollover if needed
+    if offset < (base & 31) {
+        value += 32;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::de::crc::caculate_crc;
+    use std::io::Cursor;
+
+    /// Wrap data messages in a FIT header and CRC
+    fn fit_file(messages: &[u8]) -> Vec<u8> {
+        let mut data = vec![12, 0x10, 0x64, 0x00, messages.len() as u8, 0, 0, 0];
+        data.extend_from_slice(b".FIT");
+        data.extend_from_slice(messages);
+        data.extend_from_slice(&caculate_crc(&data).to_le_bytes());
+        data
+    }
+
+    /// Check that every message read out of order decodes to the same fields as a full decode
+    fn assert_random_access_matches(data: &[u8]) {
+        let records = crate::from_bytes(data).unwrap();
+        let index = FitIndex::from_bytes(data).unwrap();
+        assert_eq!(index.len(), records.len());
+        let mut reader = IndexedReader::new(Cursor::new(data), index);
+        // read backwards so every message is decoded without its predecessors
+        for position in (0..records.len()).rev() {
+            let record = reader.read(position).unwrap();
+            let expected = &records[position];
+            assert_eq!(record.kind(), expected.kind());
+            assert_eq!(record.fields().len(), expected.fields().len());
+            for (field, expected) in record.fields().iter().zip(expected.fields()) {
+                assert_eq!(field.name(), expected.name());
+                assert_eq!(field.value(), expected.value(), "{}", field.name());
+                assert_eq!(field.units(), expected.units());
+            }
+        }
+        let laps = reader.read_kind(MesgNum::Lap).unwrap();
+        assert_eq!(
+            laps.len(),
+            records.iter().filter(|r| r.kind() == MesgNum::Lap).count()
+        );
+    }
+
+    #[test]
+    fn random_access_matches_full_decode() {
+        let fixtures: [&[u8]; 3] = [
+            include_bytes!("../tests/fixtures/garmin-fenix-5-bike.fit"),
+            include_bytes!("../tests/fixtures/DeveloperData.fit"),
+            include_bytes!("../tests/fixtures/sample_mulitple_header.fit"),
+        ];
+        for data in fixtures.iter() {
+            assert_random_access_matches(data);
+        }
+        let mut reader = IndexedReader::new(Cursor::new(&[]), FitIndex::default());
+        assert!(reader.read(0).is_err());
+    }
+
+    #[test]
+    fn accumulated_fields_are_restored() {
+        let data = fit_file(&[
+            // record definition with a timestamp and cycles, which accumulate into total cycles
+            0x40, 0, 0, 20, 0, 2, 253, 4, 0x86, 18, 1, 0x02,
+            // data messages with cycles 250, 5 and 10
+            0x00, 0x00, 0x04, 0, 0, 250, 0x00, 0x01, 0x04, 0, 0, 5, 0x00, 0x02, 0x04, 0, 0, 10,
+        ]);
+        assert_random_access_matches(&data);
+
+        let index = FitIndex::from_bytes(&data).unwrap();
+        let mut reader = IndexedReader::new(Cursor::new(data), index);
+        let last = reader.read(2).unwrap();
+        let total = last.fields().iter().find(|f| f.name() == "total_cycles");
+        assert_eq!(total.map(|f| f.value()), Some(&Value::UInt64(265)));
+    }
+
+    #[test]
+    fn compressed_timestamps_are_rebuilt() {
+        let data = fit_file(&[
+            // record definition with a timestamp, then a data message
+            0x40, 0, 0, 20, 0, 1, 253, 4, 0x86, 0x00, 0xFE, 0x03, 0, 0,
+            // record definition with heart rate, then compressed timestamp data messages with
+            // offsets 2 and 1 where the second rolls over
+            0x41, 0, 0, 20, 0, 1, 3, 1, 0x02, 0xA2, 120, 0xA1, 121,
+        ]);
+
+        let records = crate::from_bytes(&data).unwrap();
+        let index = FitIndex::from_bytes(&data).unwrap();
+        assert_eq!(index.entries()[2].time_offset(), Some(1));
+        assert_eq!(index.entries()[2].timestamp_base(), 0x402);
+        let mut reader = IndexedReader::new(Cursor::new(data), index);
+        let last = reader.read(2).unwrap();
+        assert_eq!(last.timestamp(), records[2].timestamp());
+        assert_eq!(
+            (last.timestamp().unwrap() - records[0].timestamp().unwrap()).num_seconds(),
+            35
+        );
+    }
+}
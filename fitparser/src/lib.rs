@@ -2,8 +2,8 @@
 //!
 //! `fitparser` is a utility to parse an ANT FIT file based on a given profile into a more
 //! useful form for consuming applications. To that end the [serde](https://github.com/serde-rs/serde)
-//! framework is used to allow the data to be serialized into any format supported by serde. This
-//! library currently does not support writing FIT files.
+//! framework is used to allow the data to be serialized into any format supported by serde. FIT
+//! files can be written from raw data messages using the encoder in the [`ser`] module.
 //!
 //! ## Example
 //! Open a file or pass in any other object that implements the Read
@@ -38,15 +38,34 @@
 use chrono::{DateTime, Local};
 use profile::field_types::FitBaseType;
 use serde::Serialize;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert;
 use std::fmt;
 
+pub mod activity;
+pub mod analysis;
+pub mod anonymize;
+pub mod course;
+pub mod crop;
 pub mod de;
+pub mod diff;
+mod edit;
 mod error;
+pub mod geo;
+pub mod geojson;
+pub mod index;
+pub mod merge;
 pub mod profile;
+pub mod raw;
+pub mod repair;
+pub mod ser;
+#[cfg(test)]
+mod test_util;
+pub mod validate;
+pub mod workout;
 
-pub use de::{from_bytes, from_reader};
+pub use de::{files_from_bytes, from_bytes, from_reader};
 pub use error::{Error, ErrorKind, Result};
 
 /// Defines a set of data derived from a FIT Data message.
@@ -78,6 +97,19 @@ impl FitDataRecord {
         &self.fields
     }
 
+    /// Return the first field with the given name if it exists in the record
+    pub fn field(&self, name: &str) -> Option<&FitDataField> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    /// Return the value of the `timestamp` field if the record has one
+    pub fn timestamp(&self) -> Option<DateTime<Local>> {
+        match self.field("timestamp")?.value() {
+            Value::Timestamp(val) => Some(*val),
+            _ => None,
+        }
+    }
+
     /// Add a field to the record
     pub fn push(&mut self, field: FitDataField) {
         self.fields.push(field)
@@ -94,31 +126,32 @@ impl FitDataRecord {
     }
 }
 
-/// Stores a value and it's defined units which are set by the FIT profile during decoding
+/// Stores a value and it's defined units which are set by the FIT profile during decoding, the name
+/// and units borrow the static strings of the FIT profile unless the field is a developer field
 #[derive(Clone, Debug, Serialize)]
 pub struct FitDataField {
-    name: String,
+    name: Cow<'static, str>,
     number: u8,
     developer_data_index: Option<u8>, // None for built-in fields, for developer fields identifies which developer
     value: Value,
-    units: String,
+    units: Cow<'static, str>,
 }
 
 impl FitDataField {
     /// Create a new FitDataField
     pub fn new(
-        name: String,
+        name: impl Into<Cow<'static, str>>,
         number: u8,
         developer_data_index: Option<u8>,
         value: Value,
-        units: String,
+        units: impl Into<Cow<'static, str>>,
     ) -> Self {
         FitDataField {
-            name,
+            name: name.into(),
             number,
             developer_data_index,
             value,
-            units,
+            units: units.into(),
         }
     }
 
@@ -235,25 +268,33 @@ impl fmt::Display for Value {
 impl convert::TryInto<f64> for Value {
     type Error = error::Error;
 
+    fn try_into(self) -> Result<f64> {
+        (&self).try_into()
+    }
+}
+
+impl convert::TryInto<f64> for &Value {
+    type Error = error::Error;
+
     fn try_into(self) -> Result<f64> {
         match self {
             Value::Timestamp(val) => Ok(val.timestamp() as f64),
-            Value::Byte(val) => Ok(val as f64),
-            Value::Enum(val) => Ok(val as f64),
-            Value::SInt8(val) => Ok(val as f64),
-            Value::UInt8(val) => Ok(val as f64),
-            Value::UInt8z(val) => Ok(val as f64),
-            Value::SInt16(val) => Ok(val as f64),
-            Value::UInt16(val) => Ok(val as f64),
-            Value::UInt16z(val) => Ok(val as f64),
-            Value::SInt32(val) => Ok(val as f64),
-            Value::UInt32(val) => Ok(val as f64),
-            Value::UInt32z(val) => Ok(val as f64),
-            Value::SInt64(val) => Ok(val as f64),
-            Value::UInt64(val) => Ok(val as f64),
-            Value::UInt64z(val) => Ok(val as f64),
-            Value::Float32(val) => Ok(val as f64),
-            Value::Float64(val) => Ok(val),
+            Value::Byte(val) => Ok(*val as f64),
+            Value::Enum(val) => Ok(*val as f64),
+            Value::SInt8(val) => Ok(*val as f64),
+            Value::UInt8(val) => Ok(*val as f64),
+            Value::UInt8z(val) => Ok(*val as f64),
+            Value::SInt16(val) => Ok(*val as f64),
+            Value::UInt16(val) => Ok(*val as f64),
+            Value::UInt16z(val) => Ok(*val as f64),
+            Value::SInt32(val) => Ok(*val as f64),
+            Value::UInt32(val) => Ok(*val as f64),
+            Value::UInt32z(val) => Ok(*val as f64),
+            Value::SInt64(val) => Ok(*val as f64),
+            Value::UInt64(val) => Ok(*val as f64),
+            Value::UInt64z(val) => Ok(*val as f64),
+            Value::Float32(val) => Ok(*val as f64),
+            Value::Float64(val) => Ok(*val),
             Value::String(_) => {
                 Err(ErrorKind::ValueError(format!("cannot convert {} into an f64", self)).into())
             }
@@ -366,7 +407,7 @@ impl ValueWithUnits {
 
 impl convert::From<FitDataField> for ValueWithUnits {
     fn from(field: FitDataField) -> Self {
-        ValueWithUnits::new(field.value, field.units)
+        ValueWithUnits::new(field.value, field.units.into_owned())
     }
 }
 
@@ -406,7 +447,7 @@ impl TryFrom<&Vec<FitDataField>> for DeveloperFieldDescription {
     ) -> std::result::Result<DeveloperFieldDescription, error::ErrorKind> {
         let mut name_to_value = HashMap::new();
         for field in fields {
-            name_to_value.insert(field.name.clone(), field.value.clone());
+            name_to_value.insert(field.name(), field.value().clone());
         }
         let developer_data_index = if let Value::UInt8(developer_data_index) = name_to_value
             .get("developer_data_index")
@@ -492,6 +533,7 @@ impl TryFrom<&Vec<FitDataField>> for DeveloperFieldDescription {
 mod tests {
     use super::*;
     use std::collections::HashSet;
+    use std::fs::File;
 
     #[test]
     fn parse_activity() {
@@ -585,6 +627,182 @@ mod tests {
         assert_eq!(fit_data.len(), 3023);
     }
 
+    #[test]
+    fn parse_chained_files() {
+        let data = include_bytes!("../tests/fixtures/sample_mulitple_header.fit").to_vec();
+        let files = files_from_bytes(&data).unwrap();
+        assert!(files.len() > 1);
+        assert_eq!(files[0].records()[0].kind(), profile::MesgNum::FileId);
+        assert_eq!(files[0].range().start, 0);
+        assert_eq!(files[files.len() - 1].range().end, data.len());
+        let mut count = 0;
+        for (idx, file) in files.iter().enumerate() {
+            let range = file.range();
+            if idx > 0 {
+                assert_eq!(files[idx - 1].range().end, range.start);
+            }
+            assert_eq!(file.data_crc(), de::CrcStatus::Valid);
+            assert!(file.header_crc().is_ok());
+            // every file of the chain is a standalone file
+            let standalone = from_bytes(&data[range]).unwrap();
+            assert_eq!(standalone.len(), file.records().len());
+            count += file.records().len();
+        }
+        assert_eq!(count, 3023);
+
+        // the status of invalid CRCs is reported when validation is skipped
+        let mut data = include_bytes!("../tests/fixtures/MonitoringFile.fit").to_vec();
+        let leng = data.len();
+        data[leng - 2] ^= 0xFF;
+        assert!(files_from_bytes(&data).is_err());
+        let options = [de::DecodeOption::SkipDataCrcValidation]
+            .iter()
+            .copied()
+            .collect();
+        let files = de::files_from_bytes_with_options(&data, &options).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].header_crc(), de::CrcStatus::Valid);
+        assert!(!files[0].data_crc().is_ok());
+        assert_eq!(files[0].records().len(), 355);
+    }
+
+    #[test]
+    fn parse_with_filter() {
+        let data = include_bytes!("../tests/fixtures/garmin-fenix-5-bike.fit").to_vec();
+        let fit_data = from_bytes(&data).unwrap();
+        let mut filter = de::DecodeFilter::new();
+        filter.push_kind(profile::MesgNum::Session);
+        filter.push_kind(profile::MesgNum::Lap);
+        let options = HashSet::new();
+        let filtered = de::from_bytes_with_filter(&data, &options, &filter).unwrap();
+        let expected: Vec<&FitDataRecord> = fit_data
+            .iter()
+            .filter(|r| filter.is_kind_selected(r.kind()))
+            .collect();
+        assert_eq!(filtered.len(), expected.len());
+        for (record, expected) in filtered.iter().zip(expected) {
+            assert_eq!(record.kind(), expected.kind());
+            assert_eq!(record.fields().len(), expected.fields().len());
+        }
+
+        filter.push_field("timestamp");
+        filter.push_field("total_distance");
+        let filtered = de::from_bytes_with_filter(&data, &options, &filter).unwrap();
+        for record in &filtered {
+            assert_eq!(record.fields().len(), 2);
+            assert!(record.timestamp().is_some());
+            assert!(record.field("total_distance").is_some());
+        }
+    }
+
+    #[test]
+    fn subfields_use_expanded_components() {
+        // activity_type is expanded from current_activity_type_intensity, which is defined after
+        // the cycles field that resolves to steps for walking and running
+        let data = include_bytes!("../tests/fixtures/MonitoringFile.fit").to_vec();
+        let fit_data = from_bytes(&data).unwrap();
+        let on_foot: Vec<&FitDataRecord> = fit_data
+            .iter()
+            .filter(|r| r.kind() == profile::MesgNum::Monitoring)
+            .filter(|r| {
+                r.field("activity_type").is_some_and(
+                    |f| matches!(f.value(), Value::String(s) if s == "walking" || s == "running"),
+                )
+            })
+            .collect();
+        assert!(!on_foot.is_empty());
+        for record in on_foot {
+            assert!(record.field("steps").is_some());
+            assert!(record.field("cycles").is_none());
+        }
+    }
+
+    #[test]
+    fn filtered_fields_match_full_decode() {
+        let mut filter = de::DecodeFilter::new();
+        for name in [
+            "timestamp",
+            "enhanced_speed",
+            "total_cycles",
+            "timer_trigger",
+        ] {
+            filter.push_field(name);
+        }
+        let options = HashSet::new();
+        for path in [
+            "tests/fixtures/garmin-fenix-5-bike.fit",
+            "tests/fixtures/Activity.fit",
+        ] {
+            let fit_data = from_reader(&mut File::open(path).unwrap()).unwrap();
+            let mut fp = File::open(path).unwrap();
+            let filtered = de::from_reader_with_filter(&mut fp, &options, &filter).unwrap();
+            assert_eq!(filtered.len(), fit_data.len());
+            for (record, expected) in filtered.iter().zip(&fit_data) {
+                let expected: Vec<&FitDataField> = expected
+                    .fields()
+                    .iter()
+                    .filter(|f| filter.is_field_selected(f.name()))
+                    .collect();
+                assert_eq!(record.fields().len(), expected.len());
+                for (field, expected) in record.fields().iter().zip(expected) {
+                    assert_eq!(field.name(), expected.name());
+                    assert_eq!(field.value(), expected.value());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn skipped_messages_update_decoder_state() {
+        let messages: &[u8] = &[
+            // record definition with a timestamp and cycles, then a data message
+            0x40, 0, 0, 20, 0, 2, 253, 4, 0x86, 18, 1, 0x02, 0x00, 0xFE, 0x03, 0, 0, 200,
+            // record definition with cycles, then compressed timestamp data messages
+            0x41, 0, 0, 20, 0, 1, 18, 1, 0x02, 0xA2, 100, 0xA1, 50,
+        ];
+        let mut data = vec![12, 0x10, 0x64, 0x00, messages.len() as u8, 0, 0, 0];
+        data.extend_from_slice(b".FIT");
+        data.extend_from_slice(messages);
+        data.extend_from_slice(&de::crc::caculate_crc(&data).to_le_bytes());
+        let mut processor = de::FitStreamProcessor::new();
+        let mut buffer = &data[..];
+        let mut messages = Vec::new();
+        while !buffer.is_empty() {
+            let (remaining, obj) = processor.deserialize_next(buffer).unwrap();
+            if let de::FitObject::DataMessage(msg) = obj {
+                messages.push(msg);
+            }
+            buffer = remaining;
+        }
+
+        let mut processor = de::FitStreamProcessor::new();
+        let expected: Vec<FitDataRecord> = messages
+            .iter()
+            .map(|m| processor.decode_message(m.clone()).unwrap())
+            .collect();
+
+        // skip the first two records, the last one still sees the state they left behind
+        let mut filter = de::DecodeFilter::new();
+        filter.push_kind(profile::MesgNum::Lap);
+        let mut processor = de::FitStreamProcessor::new();
+        processor.set_filter(filter);
+        assert!(processor
+            .process_message(messages[0].clone())
+            .unwrap()
+            .is_none());
+        assert!(processor
+            .process_message(messages[1].clone())
+            .unwrap()
+            .is_none());
+        let last = processor.decode_message(messages[2].clone()).unwrap();
+        assert_eq!(last.timestamp(), expected[2].timestamp());
+        assert!(expected[2].field("total_cycles").is_some());
+        assert_eq!(
+            last.field("total_cycles").map(|f| f.value()),
+            expected[2].field("total_cycles").map(|f| f.value())
+        );
+    }
+
     #[test]
     fn parse_with_header_crc_set_to_zero() {
         // Set header CRC to zero so that the CRC at the EOF includes all bytes
@@ -0,0 +1,355 @@
+//! Merge several activity files into a single activity.
+//!
+//! A ride recorded in two files after a device restart can be joined back into one activity. The
+//! files are ordered by their first record, `Record` messages are concatenated and de-duplicated by
+//! timestamp and the record distances of a file that starts over from zero continue from the
+//! previous file. Laps, events and other timestamped messages are dropped if an earlier file has a
+//! message of the same kind with the same timestamp, identical `DeviceInfo` and developer data
+//! messages are only kept once and `message_index` fields are renumbered. File level messages such
+//! as `FileId` and `UserProfile` are taken from the first file. The sessions are replaced by a
+//! single session spanning all files, with its totals recomputed using
+//! [`analysis::summary`](crate::analysis::summary), and the activity of the last file is updated to
+//! match.
+//! ```
+//! use chrono::Duration;
+//! use fitparser::{crop, merge};
+//!
+//! let data = std::fs::read("tests/fixtures/garmin-fenix-5-bike.fit")?;
+//! let records = fitparser::from_bytes(&data)?;
+//! let start = records.iter().find_map(|r| r.timestamp()).unwrap();
+//! let (first, second) = crop::split(&data, start + Duration::seconds(30))?;
+//! let merged = merge::merge(&[&first, &second])?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::analysis::field_timestamp;
+use crate::analysis::summary::session_summaries;
+use crate::de::{FieldMap, FitDataMessage};
+use crate::edit::{
+    decode, field_number, index_value, set_activity, set_field, set_positions, set_summary,
+    with_timestamp, MESSAGE_INDEX_FIELD, RECORD_DISTANCE_FIELD,
+};
+use crate::error::{ErrorKind, Result};
+use crate::profile::field_types::{Event, EventType};
+use crate::profile::MesgNum;
+use crate::ser::{self, TIMESTAMP_FIELD};
+use crate::{FitDataRecord, Value};
+use chrono::{DateTime, Local};
+use std::collections::{HashMap, HashSet};
+
+/// Field numbers of the `Session` fields set when merging
+const SESSION_EVENT_FIELD: u8 = 0;
+const SESSION_EVENT_TYPE_FIELD: u8 = 1;
+const SESSION_START_TIME_FIELD: u8 = 2;
+const SESSION_TOTAL_ELAPSED_TIME_FIELD: u8 = 7;
+const SESSION_TOTAL_TIMER_TIME_FIELD: u8 = 8;
+const SESSION_TOTAL_DISTANCE_FIELD: u8 = 9;
+const SESSION_FIRST_LAP_INDEX_FIELD: u8 = 25;
+const SESSION_NUM_LAPS_FIELD: u8 = 26;
+
+/// Messages describing the file, the device settings or the user which are only taken from the
+/// first file
+const FILE_MESSAGES: [MesgNum; 9] = [
+    MesgNum::FileId,
+    MesgNum::FileCreator,
+    MesgNum::DeviceSettings,
+    MesgNum::UserProfile,
+    MesgNum::ZonesTarget,
+    MesgNum::Sport,
+    MesgNum::TrainingFile,
+    MesgNum::Workout,
+    MesgNum::WorkoutStep,
+];
+
+/// Messages which are repeated in every file and dropped if a previous file has an identical one
+/// apart from the timestamp
+const SHARED_MESSAGES: [MesgNum; 3] = [
+    MesgNum::DeviceInfo,
+    MesgNum::DeveloperDataId,
+    MesgNum::FieldDescription,
+];
+
+/// Merge the activity files into a single activity file
+pub fn merge(files: &[&[u8]]) -> Result<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(files.len());
+    for (idx, data) in files.iter().enumerate() {
+        let (messages, records) = decode(data)?;
+        let first_record = records
+            .iter()
+            .filter(|r| r.kind() == MesgNum::Record)
+            .find_map(|r| r.timestamp())
+            .ok_or_else(|| invalid_merge(format!("file {} doesn't contain any records", idx)))?;
+        decoded.push((first_record, messages, records));
+    }
+    if decoded.is_empty() {
+        return Err(invalid_merge("no files to merge".to_string()));
+    }
+    decoded.sort_by_key(|(first_record, ..)| *first_record);
+    let files: Vec<(&[FitDataMessage], &[FitDataRecord])> = decoded
+        .iter()
+        .map(|(_, messages, records)| (messages.as_slice(), records.as_slice()))
+        .collect();
+    merge_messages(&files)
+}
+
+fn merge_messages(files: &[(&[FitDataMessage], &[FitDataRecord])]) -> Result<Vec<u8>> {
+    let mut output: Vec<FitDataMessage> = Vec::new();
+    let mut session: Option<FitDataMessage> = None;
+    let mut activity: Option<(FitDataMessage, &FitDataRecord)> = None;
+    let mut start_time: Option<DateTime<Local>> = None;
+    let mut end_time: Option<DateTime<Local>> = None;
+    let mut last_record: Option<DateTime<Local>> = None;
+    let mut last_distance: Option<u32> = None;
+    // kind and timestamp of the messages taken from the previous files
+    let mut written: HashSet<(MesgNum, DateTime<Local>)> = HashSet::new();
+
+    for (file, (messages, records)) in files.iter().enumerate() {
+        // a file recorded after a restart starts counting the distance from zero again
+        let first_distance = messages
+            .iter()
+            .zip(records.iter())
+            .filter(|(_, r)| r.kind() == MesgNum::Record)
+            .find_map(|(m, _)| match m.fields().get(&RECORD_DISTANCE_FIELD) {
+                Some(Value::UInt32(dist)) => Some(*dist),
+                _ => None,
+            });
+        let offset = match (first_distance, last_distance) {
+            (Some(first), Some(last)) if first < last => last,
+            _ => 0,
+        };
+        let previous_files = output.len();
+        let mut file_written = Vec::new();
+
+        for (message, record) in messages.iter().zip(records.iter()) {
+            let kind = record.kind();
+            let timestamp = record.timestamp();
+            let mut message = with_timestamp(message, timestamp);
+            if let Some(ts) = timestamp {
+                end_time = end_time.max(Some(ts));
+            }
+            match kind {
+                _ if FILE_MESSAGES.contains(&kind) && file > 0 => continue,
+                _ if SHARED_MESSAGES.contains(&kind)
+                    && output[..previous_files]
+                        .iter()
+                        .any(|m| is_same_message(m, &message)) =>
+                {
+                    continue
+                }
+                _ if timestamp.is_some_and(|ts| written.contains(&(kind, ts))) => continue,
+                MesgNum::Record if timestamp.is_some() => {
+                    if last_record.is_some() && timestamp <= last_record {
+                        continue;
+                    }
+                    last_record = timestamp;
+                    start_time = start_time.or(timestamp);
+                    if let Some(Value::UInt32(dist)) =
+                        message.fields_mut().get_mut(&RECORD_DISTANCE_FIELD)
+                    {
+                        *dist = dist.saturating_add(offset);
+                        last_distance = Some(*dist);
+                    }
+                }
+                MesgNum::Session => {
+                    if let Some(ts) = field_timestamp(record, "start_time") {
+                        start_time = Some(start_time.map_or(ts, |s| s.min(ts)));
+                    }
+                    if session.is_none() {
+                        session = Some(message);
+                    }
+                    continue;
+                }
+                MesgNum::Activity => {
+                    activity = Some((message, record));
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some(ts) = timestamp {
+                file_written.push((kind, ts));
+            }
+            output.push(message);
+        }
+        written.extend(file_written);
+    }
+
+    let (start_time, end_time) = match (start_time, end_time) {
+        (Some(start), Some(end)) => (start, end),
+        _ => {
+            return Err(invalid_merge(
+                "the files don't contain any records".to_string(),
+            ))
+        }
+    };
+    let num_laps = output
+        .iter()
+        .filter(|m| m.global_message_number() == MesgNum::Lap.as_u16())
+        .count();
+    let mut session = session.unwrap_or_else(new_session);
+    let fields = session.fields_mut();
+    fields.insert(TIMESTAMP_FIELD, Value::Timestamp(end_time));
+    fields.insert(SESSION_START_TIME_FIELD, Value::Timestamp(start_time));
+    fields.insert(SESSION_FIRST_LAP_INDEX_FIELD, index_value(0));
+    fields.insert(SESSION_NUM_LAPS_FIELD, index_value(num_laps));
+    let session_pos = output.len();
+    output.push(session);
+
+    // the merged messages are decoded once to look up their fields by name and to recompute the
+    // session totals, renumbering them below doesn't change the totals
+    let (_, named) = decode(&ser::to_bytes(&output)?)?;
+    let mut counters: HashMap<u16, usize> = HashMap::new();
+    for (message, record) in output.iter_mut().zip(named.iter()) {
+        if field_number(message, record, "message_index").is_some() {
+            let counter = counters.entry(message.global_message_number()).or_insert(0);
+            set_field(message, record, "message_index", index_value(*counter));
+            *counter += 1;
+        }
+    }
+
+    let totals = session_summaries(&named);
+    let summary = totals
+        .first()
+        .ok_or_else(|| invalid_merge("the merged session has no summary".to_string()))?;
+    let message = &mut output[session_pos];
+    set_summary(message, &named[session_pos], summary);
+    set_positions(message, &named[session_pos], &named, start_time, end_time);
+
+    if let Some((mut message, record)) = activity {
+        set_activity(
+            &mut message,
+            record,
+            Some(end_time),
+            Some((summary.total_timer_time(), 1)),
+        );
+        output.push(message);
+    }
+
+    ser::to_bytes(&output)
+}
+
+/// Check if the messages are of the same type and have the same fields apart from the timestamp
+fn is_same_message(a: &FitDataMessage, b: &FitDataMessage) -> bool {
+    let fields = |m: &FitDataMessage| {
+        let mut fields: Vec<(u8, Value)> = m
+            .fields()
+            .iter()
+            .filter(|(number, _)| **number != TIMESTAMP_FIELD)
+            .map(|(number, value)| (*number, value.clone()))
+            .collect();
+        fields.sort_by_key(|(number, _)| *number);
+        fields
+    };
+    a.global_message_number() == b.global_message_number()
+        && a.developer_fields() == b.developer_fields()
+        && fields(a) == fields(b)
+}
+
+/// Session used when none of the files have one, the totals are filled in once the records are
+/// merged
+fn new_session() -> FitDataMessage {
+    let fields: FieldMap = [
+        (SESSION_EVENT_FIELD, Value::Enum(Event::Lap.as_u8())),
+        (
+            SESSION_EVENT_TYPE_FIELD,
+            Value::Enum(EventType::Stop.as_u8()),
+        ),
+        (SESSION_TOTAL_ELAPSED_TIME_FIELD, Value::UInt32(0)),
+        (SESSION_TOTAL_TIMER_TIME_FIELD, Value::UInt32(0)),
+        (SESSION_TOTAL_DISTANCE_FIELD, Value::UInt32(0)),
+        (MESSAGE_INDEX_FIELD, index_value(0)),
+    ]
+    .into_iter()
+    .collect();
+    FitDataMessage::new(MesgNum::Session.as_u16(), fields)
+}
+
+fn invalid_merge(message: String) -> crate::Error {
+    ErrorKind::InvalidMerge(message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::field_f64;
+    use crate::crop::split;
+    use chrono::Duration;
+
+    fn count(records: &[FitDataRecord], kind: MesgNum) -> usize {
+        records.iter().filter(|r| r.kind() == kind).count()
+    }
+
+    fn find(records: &[FitDataRecord], kind: MesgNum) -> &FitDataRecord {
+        records.iter().find(|r| r.kind() == kind).unwrap()
+    }
+
+    #[test]
+    fn merge_split_activity() {
+        let data = include_bytes!("../tests/fixtures/garmin-fenix-5-bike.fit");
+        let records = crate::from_bytes(data).unwrap();
+        let start = find(&records, MesgNum::Record).timestamp().unwrap();
+        let (first, second) = split(data, start + Duration::seconds(30)).unwrap();
+
+        // the files are ordered by their records, not by their position in the input
+        let merged = crate::from_bytes(&merge(&[&second, &first]).unwrap()).unwrap();
+        assert_eq!(merged[0].kind(), MesgNum::FileId);
+        assert_eq!(
+            count(&merged, MesgNum::Record),
+            count(&records, MesgNum::Record)
+        );
+        assert_eq!(
+            count(&merged, MesgNum::DeviceInfo),
+            count(&records, MesgNum::DeviceInfo)
+        );
+        assert_eq!(count(&merged, MesgNum::Session), 1);
+        assert_eq!(count(&merged, MesgNum::Activity), 1);
+        let distances: Vec<f64> = merged
+            .iter()
+            .filter(|r| r.kind() == MesgNum::Record)
+            .filter_map(|r| field_f64(r, &["distance"]))
+            .collect();
+        assert!(distances.windows(2).all(|d| d[0] <= d[1]));
+
+        let laps: Vec<f64> = merged
+            .iter()
+            .filter(|r| r.kind() == MesgNum::Lap)
+            .filter_map(|r| field_f64(r, &["message_index"]))
+            .collect();
+        assert_eq!(laps, (0..laps.len()).map(|i| i as f64).collect::<Vec<_>>());
+
+        let session = find(&merged, MesgNum::Session);
+        let original = &session_summaries(&records)[0];
+        let distance = field_f64(session, &["total_distance"]).unwrap();
+        assert!((distance - original.total_distance().unwrap()).abs() < 0.1);
+        assert_eq!(field_timestamp(session, "start_time"), Some(start));
+        assert_eq!(field_f64(session, &["num_laps"]), Some(laps.len() as f64));
+        assert!(crate::validate::validate(&merged)[0].is_valid());
+    }
+
+    #[test]
+    fn duplicate_records_are_dropped() {
+        let data = include_bytes!("../tests/fixtures/garmin-fenix-5-bike.fit");
+        let records = crate::from_bytes(data).unwrap();
+        let merged = crate::from_bytes(&merge(&[data, data]).unwrap()).unwrap();
+        assert_eq!(
+            count(&merged, MesgNum::Record),
+            count(&records, MesgNum::Record)
+        );
+        assert_eq!(
+            count(&merged, MesgNum::DeviceInfo),
+            count(&records, MesgNum::DeviceInfo)
+        );
+        assert_eq!(count(&merged, MesgNum::Lap), count(&records, MesgNum::Lap));
+        assert_eq!(
+            count(&merged, MesgNum::Event),
+            count(&records, MesgNum::Event)
+        );
+        let session = find(&merged, MesgNum::Session);
+        assert_eq!(
+            field_f64(session, &["num_laps"]),
+            Some(count(&records, MesgNum::Lap) as f64)
+        );
+
+        assert!(merge(&[]).is_err());
+        let settings = include_bytes!("../tests/fixtures/Settings.fit");
+        assert!(merge(&[data, settings]).is_err());
+    }
+}
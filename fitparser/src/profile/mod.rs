@@ -4,6 +4,7 @@ use crate::de::DecodeOption;
 use crate::error::{ErrorKind, Result};
 use crate::{FitDataField, Value};
 use chrono::{DateTime, Duration, Local, NaiveDate, TimeZone};
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 
@@ -76,6 +77,13 @@ impl TimestampField {
     }
 }
 
+impl From<DateTime<Local>> for TimestampField {
+    /// Convert a timestamp into its offset from the UTC reference date
+    fn from(timestamp: DateTime<Local>) -> TimestampField {
+        TimestampField::Utc((timestamp - TimestampField::Utc(0).to_date_time()).num_seconds())
+    }
+}
+
 impl From<TimestampField> for Value {
     fn from(timestamp: TimestampField) -> Value {
         Value::Timestamp(timestamp.to_date_time())
@@ -208,21 +216,21 @@ pub fn calculate_cumulative_value(
 pub fn data_field_with_info(
     def_number: u8,
     developer_data_index: Option<u8>,
-    name: &str,
+    name: impl Into<Cow<'static, str>>,
     data_type: FieldDataType,
     scale: f64,
     offset: f64,
-    units: &str,
+    units: impl Into<Cow<'static, str>>,
     value: Value,
     options: &HashSet<DecodeOption>,
 ) -> Result<FitDataField> {
     let value = convert_value(data_type, scale, offset, value, options)?;
     Ok(FitDataField::new(
-        name.to_string(),
+        name,
         def_number,
         developer_data_index,
         value,
-        units.to_string(),
+        units,
     ))
 }
 
@@ -233,7 +241,7 @@ pub fn unknown_field(field_def_num: u8, value: Value) -> FitDataField {
         field_def_num,
         None,
         value,
-        String::new(),
+        "",
     )
 }
 
@@ -0,0 +1,248 @@
+//! Parse FIT files into their structural elements while keeping the original bytes.
+//!
+//! Each header, definition message, data message and CRC of a file is returned as a
+//! [`RawObject`] holding the exact bytes it was parsed from, including the message header byte
+//! which tells normal and compressed timestamp headers apart. Writing the objects back with
+//! [`to_bytes`] reproduces the input byte for byte, so edits can be limited to the objects that
+//! need to change.
+//! ```
+//! use fitparser::raw;
+//!
+//! let data = std::fs::read("tests/fixtures/Activity.fit")?;
+//! let objects = raw::from_bytes(&data)?;
+//! for obj in &objects {
+//!     if let raw::RawObject::DataMessage(msg) = obj {
+//!         println!("{:#04x} {:?}", msg.header_byte(), msg.bytes());
+//!     }
+//! }
+//! assert_eq!(raw::to_bytes(&objects), data);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::de::parser::message_header;
+use crate::de::{
+    DecodeOption, FitDataMessage, FitDefinitionMessage, FitFileHeader, FitMessageHeader, FitObject,
+    FitStreamProcessor,
+};
+use crate::error::{ErrorKind, Result};
+use crate::profile::MesgNum;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// A FIT file header and its bytes
+#[derive(Clone, Debug)]
+pub struct RawHeader {
+    header: FitFileHeader,
+    bytes: Vec<u8>,
+}
+
+impl RawHeader {
+    /// Parsed header
+    pub fn header(&self) -> &FitFileHeader {
+        &self.header
+    }
+
+    /// Bytes of the header, including the header CRC if present
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// A definition or data message, its message header and its bytes
+#[derive(Clone, Debug)]
+pub struct RawMessage<T> {
+    header: FitMessageHeader,
+    message: T,
+    bytes: Vec<u8>,
+}
+
+impl<T> RawMessage<T> {
+    /// Parsed message header
+    pub fn header(&self) -> &FitMessageHeader {
+        &self.header
+    }
+
+    /// Parsed message
+    pub fn message(&self) -> &T {
+        &self.message
+    }
+
+    /// Bytes of the message starting with the message header byte
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Original message header byte
+    pub fn header_byte(&self) -> u8 {
+        self.bytes[0]
+    }
+
+    /// Local message number of the message
+    pub fn local_message_number(&self) -> u8 {
+        self.header.local_message_number()
+    }
+
+    /// Check if the message used a compressed timestamp header
+    pub fn is_compressed_timestamp(&self) -> bool {
+        self.header.is_compressed_timestamp()
+    }
+}
+
+/// A structural element of a FIT file with its original bytes
+#[derive(Clone, Debug)]
+pub enum RawObject {
+    /// Header containing FIT file info
+    Header(RawHeader),
+    /// A definition message used to define upcoming data messages
+    DefinitionMessage(RawMessage<Arc<FitDefinitionMessage>>),
+    /// A data message
+    DataMessage(RawMessage<FitDataMessage>),
+    /// Checksum at end of data section
+    Crc(u16),
+}
+
+impl RawObject {
+    /// Bytes of the object as they appear in the file
+    pub fn bytes(&self) -> Vec<u8> {
+        match self {
+            RawObject::Header(header) => header.bytes.clone(),
+            RawObject::DefinitionMessage(message) => message.bytes.clone(),
+            RawObject::DataMessage(message) => message.bytes.clone(),
+            RawObject::Crc(crc) => crc.to_le_bytes().to_vec(),
+        }
+    }
+
+    /// Append the bytes of the object to the buffer
+    pub fn write_to(&self, buffer: &mut Vec<u8>) {
+        match self {
+            RawObject::Header(header) => buffer.extend_from_slice(&header.bytes),
+            RawObject::DefinitionMessage(message) => buffer.extend_from_slice(&message.bytes),
+            RawObject::DataMessage(message) => buffer.extend_from_slice(&message.bytes),
+            RawObject::Crc(crc) => buffer.extend_from_slice(&crc.to_le_bytes()),
+        }
+    }
+}
+
+/// Parse a FIT file stored as an array of bytes into its structural elements, with additional
+/// decode options. Only the CRC validation options have an effect.
+pub fn from_bytes_with_options(
+    mut buffer: &[u8],
+    options: &HashSet<DecodeOption>,
+) -> Result<Vec<RawObject>> {
+    let mut processor = FitStreamProcessor::new();
+    let mut objects = Vec::new();
+
+    options.iter().for_each(|o| processor.add_option(*o));
+    while !buffer.is_empty() {
+        let (remaining, obj) = processor.deserialize_next(buffer)?;
+        let bytes = buffer[0..(buffer.len() - remaining.len())].to_vec();
+        let raw = match obj {
+            FitObject::Crc(crc) => {
+                processor.reset();
+                RawObject::Crc(crc)
+            }
+            FitObject::Header(header) => RawObject::Header(RawHeader { header, bytes }),
+            FitObject::DefinitionMessage(message) => RawObject::DefinitionMessage(RawMessage {
+                header: parse_message_header(&bytes)?,
+                message,
+                bytes,
+            }),
+            FitObject::DataMessage(message) => {
+                // developer field descriptions have to be decoded so later messages using the
+                // developer fields can be parsed
+                if message.global_message_number() == MesgNum::FieldDescription.as_u16() {
+                    processor.decode_message(message.clone())?;
+                }
+                RawObject::DataMessage(RawMessage {
+                    header: parse_message_header(&bytes)?,
+                    message,
+                    bytes,
+                })
+            }
+        };
+        objects.push(raw);
+        buffer = remaining;
+    }
+
+    Ok(objects)
+}
+
+/// Parse a FIT file stored as an array of bytes into its structural elements
+pub fn from_bytes(buffer: &[u8]) -> Result<Vec<RawObject>> {
+    from_bytes_with_options(buffer, &HashSet::new())
+}
+
+/// Write the objects back into FIT file bytes
+pub fn to_bytes(objects: &[RawObject]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    objects.iter().for_each(|obj| obj.write_to(&mut buffer));
+    buffer
+}
+
+fn parse_message_header(bytes: &[u8]) -> Result<FitMessageHeader> {
+    message_header(bytes)
+        .map(|(_, header)| header)
+        .map_err(|_| ErrorKind::UnexpectedEof(nom::Needed::new(1)).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::de::crc::caculate_crc;
+
+    #[test]
+    fn fixtures_round_trip() {
+        let fixtures: [&[u8]; 4] = [
+            include_bytes!("../tests/fixtures/Activity.fit"),
+            include_bytes!("../tests/fixtures/DeveloperData.fit"),
+            include_bytes!("../tests/fixtures/MonitoringFile.fit"),
+            include_bytes!("../tests/fixtures/sample_mulitple_header.fit"),
+        ];
+        for data in fixtures.iter() {
+            let objects = from_bytes(data).unwrap();
+            assert_eq!(to_bytes(&objects), *data);
+        }
+    }
+
+    #[test]
+    fn message_headers_are_kept() {
+        let messages: &[u8] = &[
+            // record definition with a timestamp, then a data message
+            0x40, 0, 0, 20, 0, 1, 253, 4, 0x86, 0x00, 0xE8, 0x03, 0, 0,
+            // record definition with heart rate, then a compressed timestamp data message
+            0x41, 0, 0, 20, 0, 1, 3, 1, 0x02, 0xAA, 120,
+        ];
+        let mut data = vec![12, 0x10, 0x64, 0x00, messages.len() as u8, 0, 0, 0];
+        data.extend_from_slice(b".FIT");
+        data.extend_from_slice(messages);
+        data.extend_from_slice(&caculate_crc(&data).to_le_bytes());
+
+        let objects = from_bytes(&data).unwrap();
+        assert_eq!(objects.len(), 6);
+        assert_eq!(to_bytes(&objects), data);
+        match &objects[2] {
+            RawObject::DataMessage(msg) => {
+                assert_eq!(msg.header_byte(), 0x00);
+                assert!(!msg.is_compressed_timestamp());
+            }
+            obj => panic!("unexpected object {:?}", obj),
+        }
+        match &objects[3] {
+            RawObject::DefinitionMessage(msg) => {
+                assert!(msg.header().is_definition());
+                assert_eq!(msg.local_message_number(), 1);
+                assert_eq!(msg.message().global_message_number(), 20);
+            }
+            obj => panic!("unexpected object {:?}", obj),
+        }
+        match &objects[4] {
+            RawObject::DataMessage(msg) => {
+                assert_eq!(msg.header_byte(), 0xAA);
+                assert!(msg.is_compressed_timestamp());
+                assert_eq!(msg.local_message_number(), 1);
+                assert_eq!(msg.header().time_offset(), Some(10));
+                assert_eq!(msg.bytes(), &[0xAA, 120]);
+            }
+            obj => panic!("unexpected object {:?}", obj),
+        }
+    }
+}
@@ -0,0 +1,405 @@
+//! Repair FIT files with a broken header or a missing or invalid CRC.
+//!
+//! Files written by a device that lost power or was edited by other tools often have a header
+//! `data_size` that doesn't match the messages present, a truncated final message or no trailing
+//! CRC. The repair walks the messages of each file without validating CRC values, drops
+//! everything after the last complete message, rewrites the header `data_size` and recomputes the
+//! header and data CRC values. Chained files are repaired one at a time.
+//! ```
+//! use fitparser::repair::repair;
+//!
+//! let mut data = std::fs::read("tests/fixtures/Activity.fit")?;
+//! data.truncate(data.len() - 10);
+//! let repaired = repair(&data)?;
+//! for fix in repaired.fixes() {
+//!     println!("{}", fix);
+//! }
+//! let records = fitparser::from_bytes(repaired.data())?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::de::crc::{caculate_crc, update_crc};
+use crate::de::parser::{self, FitDefinitionMessage, FitMessage};
+use crate::error::{ErrorKind, Result};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::sync::Arc;
+
+/// A change made to the data while repairing it, `file` is the index of the file in a chained
+/// FIT stream and positions are byte offsets into the original data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fix {
+    /// The header `data_size` didn't match the size of the complete messages
+    DataSize {
+        /// Index of the file
+        file: usize,
+        /// Value stored in the header
+        found: u32,
+        /// Size of the complete messages
+        fixed: u32,
+    },
+    /// Bytes after the last complete message were removed
+    Truncated {
+        /// Index of the file
+        file: usize,
+        /// Position of the first removed byte
+        position: usize,
+        /// Number of bytes removed
+        length: usize,
+    },
+    /// The header CRC was zero or didn't match the header
+    HeaderCrc {
+        /// Index of the file
+        file: usize,
+        /// Value stored in the header
+        found: u16,
+        /// Recomputed value
+        fixed: u16,
+    },
+    /// The data CRC was missing or didn't match the data
+    DataCrc {
+        /// Index of the file
+        file: usize,
+        /// Value stored after the data, None if it was missing
+        found: Option<u16>,
+        /// Recomputed value
+        fixed: u16,
+    },
+}
+
+impl fmt::Display for Fix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fix::DataSize { file, found, fixed } => write!(
+                f,
+                "file {}: changed header data_size from {} to {}",
+                file, found, fixed
+            ),
+            Fix::Truncated {
+                file,
+                position,
+                length,
+            } => write!(
+                f,
+                "file {}: removed {} bytes of incomplete data at position {:#x}",
+                file, length, position
+            ),
+            Fix::HeaderCrc { file, found, fixed } => write!(
+                f,
+                "file {}: changed header CRC from {:#06x} to {:#06x}",
+                file, found, fixed
+            ),
+            Fix::DataCrc {
+                file,
+                found: Some(found),
+                fixed,
+            } => write!(
+                f,
+                "file {}: changed data CRC from {:#06x} to {:#06x}",
+                file, found, fixed
+            ),
+            Fix::DataCrc {
+                file,
+                found: None,
+                fixed,
+            } => write!(f, "file {}: added missing data CRC {:#06x}", file, fixed),
+        }
+    }
+}
+
+/// Repaired FIT data and the fixes that were applied
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Repair {
+    data: Vec<u8>,
+    fixes: Vec<Fix>,
+}
+
+impl Repair {
+    /// Repaired FIT data
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Take the repaired FIT data
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Fixes applied in order
+    pub fn fixes(&self) -> &[Fix] {
+        &self.fixes
+    }
+
+    /// Check if the data had to be changed
+    pub fn is_modified(&self) -> bool {
+        !self.fixes.is_empty()
+    }
+}
+
+/// End of the messages of a file found by scanning them
+struct Scan {
+    /// Position after the last complete message
+    end: usize,
+    /// Bytes treated as the data CRC
+    crc: Option<u16>,
+    /// Position after the data CRC
+    next: usize,
+}
+
+/// Repair the FIT data, an error is only returned if the data doesn't start with a FIT header
+pub fn repair(data: &[u8]) -> Result<Repair> {
+    let mut output = Vec::with_capacity(data.len() + 2);
+    let mut fixes = Vec::new();
+    let mut start = 0;
+    let mut file = 0;
+
+    loop {
+        let (_, header) =
+            parser::fit_file_header(&data[start..]).map_err(|e| parse_err(e, start))?;
+        let header_size = header.header_size() as usize;
+        let header_crc = header.crc().unwrap_or(0);
+        let data_start = start + header_size;
+        let declared_end = data_start + header.data_size() as usize;
+
+        // a header without its own CRC is included in the data CRC
+        let initial_crc = if header_crc == 0 {
+            caculate_crc(&data[start..data_start])
+        } else {
+            0
+        };
+        let scan = scan_messages(data, data_start, declared_end, initial_crc);
+
+        let data_size = (scan.end - data_start) as u32;
+        if data_size != header.data_size() {
+            fixes.push(Fix::DataSize {
+                file,
+                found: header.data_size(),
+                fixed: data_size,
+            });
+        }
+        let (fixed_header_crc, fixed) = write_file(
+            &data[start..data_start],
+            &data[data_start..scan.end],
+            &mut output,
+        );
+        if let Some(fixed) = fixed_header_crc.filter(|crc| *crc != header_crc) {
+            fixes.push(Fix::HeaderCrc {
+                file,
+                found: header_crc,
+                fixed,
+            });
+        }
+        if scan.crc != Some(fixed) {
+            fixes.push(Fix::DataCrc {
+                file,
+                found: scan.crc,
+                fixed,
+            });
+        }
+
+        if scan.next >= data.len() {
+            break;
+        }
+        if !is_header(&data[scan.next..]) {
+            fixes.push(Fix::Truncated {
+                file,
+                position: scan.next,
+                length: data.len() - scan.next,
+            });
+            break;
+        }
+        start = scan.next;
+        file += 1;
+    }
+
+    Ok(Repair {
+        data: output,
+        fixes,
+    })
+}
+
+/// Write a file made of the header, with its data size and CRC set for the messages, the
+/// messages and the data CRC. Returns the header CRC, if the header has one, and the data CRC.
+pub(crate) fn write_file(
+    header: &[u8],
+    messages: &[u8],
+    output: &mut Vec<u8>,
+) -> (Option<u16>, u16) {
+    let mut header = header.to_vec();
+    header[4..8].copy_from_slice(&(messages.len() as u32).to_le_bytes());
+    let (header_crc, crc) = if header.len() >= 14 {
+        let header_crc = caculate_crc(&header[0..12]);
+        header[12..14].copy_from_slice(&header_crc.to_le_bytes());
+        (Some(header_crc), 0)
+    } else {
+        // a header without its own CRC is included in the data CRC
+        (None, caculate_crc(&header))
+    };
+    let crc = update_crc(crc, messages);
+    output.extend_from_slice(&header);
+    output.extend_from_slice(messages);
+    output.extend_from_slice(&crc.to_le_bytes());
+    (header_crc, crc)
+}
+
+/// Walk the messages of a file until the data CRC, the next chained file or the first incomplete
+/// message. The header `data_size` is only trusted if a valid CRC follows the messages there.
+fn scan_messages(data: &[u8], start: usize, declared_end: usize, mut crc: u16) -> Scan {
+    let mut definitions = HashMap::new();
+    let mut position = start;
+    loop {
+        let remaining = &data[position..];
+        let stored_crc = remaining
+            .get(0..2)
+            .map(|b| u16::from_le_bytes(b.try_into().unwrap()));
+        let crc_scan = Scan {
+            end: position,
+            crc: stored_crc,
+            next: position + 2,
+        };
+        if remaining.is_empty() || is_header(remaining) {
+            return Scan {
+                end: position,
+                crc: None,
+                next: position,
+            };
+        }
+        if (position == declared_end && stored_crc == Some(crc))
+            || remaining.len() == 2
+            || is_header(remaining.get(2..).unwrap_or(&[]))
+        {
+            return crc_scan;
+        }
+        match message_size(remaining, &mut definitions) {
+            Some(size) => {
+                crc = update_crc(crc, &remaining[0..size]);
+                position += size;
+            }
+            None => {
+                return Scan {
+                    end: position,
+                    crc: None,
+                    next: position,
+                }
+            }
+        }
+    }
+}
+
+/// Size of the complete message at the start of the input, None if the message is incomplete or
+/// uses an undefined local message number
+fn message_size(
+    input: &[u8],
+    definitions: &mut HashMap<u8, Arc<FitDefinitionMessage>>,
+) -> Option<usize> {
+    let header_byte = *input.first()?;
+    let size = if header_byte & 0x80 == 0x80 {
+        // compressed timestamp header
+        definitions
+            .get(&((header_byte >> 5) & 0x3))?
+            .data_message_size()
+    } else if header_byte & 0x40 == 0x40 {
+        // definitions don't need the developer field descriptions to be parsed
+        match parser::fit_message(input, definitions, &HashMap::new()) {
+            Ok((remaining, FitMessage::Definition(definition))) => {
+                definitions.insert(definition.local_message_number(), Arc::new(definition));
+                input.len() - remaining.len()
+            }
+            _ => return None,
+        }
+    } else {
+        definitions.get(&(header_byte & 0xF))?.data_message_size()
+    };
+    if size <= input.len() {
+        Some(size)
+    } else {
+        None
+    }
+}
+
+/// Check if the data starts with a FIT file header
+fn is_header(data: &[u8]) -> bool {
+    data.len() >= 12 && (data[0] == 12 || data[0] == 14) && &data[8..12] == b".FIT"
+}
+
+fn parse_err(err: nom::Err<nom::error::Error<&[u8]>>, position: usize) -> crate::Error {
+    match err {
+        nom::Err::Error(inner) | nom::Err::Failure(inner) => {
+            ErrorKind::ParseError(position, inner.code).into()
+        }
+        nom::Err::Incomplete(needed) => ErrorKind::UnexpectedEof(needed).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_bytes;
+
+    #[test]
+    fn valid_files_are_unchanged() {
+        let fixtures: [&[u8]; 2] = [
+            include_bytes!("../tests/fixtures/Activity.fit"),
+            include_bytes!("../tests/fixtures/sample_mulitple_header.fit"),
+        ];
+        for data in fixtures.iter() {
+            let repaired = repair(data).unwrap();
+            assert!(!repaired.is_modified(), "{:?}", repaired.fixes());
+            assert_eq!(repaired.data(), *data);
+        }
+    }
+
+    #[test]
+    fn broken_headers_and_crcs() {
+        let data = include_bytes!("../tests/fixtures/MonitoringFile.fit").to_vec();
+
+        // a zeroed header CRC, a lost data CRC and a partial message
+        let mut broken = data.clone();
+        broken.truncate(data.len() - 2);
+        broken.extend_from_slice(&[0x04, 0x01, 0x02]);
+        broken[12] = 0;
+        broken[13] = 0;
+        let repaired = repair(&broken).unwrap();
+        assert_eq!(repaired.data(), &data[..]);
+        assert_eq!(
+            repaired.fixes(),
+            &[
+                Fix::HeaderCrc {
+                    file: 0,
+                    found: 0,
+                    fixed: u16::from_le_bytes([data[12], data[13]])
+                },
+                Fix::DataCrc {
+                    file: 0,
+                    found: None,
+                    fixed: u16::from_le_bytes([data[data.len() - 2], data[data.len() - 1]])
+                },
+                Fix::Truncated {
+                    file: 0,
+                    position: data.len() - 2,
+                    length: 3
+                },
+            ]
+        );
+
+        // a data_size of 0 left behind by a device that never finished the file, the 12 byte
+        // header is part of the data CRC
+        let data = include_bytes!("../tests/fixtures/Activity.fit").to_vec();
+        let records = from_bytes(&data).unwrap();
+        let mut broken = data.clone();
+        broken[4..8].copy_from_slice(&[0, 0, 0, 0]);
+        let repaired = repair(&broken).unwrap();
+        assert_eq!(
+            repaired.fixes()[0],
+            Fix::DataSize {
+                file: 0,
+                found: 0,
+                fixed: data.len() as u32 - 14
+            }
+        );
+        assert_eq!(repaired.data(), &data[..]);
+        assert_eq!(from_bytes(repaired.data()).unwrap().len(), records.len());
+
+        assert!(repair(b"not a fit file").is_err());
+    }
+}
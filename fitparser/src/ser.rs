@@ -0,0 +1,418 @@
+//! Serialize raw FIT data messages into the binary FIT file format.
+//!
+//! The serializer works with the same raw, unscaled values the parser produces in a
+//! [`FitDataMessage`], the FIT base type of each field is taken from the [`Value`] variant.
+//! Definition messages are generated as needed and the file header and CRC values are computed
+//! when the file is finished.
+//! ```
+//! use fitparser::de::FitDataMessage;
+//! use fitparser::profile::MesgNum;
+//! use fitparser::ser::Serializer;
+//! use fitparser::Value;
+//! use std::collections::HashMap;
+//!
+//! let mut fields = HashMap::new();
+//! fields.insert(0, Value::Enum(4)); // type: activity
+//! fields.insert(1, Value::UInt16(255)); // manufacturer: development
+//! let mut serializer = Serializer::new();
+//! serializer.serialize(&FitDataMessage::new(MesgNum::FileId.as_u16(), fields))?;
+//! let bytes = serializer.finish();
+//!
+//! let records = fitparser::from_bytes(&bytes)?;
+//! assert_eq!(records[0].kind(), MesgNum::FileId);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::de::crc::{caculate_crc, update_crc};
+use crate::de::FitDataMessage;
+use crate::error::{ErrorKind, Result};
+use crate::profile::field_types::FitBaseType;
+use crate::profile::TimestampField;
+use crate::Value;
+use std::convert::TryInto;
+
+/// Size of the file header written, the 14 byte header includes a CRC
+const HEADER_SIZE: u8 = 14;
+
+/// FIT protocol version 2.0
+const PROTOCOL_VERSION: u8 = 0x20;
+
+/// Number of local message numbers available in a normal message header
+const LOCAL_MESSAGE_COUNT: usize = 16;
+
+/// Field number of the timestamp field shared by all messages
+pub(crate) const TIMESTAMP_FIELD: u8 = 253;
+
+/// Field layout of a data message, a new definition message is written whenever the layout of
+/// the next data message doesn't match the definition assigned to a local message number.
+#[derive(Clone, Debug, PartialEq)]
+struct Definition {
+    global_message_number: u16,
+    /// (field number, size, base type)
+    fields: Vec<(u8, u8, FitBaseType)>,
+    /// (field number, size, developer data index)
+    developer_fields: Vec<(u8, u8, u8)>,
+}
+
+/// Encodes data messages into a FIT file
+#[derive(Debug, Default)]
+pub struct Serializer {
+    /// Encoded definition and data messages
+    data: Vec<u8>,
+    /// Definition currently assigned to each local message number
+    definitions: Vec<Definition>,
+    /// Local message number to replace next once all of them are in use
+    next_local_message: usize,
+    /// Last timestamp written, used to expand compressed timestamp messages
+    last_timestamp: Option<u32>,
+}
+
+impl Serializer {
+    /// Create a serializer for a new file
+    pub fn new() -> Self {
+        Serializer::default()
+    }
+
+    /// Encode a data message, along with a definition message if required.
+    ///
+    /// Fields are written in field number order using little endian byte order. Messages that
+    /// were parsed with a compressed timestamp header are written with a normal header and the
+    /// full `timestamp` field restored from the last timestamp written.
+    pub fn serialize(&mut self, message: &FitDataMessage) -> Result<()> {
+        let mut fields: Vec<(u8, Value)> = message
+            .fields()
+            .iter()
+            .filter(|(_, v)| **v != Value::Invalid)
+            .map(|(k, v)| (*k, v.clone()))
+            .collect();
+        if let Some(offset) = message.time_offset() {
+            if !fields.iter().any(|(k, _)| *k == TIMESTAMP_FIELD) {
+                let last = self.last_timestamp.ok_or_else(|| {
+                    value_error("compressed timestamp message without a preceding timestamp")
+                })?;
+                let timestamp = last + ((offset as u32).wrapping_sub(last) & 0x1F);
+                fields.push((TIMESTAMP_FIELD, Value::UInt32(timestamp)));
+            }
+        }
+        fields.sort_by_key(|(k, _)| *k);
+        let mut developer_fields: Vec<((u8, u8), Value)> = message
+            .developer_fields()
+            .iter()
+            .filter(|(_, v)| **v != Value::Invalid)
+            .map(|(k, v)| (*k, v.clone()))
+            .collect();
+        developer_fields.sort_by_key(|(k, _)| *k);
+
+        let mut content = Vec::new();
+        let mut definition = Definition {
+            global_message_number: message.global_message_number(),
+            fields: Vec::with_capacity(fields.len()),
+            developer_fields: Vec::with_capacity(developer_fields.len()),
+        };
+        for (number, value) in &fields {
+            let (size, base_type) = encode_value(value, &mut content)?;
+            definition.fields.push((*number, size, base_type));
+            if *number == TIMESTAMP_FIELD {
+                if let Value::UInt32(val) = value {
+                    self.last_timestamp = Some(*val);
+                } else if let Value::Timestamp(val) = value {
+                    self.last_timestamp = Some(TimestampField::from(*val).as_i64() as u32);
+                }
+            }
+        }
+        for ((developer_data_index, number), value) in &developer_fields {
+            let (size, _) = encode_value(value, &mut content)?;
+            definition
+                .developer_fields
+                .push((*number, size, *developer_data_index));
+        }
+
+        let local_message_number = self.local_message_number(definition);
+        self.data.push(local_message_number);
+        self.data.extend(content);
+        Ok(())
+    }
+
+    /// Return the local message number for the definition, writing a definition message if it
+    /// isn't currently assigned to one.
+    fn local_message_number(&mut self, definition: Definition) -> u8 {
+        if let Some(idx) = self.definitions.iter().position(|d| *d == definition) {
+            return idx as u8;
+        }
+        let idx = if self.definitions.len() < LOCAL_MESSAGE_COUNT {
+            self.definitions.push(definition);
+            self.definitions.len() - 1
+        } else {
+            let idx = self.next_local_message;
+            self.next_local_message = (idx + 1) % LOCAL_MESSAGE_COUNT;
+            self.definitions[idx] = definition;
+            idx
+        };
+
+        let definition = &self.definitions[idx];
+        let mut header = 0x40 | idx as u8;
+        if !definition.developer_fields.is_empty() {
+            header |= 0x20;
+        }
+        self.data.push(header);
+        self.data.push(0); // reserved
+        self.data.push(0); // little endian
+        self.data
+            .extend(definition.global_message_number.to_le_bytes());
+        self.data.push(definition.fields.len() as u8);
+        for (number, size, base_type) in &definition.fields {
+            self.data.extend([*number, *size, base_type.as_u8()]);
+        }
+        if !definition.developer_fields.is_empty() {
+            self.data.push(definition.developer_fields.len() as u8);
+            for (number, size, developer_data_index) in &definition.developer_fields {
+                self.data.extend([*number, *size, *developer_data_index]);
+            }
+        }
+
+        idx as u8
+    }
+
+    /// Write the file header and CRC around the encoded messages and return the file contents
+    pub fn finish(self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(HEADER_SIZE as usize + self.data.len() + 2);
+        output.push(HEADER_SIZE);
+        output.push(PROTOCOL_VERSION);
+        output.extend(profile_version().to_le_bytes());
+        output.extend((self.data.len() as u32).to_le_bytes());
+        output.extend(b".FIT");
+        let header_crc = caculate_crc(&output);
+        output.extend(header_crc.to_le_bytes());
+
+        let crc = update_crc(0, &self.data);
+        output.extend(self.data);
+        output.extend(crc.to_le_bytes());
+        output
+    }
+}
+
+/// Serialize the data messages into a complete FIT file
+pub fn to_bytes(messages: &[FitDataMessage]) -> Result<Vec<u8>> {
+    let mut serializer = Serializer::new();
+    for message in messages {
+        serializer.serialize(message)?;
+    }
+    Ok(serializer.finish())
+}
+
+/// Encode the packaged profile version as major * 1000 + minor
+fn profile_version() -> u16 {
+    let mut parts = crate::profile::VERSION.split('.');
+    let major: u16 = parts.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+    let minor: u16 = parts.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+    major * 1000 + minor
+}
+
+fn value_error(message: &str) -> crate::Error {
+    ErrorKind::ValueError(message.to_string()).into()
+}
+
+/// Append the little endian bytes of the value and return the field size and base type.
+/// Timestamps are stored as seconds since the UTC FIT reference date.
+fn encode_value(value: &Value, output: &mut Vec<u8>) -> Result<(u8, FitBaseType)> {
+    let start = output.len();
+    let base_type = match value {
+        Value::Enum(val) => {
+            output.push(*val);
+            FitBaseType::Enum
+        }
+        Value::Byte(val) => {
+            output.push(*val);
+            FitBaseType::Byte
+        }
+        Value::SInt8(val) => {
+            output.push(*val as u8);
+            FitBaseType::Sint8
+        }
+        Value::UInt8(val) => {
+            output.push(*val);
+            FitBaseType::Uint8
+        }
+        Value::UInt8z(val) => {
+            output.push(*val);
+            FitBaseType::Uint8z
+        }
+        Value::SInt16(val) => {
+            output.extend(val.to_le_bytes());
+            FitBaseType::Sint16
+        }
+        Value::UInt16(val) => {
+            output.extend(val.to_le_bytes());
+            FitBaseType::Uint16
+        }
+        Value::UInt16z(val) => {
+            output.extend(val.to_le_bytes());
+            FitBaseType::Uint16z
+        }
+        Value::SInt32(val) => {
+            output.extend(val.to_le_bytes());
+            FitBaseType::Sint32
+        }
+        Value::UInt32(val) => {
+            output.extend(val.to_le_bytes());
+            FitBaseType::Uint32
+        }
+        Value::UInt32z(val) => {
+            output.extend(val.to_le_bytes());
+            FitBaseType::Uint32z
+        }
+        Value::SInt64(val) => {
+            output.extend(val.to_le_bytes());
+            FitBaseType::Sint64
+        }
+        Value::UInt64(val) => {
+            output.extend(val.to_le_bytes());
+            FitBaseType::Uint64
+        }
+        Value::UInt64z(val) => {
+            output.extend(val.to_le_bytes());
+            FitBaseType::Uint64z
+        }
+        Value::Float32(val) => {
+            output.extend(val.to_le_bytes());
+            FitBaseType::Float32
+        }
+        Value::Float64(val) => {
+            output.extend(val.to_le_bytes());
+            FitBaseType::Float64
+        }
+        Value::Timestamp(val) => {
+            let seconds: u32 = TimestampField::from(*val)
+                .as_i64()
+                .try_into()
+                .map_err(|_| value_error("timestamp precedes the FIT reference date"))?;
+            output.extend(seconds.to_le_bytes());
+            FitBaseType::Uint32
+        }
+        Value::String(val) => {
+            output.extend(val.as_bytes());
+            output.push(0);
+            FitBaseType::String
+        }
+        Value::Array(vals) => {
+            // the type is taken from the valid values, invalid elements are written using the
+            // invalid value of that type
+            let mut scratch = Vec::new();
+            let mut base_type = None;
+            for val in vals.iter().filter(|v| **v != Value::Invalid) {
+                if matches!(val, Value::Array(_) | Value::String(_)) {
+                    return Err(value_error("arrays may only contain numeric values"));
+                }
+                let (_, val_type) = encode_value(val, &mut scratch)?;
+                if base_type.is_some_and(|t| t != val_type) {
+                    return Err(value_error("array values must share the same type"));
+                }
+                base_type = Some(val_type);
+            }
+            let base_type =
+                base_type.ok_or_else(|| value_error("arrays need at least one valid value"))?;
+            for val in vals {
+                match val {
+                    Value::Invalid => encode_value(&invalid_value(base_type), output)?,
+                    val => encode_value(val, output)?,
+                };
+            }
+            base_type
+        }
+        Value::Invalid => return Err(value_error("invalid values can't be serialized")),
+    };
+
+    let size = output.len() - start;
+    if size > u8::MAX as usize {
+        return Err(value_error("field values are limited to 255 bytes"));
+    }
+    Ok((size as u8, base_type))
+}
+
+/// Value marking an invalid element of a numeric base type
+fn invalid_value(base_type: FitBaseType) -> Value {
+    match base_type {
+        FitBaseType::Enum => Value::Enum(0xFF),
+        FitBaseType::Byte => Value::Byte(0xFF),
+        FitBaseType::Sint8 => Value::SInt8(0x7F),
+        FitBaseType::Uint8z => Value::UInt8z(0),
+        FitBaseType::Sint16 => Value::SInt16(0x7FFF),
+        FitBaseType::Uint16 => Value::UInt16(0xFFFF),
+        FitBaseType::Uint16z => Value::UInt16z(0),
+        FitBaseType::Sint32 => Value::SInt32(0x7FFF_FFFF),
+        FitBaseType::Uint32 => Value::UInt32(0xFFFF_FFFF),
+        FitBaseType::Uint32z => Value::UInt32z(0),
+        FitBaseType::Float32 => Value::Float32(f32::from_bits(0xFFFF_FFFF)),
+        FitBaseType::Float64 => Value::Float64(f64::from_bits(0xFFFF_FFFF_FFFF_FFFF)),
+        FitBaseType::Sint64 => Value::SInt64(0x7FFF_FFFF_FFFF_FFFF),
+        FitBaseType::Uint64 => Value::UInt64(0xFFFF_FFFF_FFFF_FFFF),
+        FitBaseType::Uint64z => Value::UInt64z(0),
+        _ => Value::UInt8(0xFF),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::de::{FitObject, FitStreamProcessor};
+    use crate::from_bytes;
+    use crate::profile::MesgNum;
+    use std::collections::HashMap;
+
+    /// Parse the raw data messages of a file
+    fn data_messages(data: &[u8]) -> Vec<FitDataMessage> {
+        let mut processor = FitStreamProcessor::new();
+        let mut buffer = data;
+        let mut messages = Vec::new();
+        while !buffer.is_empty() {
+            let (remaining, obj) = processor.deserialize_next(buffer).unwrap();
+            if let FitObject::DataMessage(message) = obj {
+                messages.push(message);
+            }
+            buffer = remaining;
+        }
+        messages
+    }
+
+    #[test]
+    fn reencode_activity() {
+        let data = include_bytes!("../tests/fixtures/Activity.fit").to_vec();
+        let messages = data_messages(&data);
+        let encoded = to_bytes(&messages).unwrap();
+        let reencoded = to_bytes(&data_messages(&encoded)).unwrap();
+        assert_eq!(encoded, reencoded);
+
+        let original = from_bytes(&data).unwrap();
+        let decoded = from_bytes(&encoded).unwrap();
+        assert_eq!(original.len(), decoded.len());
+        for (orig, dec) in original.iter().zip(&decoded) {
+            assert_eq!(orig.kind(), dec.kind());
+            assert_eq!(
+                format!("{:?}", orig.fields()),
+                format!("{:?}", dec.fields())
+            );
+        }
+    }
+
+    #[test]
+    fn definitions_are_reused() {
+        let mut serializer = Serializer::new();
+        for idx in 0..20u16 {
+            let mut fields = HashMap::new();
+            fields.insert(254, Value::UInt16(idx));
+            // alternate between layouts, string lengths also change the layout
+            if idx % 2 == 0 {
+                fields.insert(0, Value::String(format!("step {}", idx)));
+            }
+            serializer
+                .serialize(&FitDataMessage::new(MesgNum::WorkoutStep.as_u16(), fields))
+                .unwrap();
+        }
+        let bytes = serializer.finish();
+        let records = from_bytes(&bytes).unwrap();
+        assert_eq!(records.len(), 20);
+        assert_eq!(
+            records[4].field("wkt_step_name").unwrap().value(),
+            &Value::String("step 4".to_string())
+        );
+    }
+}
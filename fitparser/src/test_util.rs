@@ -0,0 +1,32 @@
+//! Builders for decoded records used by the unit tests
+use crate::profile::{MesgNum, TimestampField};
+use crate::{FitDataField, FitDataRecord, Value};
+
+/// Create a field without units
+pub(crate) fn field(name: &str, number: u8, value: Value) -> FitDataField {
+    FitDataField::new(name.to_string(), number, None, value, "")
+}
+
+/// Create a timestamp field the given number of seconds after the FIT epoch
+pub(crate) fn timestamp_field(offset: i64) -> FitDataField {
+    field("timestamp", 253, Value::from(TimestampField::Utc(offset)))
+}
+
+/// Create a message from field names and values, the field numbers are not set
+pub(crate) fn message(kind: MesgNum, fields: Vec<(&str, Value)>) -> FitDataRecord {
+    let mut record = FitDataRecord::new(kind);
+    for (name, value) in fields {
+        record.push(field(name, 0, value));
+    }
+    record
+}
+
+/// Create a message with a timestamp the given number of seconds after the FIT epoch
+pub(crate) fn message_at(kind: MesgNum, offset: i64, fields: Vec<(&str, Value)>) -> FitDataRecord {
+    let mut record = FitDataRecord::new(kind);
+    record.push(timestamp_field(offset));
+    for (name, value) in fields {
+        record.push(field(name, 0, value));
+    }
+    record
+}
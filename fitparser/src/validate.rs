@@ -0,0 +1,292 @@
+//! Check decoded FIT files against the structure the FIT SDK requires for each file type.
+//!
+//! Every FIT file has to start with a `FileId` message whose `type` field determines which
+//! other messages must be present, e.g. an activity file needs `Activity`, `Session`, `Lap` and
+//! `Record` messages. Chained files are validated separately, each `FileId` message starts a
+//! new file.
+//! ```
+//! use fitparser::validate::validate;
+//! use std::fs::File;
+//!
+//! let mut fp = File::open("tests/fixtures/Activity.fit")?;
+//! let records = fitparser::from_reader(&mut fp)?;
+//! for file in validate(&records) {
+//!     for violation in file.violations() {
+//!         println!("{:?} file: {}", file.file_type(), violation);
+//!     }
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::analysis::enum_value;
+use crate::profile::field_types::File;
+use crate::profile::MesgNum;
+use crate::FitDataRecord;
+use std::fmt;
+
+/// A structural problem found in a file, positions are indices into the validated records
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+    /// The file doesn't start with a `FileId` message
+    MissingFileId,
+    /// The `FileId` message has no `type` field so the file type is unknown
+    MissingFileType {
+        /// Position of the `FileId` message
+        position: usize,
+    },
+    /// None of the messages is present, a single message if it is required by itself
+    MissingMessage {
+        /// Messages of which at least one is required
+        required: Vec<MesgNum>,
+    },
+    /// A message appears before the message it has to follow
+    OutOfOrder {
+        /// Position of the misplaced message
+        position: usize,
+        /// Kind of the misplaced message
+        message: MesgNum,
+        /// Message that has to appear first
+        expected_after: MesgNum,
+    },
+}
+
+impl Violation {
+    /// Position of the offending message if the violation relates to a single message
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            Violation::MissingFileId | Violation::MissingMessage { .. } => None,
+            Violation::MissingFileType { position } => Some(*position),
+            Violation::OutOfOrder { position, .. } => Some(*position),
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::MissingFileId => write!(f, "file doesn't start with a file_id message"),
+            Violation::MissingFileType { position } => {
+                write!(f, "file_id message at {} has no type", position)
+            }
+            Violation::MissingMessage { required } => {
+                let names: Vec<String> = required.iter().map(|m| m.to_string()).collect();
+                write!(f, "missing required {} message", names.join(" or "))
+            }
+            Violation::OutOfOrder {
+                position,
+                message,
+                expected_after,
+            } => write!(
+                f,
+                "{} message at {} appears before the first {} message",
+                message, position, expected_after
+            ),
+        }
+    }
+}
+
+/// Result of validating a single file
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileValidation {
+    position: usize,
+    file_type: Option<File>,
+    violations: Vec<Violation>,
+}
+
+impl FileValidation {
+    /// Position of the first message of the file
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// File type from the `FileId` message
+    pub fn file_type(&self) -> Option<File> {
+        self.file_type
+    }
+
+    /// Problems found in the file
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    /// Check if the file has no violations
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Validate the records, returning one result for each file in the record stream
+pub fn validate(records: &[FitDataRecord]) -> Vec<FileValidation> {
+    let mut starts: Vec<usize> = records
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| r.kind() == MesgNum::FileId)
+        .map(|(idx, _)| idx)
+        .collect();
+    if starts.first() != Some(&0) {
+        starts.insert(0, 0);
+    }
+
+    starts
+        .iter()
+        .enumerate()
+        .map(|(idx, start)| {
+            let end = starts.get(idx + 1).copied().unwrap_or(records.len());
+            validate_file(&records[*start..end], *start)
+        })
+        .collect()
+}
+
+fn validate_file(records: &[FitDataRecord], offset: usize) -> FileValidation {
+    let mut violations = Vec::new();
+    let file_type = match records.first() {
+        Some(file_id) if file_id.kind() == MesgNum::FileId => {
+            let file_type = enum_value::<File>(file_id, "type");
+            if file_type.is_none() {
+                violations.push(Violation::MissingFileType { position: offset });
+            }
+            file_type
+        }
+        _ => {
+            violations.push(Violation::MissingFileId);
+            None
+        }
+    };
+
+    if let Some(file_type) = file_type {
+        for group in required_messages(file_type) {
+            if !records.iter().any(|r| group.contains(&r.kind())) {
+                violations.push(Violation::MissingMessage {
+                    required: group.to_vec(),
+                });
+            }
+        }
+        for (first, then) in message_order(file_type) {
+            let first_pos = records.iter().position(|r| r.kind() == *first);
+            let misplaced = records
+                .iter()
+                .position(|r| r.kind() == *then)
+                .filter(|pos| first_pos.is_some_and(|f| *pos < f));
+            if let Some(pos) = misplaced {
+                violations.push(Violation::OutOfOrder {
+                    position: offset + pos,
+                    message: *then,
+                    expected_after: *first,
+                });
+            }
+        }
+    }
+
+    FileValidation {
+        position: offset,
+        file_type,
+        violations,
+    }
+}
+
+/// Messages each file type requires in addition to the `FileId`, at least one message of each
+/// group has to be present
+fn required_messages(file_type: File) -> &'static [&'static [MesgNum]] {
+    match file_type {
+        File::Activity => &[
+            &[MesgNum::Activity],
+            &[MesgNum::Session],
+            &[MesgNum::Lap],
+            &[MesgNum::Record],
+        ],
+        File::ActivitySummary => &[&[MesgNum::Activity], &[MesgNum::Session], &[MesgNum::Lap]],
+        File::Workout => &[&[MesgNum::Workout], &[MesgNum::WorkoutStep]],
+        File::Course => &[&[MesgNum::Course], &[MesgNum::Lap], &[MesgNum::Record]],
+        File::Settings => &[&[
+            MesgNum::UserProfile,
+            MesgNum::HrmProfile,
+            MesgNum::SdmProfile,
+            MesgNum::BikeProfile,
+            MesgNum::DeviceSettings,
+        ]],
+        File::Sport => &[&[MesgNum::ZonesTarget, MesgNum::Sport]],
+        File::Schedules => &[&[MesgNum::Schedule]],
+        File::Weight => &[&[MesgNum::WeightScale]],
+        File::Totals => &[&[MesgNum::Totals]],
+        File::Goals => &[&[MesgNum::Goal]],
+        File::BloodPressure => &[&[MesgNum::BloodPressure]],
+        File::MonitoringA | File::MonitoringB => {
+            &[&[MesgNum::MonitoringInfo], &[MesgNum::Monitoring]]
+        }
+        File::MonitoringDaily => &[&[MesgNum::Monitoring]],
+        File::Segment => &[&[MesgNum::SegmentId], &[MesgNum::SegmentPoint]],
+        File::SegmentList => &[&[MesgNum::SegmentFile]],
+        _ => &[],
+    }
+}
+
+/// (first, then) pairs where no `then` message may appear before the first `first` message
+fn message_order(file_type: File) -> &'static [(MesgNum, MesgNum)] {
+    match file_type {
+        File::Workout => &[(MesgNum::Workout, MesgNum::WorkoutStep)],
+        File::Course => &[
+            (MesgNum::Course, MesgNum::Lap),
+            (MesgNum::Course, MesgNum::Record),
+        ],
+        File::MonitoringA | File::MonitoringB => &[(MesgNum::MonitoringInfo, MesgNum::Monitoring)],
+        File::Segment => &[(MesgNum::SegmentId, MesgNum::SegmentPoint)],
+        _ => &[],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_bytes;
+
+    #[test]
+    fn valid_fixtures() {
+        let fixtures: [&[u8]; 5] = [
+            include_bytes!("../tests/fixtures/Activity.fit"),
+            include_bytes!("../tests/fixtures/MonitoringFile.fit"),
+            include_bytes!("../tests/fixtures/Settings.fit"),
+            include_bytes!("../tests/fixtures/WeightScaleSingleUser.fit"),
+            include_bytes!("../tests/fixtures/WorkoutRepeatSteps.fit"),
+        ];
+        for data in fixtures.iter() {
+            let files = validate(&from_bytes(data).unwrap());
+            assert_eq!(files.len(), 1);
+            assert!(files[0].is_valid(), "{:?}", files[0]);
+        }
+    }
+
+    #[test]
+    fn missing_and_misplaced_messages() {
+        let data = include_bytes!("../tests/fixtures/DeveloperData.fit");
+        let files = validate(&from_bytes(data).unwrap());
+        assert_eq!(files[0].file_type(), Some(File::Activity));
+        assert_eq!(
+            files[0].violations(),
+            &[
+                Violation::MissingMessage {
+                    required: vec![MesgNum::Activity]
+                },
+                Violation::MissingMessage {
+                    required: vec![MesgNum::Session]
+                },
+                Violation::MissingMessage {
+                    required: vec![MesgNum::Lap]
+                },
+            ]
+        );
+
+        let data = include_bytes!("../tests/fixtures/WorkoutRepeatSteps.fit");
+        let mut records = from_bytes(data).unwrap();
+        records.swap(1, 2);
+        let files = validate(&records[1..]);
+        assert_eq!(files[0].violations()[0], Violation::MissingFileId);
+        let files = validate(&records);
+        assert_eq!(
+            files[0].violations(),
+            &[Violation::OutOfOrder {
+                position: 1,
+                message: MesgNum::WorkoutStep,
+                expected_after: MesgNum::Workout
+            }]
+        );
+    }
+}
@@ -0,0 +1,1466 @@
+//! A structured model of a workout built from the `Workout` and `WorkoutStep` messages.
+//!
+//! Workout files store the steps as a flat list where the meaning of the duration and target
+//! values depends on the `duration_type` and `target_type` of each step, and repeats are encoded
+//! as an extra step referencing the `message_index` of the first step to repeat. [`Workout`]
+//! turns that list into a tree of steps and repeat blocks with typed durations and targets, and
+//! converts it back into records. [`WorkoutFile`] encodes a workout into a FIT workout file.
+//! ```
+//! use fitparser::workout::{Workout, WorkoutNode};
+//! use std::fs::File;
+//!
+//! let mut fp = File::open("tests/fixtures/WorkoutRepeatSteps.fit")?;
+//! let records = fitparser::from_reader(&mut fp)?;
+//! let workout = Workout::from_records(&records)?;
+//! for node in workout.steps() {
+//!     match node {
+//!         WorkoutNode::Step(step) => println!("{:?} {:?}", step.duration(), step.target()),
+//!         WorkoutNode::Repeat(block) => println!("repeat {:?}", block.condition()),
+//!     }
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::analysis::enum_value;
+use crate::de::{FieldMap, FitDataMessage};
+use crate::error::{ErrorKind, Result};
+use crate::profile::field_types::{
+    File, Intensity, Manufacturer, Sport, SubSport, SwimStroke, WktStepDuration, WktStepTarget,
+};
+use crate::profile::MesgNum;
+use crate::ser::Serializer;
+use crate::{FitDataField, FitDataRecord, Value};
+use chrono::{DateTime, Local};
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+/// Names the `duration_value` field (number 2) is decoded as
+const DURATION_VALUE_NAMES: [&str; 8] = [
+    "duration_value",
+    "duration_time",
+    "duration_distance",
+    "duration_hr",
+    "duration_calories",
+    "duration_step",
+    "duration_power",
+    "duration_reps",
+];
+
+/// Names the `target_value` field (number 4) is decoded as
+const TARGET_VALUE_NAMES: [&str; 12] = [
+    "target_value",
+    "target_speed_zone",
+    "target_hr_zone",
+    "target_cadence_zone",
+    "target_power_zone",
+    "repeat_steps",
+    "repeat_time",
+    "repeat_distance",
+    "repeat_calories",
+    "repeat_hr",
+    "repeat_power",
+    "target_stroke_type",
+];
+
+/// Names the `custom_target_value_low` field (number 5) is decoded as
+const CUSTOM_TARGET_LOW_NAMES: [&str; 5] = [
+    "custom_target_value_low",
+    "custom_target_speed_low",
+    "custom_target_heart_rate_low",
+    "custom_target_cadence_low",
+    "custom_target_power_low",
+];
+
+/// Names the `custom_target_value_high` field (number 6) is decoded as
+const CUSTOM_TARGET_HIGH_NAMES: [&str; 5] = [
+    "custom_target_value_high",
+    "custom_target_speed_high",
+    "custom_target_heart_rate_high",
+    "custom_target_cadence_high",
+    "custom_target_power_high",
+];
+
+/// A heart rate value, stored in the file as a percentage of max heart rate below 100 and as
+/// bpm + 100 above it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeartRate {
+    /// Percentage of the maximum heart rate
+    PercentMax(u32),
+    /// Beats per minute
+    Bpm(u32),
+}
+
+impl HeartRate {
+    fn from_raw(value: u32) -> Self {
+        if value > 100 {
+            HeartRate::Bpm(value - 100)
+        } else {
+            HeartRate::PercentMax(value)
+        }
+    }
+
+    fn to_raw(self) -> u32 {
+        match self {
+            HeartRate::PercentMax(val) => val,
+            HeartRate::Bpm(val) => val + 100,
+        }
+    }
+}
+
+/// A power value, stored in the file as a percentage of FTP below 1000 and as watts + 1000
+/// above it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Power {
+    /// Percentage of the functional threshold power
+    PercentFtp(u32),
+    /// Watts
+    Watts(u32),
+}
+
+impl Power {
+    fn from_raw(value: u32) -> Self {
+        if value > 1000 {
+            Power::Watts(value - 1000)
+        } else {
+            Power::PercentFtp(value)
+        }
+    }
+
+    fn to_raw(self) -> u32 {
+        match self {
+            Power::PercentFtp(val) => val,
+            Power::Watts(val) => val + 1000,
+        }
+    }
+}
+
+/// A target given either as one of the user's zones or a custom range
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TargetRange<T> {
+    /// Zone number as configured on the device
+    Zone(u32),
+    /// Custom low and high limits
+    Custom {
+        /// Lower limit
+        low: T,
+        /// Upper limit
+        high: T,
+    },
+}
+
+/// Condition that ends a workout step
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StepDuration {
+    /// The step lasts until the lap button is pressed
+    Open,
+    /// Time in seconds
+    Time(f64),
+    /// Distance in meters
+    Distance(f64),
+    /// Until the heart rate drops below the value
+    HeartRateLessThan(HeartRate),
+    /// Until the heart rate rises above the value
+    HeartRateGreaterThan(HeartRate),
+    /// Calories burned
+    Calories(u32),
+    /// Until the power drops below the value
+    PowerLessThan(Power),
+    /// Until the power rises above the value
+    PowerGreaterThan(Power),
+    /// Number of repetitions of an exercise
+    Reps(u32),
+    /// Any other duration type with its raw value
+    Other {
+        /// Duration type of the step
+        duration_type: WktStepDuration,
+        /// Raw duration value
+        value: Option<u32>,
+    },
+}
+
+/// Intensity target of a workout step
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StepTarget {
+    /// No target
+    Open,
+    /// Heart rate target
+    HeartRate(TargetRange<HeartRate>),
+    /// Power target
+    Power(TargetRange<Power>),
+    /// Speed target in m/s
+    Speed(TargetRange<f64>),
+    /// Cadence target in rpm
+    Cadence(TargetRange<u32>),
+    /// Swim stroke to use, None means any stroke
+    SwimStroke(Option<SwimStroke>),
+    /// Any other target type with its raw values
+    Other {
+        /// Target type of the step
+        target_type: WktStepTarget,
+        /// Raw target value
+        value: Option<u32>,
+        /// Raw custom lower limit
+        low: Option<u32>,
+        /// Raw custom upper limit
+        high: Option<u32>,
+    },
+}
+
+/// Condition that ends a repeat block
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RepeatCondition {
+    /// Number of times the steps are performed
+    Count(u32),
+    /// Until the total time in seconds has elapsed
+    UntilTime(f64),
+    /// Until the total distance in meters has been covered
+    UntilDistance(f64),
+    /// Until the calories have been burned
+    UntilCalories(u32),
+    /// Until the heart rate drops below the value
+    UntilHeartRateLessThan(HeartRate),
+    /// Until the heart rate rises above the value
+    UntilHeartRateGreaterThan(HeartRate),
+    /// Until the power drops below the value
+    UntilPowerLessThan(Power),
+    /// Until the power rises above the value
+    UntilPowerGreaterThan(Power),
+    /// Any other repeat type with its raw value
+    Other {
+        /// Duration type of the repeat step
+        duration_type: WktStepDuration,
+        /// Raw repeat value
+        value: Option<u32>,
+    },
+}
+
+/// A single step of a workout
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkoutStep {
+    name: Option<String>,
+    notes: Option<String>,
+    intensity: Option<Intensity>,
+    duration: StepDuration,
+    target: StepTarget,
+}
+
+impl WorkoutStep {
+    /// Create a step with the given duration and target
+    pub fn new(duration: StepDuration, target: StepTarget) -> Self {
+        WorkoutStep {
+            name: None,
+            notes: None,
+            intensity: None,
+            duration,
+            target,
+        }
+    }
+
+    /// Name of the step
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Set the name of the step
+    pub fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    /// Notes displayed for the step
+    pub fn notes(&self) -> Option<&str> {
+        self.notes.as_deref()
+    }
+
+    /// Set the notes of the step
+    pub fn set_notes(&mut self, notes: &str) {
+        self.notes = Some(notes.to_string());
+    }
+
+    /// Intensity of the step, e.g. warmup or active
+    pub fn intensity(&self) -> Option<Intensity> {
+        self.intensity
+    }
+
+    /// Set the intensity of the step
+    pub fn set_intensity(&mut self, intensity: Intensity) {
+        self.intensity = Some(intensity);
+    }
+
+    /// Condition ending the step
+    pub fn duration(&self) -> StepDuration {
+        self.duration
+    }
+
+    /// Intensity target of the step
+    pub fn target(&self) -> StepTarget {
+        self.target
+    }
+
+    fn from_record(record: &FitDataRecord, duration_type: WktStepDuration) -> Self {
+        let duration = match duration_type {
+            WktStepDuration::Open => StepDuration::Open,
+            WktStepDuration::Time => StepDuration::Time(
+                value_f64(record, &DURATION_VALUE_NAMES, "duration_time", 1000.0).unwrap_or(0.0),
+            ),
+            WktStepDuration::Distance => StepDuration::Distance(
+                value_f64(record, &DURATION_VALUE_NAMES, "duration_distance", 100.0).unwrap_or(0.0),
+            ),
+            WktStepDuration::HrLessThan => StepDuration::HeartRateLessThan(HeartRate::from_raw(
+                value_u32(record, &DURATION_VALUE_NAMES).unwrap_or(0),
+            )),
+            WktStepDuration::HrGreaterThan => StepDuration::HeartRateGreaterThan(
+                HeartRate::from_raw(value_u32(record, &DURATION_VALUE_NAMES).unwrap_or(0)),
+            ),
+            WktStepDuration::Calories => {
+                StepDuration::Calories(value_u32(record, &DURATION_VALUE_NAMES).unwrap_or(0))
+            }
+            WktStepDuration::PowerLessThan => StepDuration::PowerLessThan(Power::from_raw(
+                value_u32(record, &DURATION_VALUE_NAMES).unwrap_or(0),
+            )),
+            WktStepDuration::PowerGreaterThan => StepDuration::PowerGreaterThan(Power::from_raw(
+                value_u32(record, &DURATION_VALUE_NAMES).unwrap_or(0),
+            )),
+            WktStepDuration::Reps => {
+                StepDuration::Reps(value_u32(record, &DURATION_VALUE_NAMES).unwrap_or(0))
+            }
+            duration_type => StepDuration::Other {
+                duration_type,
+                value: value_u32(record, &DURATION_VALUE_NAMES),
+            },
+        };
+
+        let target_type =
+            enum_value::<WktStepTarget>(record, "target_type").unwrap_or(WktStepTarget::Open);
+        let zone = value_u32(record, &TARGET_VALUE_NAMES).unwrap_or(0);
+        let low = value_u32(record, &CUSTOM_TARGET_LOW_NAMES);
+        let high = value_u32(record, &CUSTOM_TARGET_HIGH_NAMES);
+        let (raw_low, raw_high) = (low.unwrap_or(0), high.unwrap_or(0));
+        let target = match target_type {
+            WktStepTarget::Open => StepTarget::Open,
+            WktStepTarget::HeartRate => StepTarget::HeartRate(target_range(
+                zone,
+                HeartRate::from_raw(raw_low),
+                HeartRate::from_raw(raw_high),
+            )),
+            WktStepTarget::Power => StepTarget::Power(target_range(
+                zone,
+                Power::from_raw(raw_low),
+                Power::from_raw(raw_high),
+            )),
+            WktStepTarget::Cadence => StepTarget::Cadence(target_range(zone, raw_low, raw_high)),
+            WktStepTarget::Speed => StepTarget::Speed(target_range(
+                zone,
+                value_f64(
+                    record,
+                    &CUSTOM_TARGET_LOW_NAMES,
+                    "custom_target_speed_low",
+                    1000.0,
+                )
+                .unwrap_or(0.0),
+                value_f64(
+                    record,
+                    &CUSTOM_TARGET_HIGH_NAMES,
+                    "custom_target_speed_high",
+                    1000.0,
+                )
+                .unwrap_or(0.0),
+            )),
+            WktStepTarget::SwimStroke => {
+                StepTarget::SwimStroke(enum_value::<SwimStroke>(record, "target_stroke_type"))
+            }
+            target_type => StepTarget::Other {
+                target_type,
+                value: value_u32(record, &TARGET_VALUE_NAMES),
+                low,
+                high,
+            },
+        };
+
+        WorkoutStep {
+            name: string_value(record, "wkt_step_name"),
+            notes: string_value(record, "notes"),
+            intensity: enum_value::<Intensity>(record, "intensity"),
+            duration,
+            target,
+        }
+    }
+
+    fn push_fields(&self, record: &mut FitDataRecord) {
+        if let Some(name) = &self.name {
+            record.push(field("wkt_step_name", 0, Value::String(name.clone()), ""));
+        }
+        let (duration_type, duration_field) = match self.duration {
+            StepDuration::Open => (WktStepDuration::Open, None),
+            StepDuration::Time(val) => (
+                WktStepDuration::Time,
+                Some(field("duration_time", 2, Value::Float64(val), "s")),
+            ),
+            StepDuration::Distance(val) => (
+                WktStepDuration::Distance,
+                Some(field("duration_distance", 2, Value::Float64(val), "m")),
+            ),
+            StepDuration::HeartRateLessThan(val) => (
+                WktStepDuration::HrLessThan,
+                Some(field(
+                    "duration_hr",
+                    2,
+                    Value::UInt32(val.to_raw()),
+                    "% or bpm",
+                )),
+            ),
+            StepDuration::HeartRateGreaterThan(val) => (
+                WktStepDuration::HrGreaterThan,
+                Some(field(
+                    "duration_hr",
+                    2,
+                    Value::UInt32(val.to_raw()),
+                    "% or bpm",
+                )),
+            ),
+            StepDuration::Calories(val) => (
+                WktStepDuration::Calories,
+                Some(field(
+                    "duration_calories",
+                    2,
+                    Value::UInt32(val),
+                    "calories",
+                )),
+            ),
+            StepDuration::PowerLessThan(val) => (
+                WktStepDuration::PowerLessThan,
+                Some(field(
+                    "duration_power",
+                    2,
+                    Value::UInt32(val.to_raw()),
+                    "% or watts",
+                )),
+            ),
+            StepDuration::PowerGreaterThan(val) => (
+                WktStepDuration::PowerGreaterThan,
+                Some(field(
+                    "duration_power",
+                    2,
+                    Value::UInt32(val.to_raw()),
+                    "% or watts",
+                )),
+            ),
+            StepDuration::Reps(val) => (
+                WktStepDuration::Reps,
+                Some(field("duration_reps", 2, Value::UInt32(val), "")),
+            ),
+            StepDuration::Other {
+                duration_type,
+                value,
+            } => (
+                duration_type,
+                value.map(|v| field("duration_value", 2, Value::UInt32(v), "")),
+            ),
+        };
+        record.push(enum_field("duration_type", 1, duration_type));
+        if let Some(duration_field) = duration_field {
+            record.push(duration_field);
+        }
+
+        let (target_type, fields) = match self.target {
+            StepTarget::Open => (WktStepTarget::Open, Vec::new()),
+            StepTarget::HeartRate(range) => (
+                WktStepTarget::HeartRate,
+                range_fields(
+                    range,
+                    "target_hr_zone",
+                    "custom_target_heart_rate",
+                    "% or bpm",
+                    |v| Value::UInt32(v.to_raw()),
+                ),
+            ),
+            StepTarget::Power(range) => (
+                WktStepTarget::Power,
+                range_fields(
+                    range,
+                    "target_power_zone",
+                    "custom_target_power",
+                    "% or watts",
+                    |v| Value::UInt32(v.to_raw()),
+                ),
+            ),
+            StepTarget::Speed(range) => (
+                WktStepTarget::Speed,
+                range_fields(
+                    range,
+                    "target_speed_zone",
+                    "custom_target_speed",
+                    "m/s",
+                    Value::Float64,
+                ),
+            ),
+            StepTarget::Cadence(range) => (
+                WktStepTarget::Cadence,
+                range_fields(
+                    range,
+                    "target_cadence_zone",
+                    "custom_target_cadence",
+                    "rpm",
+                    Value::UInt32,
+                ),
+            ),
+            StepTarget::SwimStroke(stroke) => (
+                WktStepTarget::SwimStroke,
+                stroke
+                    .map(|s| vec![enum_field("target_stroke_type", 4, s)])
+                    .unwrap_or_default(),
+            ),
+            StepTarget::Other {
+                target_type,
+                value,
+                low,
+                high,
+            } => {
+                let raw = [
+                    ("target_value", 4, value),
+                    ("custom_target_value_low", 5, low),
+                    ("custom_target_value_high", 6, high),
+                ];
+                let fields = raw
+                    .iter()
+                    .filter_map(|(name, number, val)| {
+                        val.map(|v| field(name, *number, Value::UInt32(v), ""))
+                    })
+                    .collect();
+                (target_type, fields)
+            }
+        };
+        record.push(enum_field("target_type", 3, target_type));
+        record.extend(fields);
+
+        if let Some(intensity) = self.intensity {
+            record.push(enum_field("intensity", 7, intensity));
+        }
+        if let Some(notes) = &self.notes {
+            record.push(field("notes", 8, Value::String(notes.clone()), ""));
+        }
+    }
+}
+
+/// A group of steps that is repeated until a condition is met
+#[derive(Clone, Debug, PartialEq)]
+pub struct RepeatBlock {
+    name: Option<String>,
+    condition: RepeatCondition,
+    steps: Vec<WorkoutNode>,
+}
+
+impl RepeatBlock {
+    /// Create an empty repeat block
+    pub fn new(condition: RepeatCondition) -> Self {
+        RepeatBlock {
+            name: None,
+            condition,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Name of the repeat step
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Set the name of the repeat step
+    pub fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    /// Condition ending the repeat
+    pub fn condition(&self) -> RepeatCondition {
+        self.condition
+    }
+
+    /// Steps that are repeated
+    pub fn steps(&self) -> &[WorkoutNode] {
+        &self.steps
+    }
+
+    /// Append a step or nested repeat block
+    pub fn push<T: Into<WorkoutNode>>(&mut self, node: T) {
+        self.steps.push(node.into());
+    }
+
+    fn condition_from_record(
+        record: &FitDataRecord,
+        duration_type: WktStepDuration,
+    ) -> RepeatCondition {
+        let raw = || value_u32(record, &TARGET_VALUE_NAMES).unwrap_or(0);
+        match duration_type {
+            WktStepDuration::RepeatUntilStepsCmplt => RepeatCondition::Count(raw()),
+            WktStepDuration::RepeatUntilTime => RepeatCondition::UntilTime(
+                value_f64(record, &TARGET_VALUE_NAMES, "repeat_time", 1000.0).unwrap_or(0.0),
+            ),
+            WktStepDuration::RepeatUntilDistance => RepeatCondition::UntilDistance(
+                value_f64(record, &TARGET_VALUE_NAMES, "repeat_distance", 100.0).unwrap_or(0.0),
+            ),
+            WktStepDuration::RepeatUntilCalories => RepeatCondition::UntilCalories(raw()),
+            WktStepDuration::RepeatUntilHrLessThan => {
+                RepeatCondition::UntilHeartRateLessThan(HeartRate::from_raw(raw()))
+            }
+            WktStepDuration::RepeatUntilHrGreaterThan => {
+                RepeatCondition::UntilHeartRateGreaterThan(HeartRate::from_raw(raw()))
+            }
+            WktStepDuration::RepeatUntilPowerLessThan => {
+                RepeatCondition::UntilPowerLessThan(Power::from_raw(raw()))
+            }
+            WktStepDuration::RepeatUntilPowerGreaterThan => {
+                RepeatCondition::UntilPowerGreaterThan(Power::from_raw(raw()))
+            }
+            duration_type => RepeatCondition::Other {
+                duration_type,
+                value: value_u32(record, &TARGET_VALUE_NAMES),
+            },
+        }
+    }
+
+    fn push_fields(&self, record: &mut FitDataRecord, first_step: usize) {
+        if let Some(name) = &self.name {
+            record.push(field("wkt_step_name", 0, Value::String(name.clone()), ""));
+        }
+        let (duration_type, target_field) = match self.condition {
+            RepeatCondition::Count(val) => (
+                WktStepDuration::RepeatUntilStepsCmplt,
+                Some(field("repeat_steps", 4, Value::UInt32(val), "")),
+            ),
+            RepeatCondition::UntilTime(val) => (
+                WktStepDuration::RepeatUntilTime,
+                Some(field("repeat_time", 4, Value::Float64(val), "s")),
+            ),
+            RepeatCondition::UntilDistance(val) => (
+                WktStepDuration::RepeatUntilDistance,
+                Some(field("repeat_distance", 4, Value::Float64(val), "m")),
+            ),
+            RepeatCondition::UntilCalories(val) => (
+                WktStepDuration::RepeatUntilCalories,
+                Some(field("repeat_calories", 4, Value::UInt32(val), "calories")),
+            ),
+            RepeatCondition::UntilHeartRateLessThan(val) => (
+                WktStepDuration::RepeatUntilHrLessThan,
+                Some(field(
+                    "repeat_hr",
+                    4,
+                    Value::UInt32(val.to_raw()),
+                    "% or bpm",
+                )),
+            ),
+            RepeatCondition::UntilHeartRateGreaterThan(val) => (
+                WktStepDuration::RepeatUntilHrGreaterThan,
+                Some(field(
+                    "repeat_hr",
+                    4,
+                    Value::UInt32(val.to_raw()),
+                    "% or bpm",
+                )),
+            ),
+            RepeatCondition::UntilPowerLessThan(val) => (
+                WktStepDuration::RepeatUntilPowerLessThan,
+                Some(field(
+                    "repeat_power",
+                    4,
+                    Value::UInt32(val.to_raw()),
+                    "% or watts",
+                )),
+            ),
+            RepeatCondition::UntilPowerGreaterThan(val) => (
+                WktStepDuration::RepeatUntilPowerGreaterThan,
+                Some(field(
+                    "repeat_power",
+                    4,
+                    Value::UInt32(val.to_raw()),
+                    "% or watts",
+                )),
+            ),
+            RepeatCondition::Other {
+                duration_type,
+                value,
+            } => (
+                duration_type,
+                value.map(|v| field("target_value", 4, Value::UInt32(v), "")),
+            ),
+        };
+        record.push(enum_field("duration_type", 1, duration_type));
+        record.push(field(
+            "duration_step",
+            2,
+            Value::UInt32(first_step as u32),
+            "",
+        ));
+        record.push(enum_field("target_type", 3, WktStepTarget::Open));
+        if let Some(target_field) = target_field {
+            record.push(target_field);
+        }
+    }
+}
+
+/// A node of the workout tree
+#[derive(Clone, Debug, PartialEq)]
+pub enum WorkoutNode {
+    /// A single step
+    Step(WorkoutStep),
+    /// A block of repeated steps
+    Repeat(RepeatBlock),
+}
+
+impl From<WorkoutStep> for WorkoutNode {
+    fn from(step: WorkoutStep) -> Self {
+        WorkoutNode::Step(step)
+    }
+}
+
+impl From<RepeatBlock> for WorkoutNode {
+    fn from(block: RepeatBlock) -> Self {
+        WorkoutNode::Repeat(block)
+    }
+}
+
+/// A workout made up of steps and repeat blocks
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Workout {
+    name: Option<String>,
+    sport: Option<Sport>,
+    sub_sport: Option<SubSport>,
+    steps: Vec<WorkoutNode>,
+}
+
+impl Workout {
+    /// Create an empty workout
+    pub fn new() -> Self {
+        Workout::default()
+    }
+
+    /// Build the workout from the `Workout` and `WorkoutStep` messages in the records. Steps
+    /// are ordered by their `message_index` and repeat steps collect the steps from the
+    /// referenced index up to the repeat step into a block.
+    pub fn from_records(records: &[FitDataRecord]) -> Result<Self> {
+        let mut workout = Workout::new();
+        if let Some(record) = records.iter().find(|r| r.kind() == MesgNum::Workout) {
+            workout.name = string_value(record, "wkt_name");
+            workout.sport = enum_value::<Sport>(record, "sport");
+            workout.sub_sport = enum_value::<SubSport>(record, "sub_sport");
+        }
+
+        let mut steps: Vec<(usize, &FitDataRecord)> = records
+            .iter()
+            .filter(|r| r.kind() == MesgNum::WorkoutStep)
+            .enumerate()
+            .map(|(idx, r)| {
+                (
+                    value_u32(r, &["message_index"]).map_or(idx, |v| v as usize),
+                    r,
+                )
+            })
+            .collect();
+        steps.sort_by_key(|(idx, _)| *idx);
+
+        // top level nodes paired with the message index of their first step
+        let mut nodes: Vec<(usize, WorkoutNode)> = Vec::new();
+        for (index, record) in steps {
+            let duration_type = enum_value::<WktStepDuration>(record, "duration_type")
+                .unwrap_or(WktStepDuration::Open);
+            if !is_repeat(duration_type) {
+                let step = WorkoutStep::from_record(record, duration_type);
+                nodes.push((index, WorkoutNode::Step(step)));
+                continue;
+            }
+
+            let first_step = value_u32(record, &DURATION_VALUE_NAMES).ok_or_else(|| {
+                invalid_workout(format!("repeat step {} has no duration_step value", index))
+            })? as usize;
+            let split = nodes
+                .iter()
+                .position(|(start, _)| *start == first_step)
+                .ok_or_else(|| {
+                    invalid_workout(format!(
+                        "repeat step {} references step {} which doesn't start a block",
+                        index, first_step
+                    ))
+                })?;
+            let block = RepeatBlock {
+                name: string_value(record, "wkt_step_name"),
+                condition: RepeatBlock::condition_from_record(record, duration_type),
+                steps: nodes.split_off(split).into_iter().map(|(_, n)| n).collect(),
+            };
+            nodes.push((first_step, WorkoutNode::Repeat(block)));
+        }
+        workout.steps = nodes.into_iter().map(|(_, n)| n).collect();
+
+        Ok(workout)
+    }
+
+    /// Convert the workout into a `Workout` message followed by the `WorkoutStep` messages
+    pub fn to_records(&self) -> Vec<FitDataRecord> {
+        let mut steps = Vec::new();
+        flatten(&self.steps, &mut steps);
+
+        let mut workout = FitDataRecord::new(MesgNum::Workout);
+        if let Some(sport) = self.sport {
+            workout.push(enum_field("sport", 4, sport));
+        }
+        workout.push(field(
+            "num_valid_steps",
+            6,
+            Value::UInt16(steps.len() as u16),
+            "",
+        ));
+        if let Some(name) = &self.name {
+            workout.push(field("wkt_name", 8, Value::String(name.clone()), ""));
+        }
+        if let Some(sub_sport) = self.sub_sport {
+            workout.push(enum_field("sub_sport", 11, sub_sport));
+        }
+
+        let mut records = vec![workout];
+        records.extend(steps);
+        records
+    }
+
+    /// Name of the workout
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Set the name of the workout
+    pub fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    /// Sport the workout is for
+    pub fn sport(&self) -> Option<Sport> {
+        self.sport
+    }
+
+    /// Set the sport the workout is for
+    pub fn set_sport(&mut self, sport: Sport) {
+        self.sport = Some(sport);
+    }
+
+    /// Sub sport the workout is for
+    pub fn sub_sport(&self) -> Option<SubSport> {
+        self.sub_sport
+    }
+
+    /// Set the sub sport the workout is for
+    pub fn set_sub_sport(&mut self, sub_sport: SubSport) {
+        self.sub_sport = Some(sub_sport);
+    }
+
+    /// Top level steps and repeat blocks
+    pub fn steps(&self) -> &[WorkoutNode] {
+        &self.steps
+    }
+
+    /// Append a step or repeat block
+    pub fn push<T: Into<WorkoutNode>>(&mut self, node: T) {
+        self.steps.push(node.into());
+    }
+
+    /// Number of `WorkoutStep` messages needed to store the workout, each repeat block takes
+    /// one message in addition to its steps
+    pub fn num_valid_steps(&self) -> usize {
+        fn count(nodes: &[WorkoutNode]) -> usize {
+            nodes
+                .iter()
+                .map(|n| match n {
+                    WorkoutNode::Step(_) => 1,
+                    WorkoutNode::Repeat(block) => 1 + count(&block.steps),
+                })
+                .sum()
+        }
+        count(&self.steps)
+    }
+}
+
+/// Builds a `File::Workout` FIT file from a [`Workout`] that can be loaded onto a device.
+/// ```
+/// use fitparser::profile::field_types::Sport;
+/// use fitparser::workout::{StepDuration, StepTarget, Workout, WorkoutFile, WorkoutStep};
+///
+/// let mut workout = Workout::new();
+/// workout.set_name("Easy run");
+/// workout.set_sport(Sport::Running);
+/// workout.push(WorkoutStep::new(StepDuration::Time(1800.0), StepTarget::Open));
+/// let bytes = WorkoutFile::new(workout).to_bytes()?;
+///
+/// let records = fitparser::from_bytes(&bytes)?;
+/// assert_eq!(Workout::from_records(&records)?.name(), Some("Easy run"));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkoutFile {
+    workout: Workout,
+    manufacturer: Manufacturer,
+    product: u16,
+    serial_number: Option<u32>,
+    time_created: DateTime<Local>,
+}
+
+impl WorkoutFile {
+    /// Create a workout file created now by a development device
+    pub fn new(workout: Workout) -> Self {
+        WorkoutFile {
+            workout,
+            manufacturer: Manufacturer::Development,
+            product: 0,
+            serial_number: None,
+            time_created: Local::now(),
+        }
+    }
+
+    /// Workout stored in the file
+    pub fn workout(&self) -> &Workout {
+        &self.workout
+    }
+
+    /// Manufacturer written to the `FileId` message
+    pub fn manufacturer(&self) -> Manufacturer {
+        self.manufacturer
+    }
+
+    /// Set the manufacturer written to the `FileId` message
+    pub fn set_manufacturer(&mut self, manufacturer: Manufacturer) {
+        self.manufacturer = manufacturer;
+    }
+
+    /// Product ID written to the `FileId` message
+    pub fn product(&self) -> u16 {
+        self.product
+    }
+
+    /// Set the product ID written to the `FileId` message
+    pub fn set_product(&mut self, product: u16) {
+        self.product = product;
+    }
+
+    /// Serial number written to the `FileId` message
+    pub fn serial_number(&self) -> Option<u32> {
+        self.serial_number
+    }
+
+    /// Set the serial number written to the `FileId` message
+    pub fn set_serial_number(&mut self, serial_number: u32) {
+        self.serial_number = Some(serial_number);
+    }
+
+    /// Creation time written to the `FileId` message
+    pub fn time_created(&self) -> DateTime<Local> {
+        self.time_created
+    }
+
+    /// Set the creation time written to the `FileId` message
+    pub fn set_time_created(&mut self, time_created: DateTime<Local>) {
+        self.time_created = time_created;
+    }
+
+    /// Check that the workout can be stored and will be accepted by a device: it must have at
+    /// least one step, repeat blocks can't be empty and values must be in range for their type.
+    pub fn validate(&self) -> Result<()> {
+        if self.workout.steps.is_empty() {
+            return Err(invalid_workout("workout has no steps".to_string()));
+        }
+        if self.workout.num_valid_steps() > u16::MAX as usize {
+            return Err(invalid_workout(format!(
+                "workout has {} steps, at most {} can be stored",
+                self.workout.num_valid_steps(),
+                u16::MAX
+            )));
+        }
+        validate_nodes(&self.workout.steps)
+    }
+
+    /// Validate the workout and encode the `FileId`, `Workout` and `WorkoutStep` messages
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.validate()?;
+        let mut file_id = HashMap::new();
+        file_id.insert(0, Value::Enum(File::Workout.as_i64() as u8));
+        file_id.insert(1, Value::UInt16(self.manufacturer.as_u16()));
+        file_id.insert(2, Value::UInt16(self.product));
+        if let Some(serial_number) = self.serial_number {
+            file_id.insert(3, Value::UInt32z(serial_number));
+        }
+        file_id.insert(4, Value::Timestamp(self.time_created));
+
+        let mut serializer = Serializer::new();
+        serializer.serialize(&FitDataMessage::new(MesgNum::FileId.as_u16(), file_id))?;
+        for record in self.workout.to_records() {
+            serializer.serialize(&raw_message(&record))?;
+        }
+        Ok(serializer.finish())
+    }
+}
+
+fn validate_nodes(nodes: &[WorkoutNode]) -> Result<()> {
+    for node in nodes {
+        match node {
+            WorkoutNode::Step(step) => validate_step(step)?,
+            WorkoutNode::Repeat(block) => {
+                if block.steps.is_empty() {
+                    return Err(invalid_workout("repeat block has no steps".to_string()));
+                }
+                match block.condition {
+                    RepeatCondition::Count(0) => {
+                        return Err(invalid_workout("repeat count must be positive".to_string()))
+                    }
+                    RepeatCondition::UntilTime(val) => check_positive("repeat time", val)?,
+                    RepeatCondition::UntilDistance(val) => check_positive("repeat distance", val)?,
+                    RepeatCondition::UntilHeartRateLessThan(val)
+                    | RepeatCondition::UntilHeartRateGreaterThan(val) => check_heart_rate(val)?,
+                    RepeatCondition::UntilPowerLessThan(val)
+                    | RepeatCondition::UntilPowerGreaterThan(val) => check_power(val)?,
+                    _ => {}
+                }
+                validate_nodes(&block.steps)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_step(step: &WorkoutStep) -> Result<()> {
+    match step.duration {
+        StepDuration::Time(val) => check_positive("step time", val)?,
+        StepDuration::Distance(val) => check_positive("step distance", val)?,
+        StepDuration::HeartRateLessThan(val) | StepDuration::HeartRateGreaterThan(val) => {
+            check_heart_rate(val)?
+        }
+        StepDuration::PowerLessThan(val) | StepDuration::PowerGreaterThan(val) => check_power(val)?,
+        _ => {}
+    }
+    match step.target {
+        StepTarget::HeartRate(range) => {
+            check_range(range, |v| v.to_raw() as f64)?;
+            if let TargetRange::Custom { low, high } = range {
+                check_heart_rate(low)?;
+                check_heart_rate(high)?;
+            }
+        }
+        StepTarget::Power(range) => {
+            check_range(range, |v| v.to_raw() as f64)?;
+            if let TargetRange::Custom { low, high } = range {
+                check_power(low)?;
+                check_power(high)?;
+            }
+        }
+        StepTarget::Speed(range) => {
+            check_range(range, |v| v)?;
+            if let TargetRange::Custom { low, high } = range {
+                check_positive("speed target", low)?;
+                check_positive("speed target", high)?;
+            }
+        }
+        StepTarget::Cadence(range) => check_range(range, |v| v as f64)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn check_positive(what: &str, value: f64) -> Result<()> {
+    if value.is_finite() && value > 0.0 {
+        Ok(())
+    } else {
+        Err(invalid_workout(format!(
+            "{} must be positive, got {}",
+            what, value
+        )))
+    }
+}
+
+/// Percentages of max heart rate above 100 would be read back as bpm
+fn check_heart_rate(value: HeartRate) -> Result<()> {
+    match value {
+        HeartRate::PercentMax(val) if val > 100 => Err(invalid_workout(format!(
+            "heart rate of {}% of max is out of range",
+            val
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Percentages of FTP above 1000 would be read back as watts
+fn check_power(value: Power) -> Result<()> {
+    match value {
+        Power::PercentFtp(val) if val > 1000 => Err(invalid_workout(format!(
+            "power of {}% of FTP is out of range",
+            val
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Zone 0 is stored as "use the custom range" and custom ranges must be ordered
+fn check_range<T: Copy>(range: TargetRange<T>, raw: fn(T) -> f64) -> Result<()> {
+    match range {
+        TargetRange::Zone(0) => Err(invalid_workout("target zones start at 1".to_string())),
+        TargetRange::Custom { low, high } if raw(low) > raw(high) => Err(invalid_workout(
+            "custom target low value is above the high value".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Convert a record produced by `Workout::to_records` back into the raw values stored in the
+/// file, enums are replaced by their numeric value and scaled fields are unscaled.
+fn raw_message(record: &FitDataRecord) -> FitDataMessage {
+    let fields: FieldMap = record
+        .fields()
+        .iter()
+        .map(|field| {
+            let value = match field.value() {
+                Value::String(val) => match raw_enum(field.name(), val) {
+                    Some(num) => enum_raw_value(field.name(), num),
+                    None => Value::String(val.clone()),
+                },
+                Value::SInt64(num) => enum_raw_value(field.name(), *num),
+                Value::Float64(val) => {
+                    let scale = match field.name() {
+                        "duration_time" | "repeat_time" => 1000.0,
+                        "custom_target_speed_low" | "custom_target_speed_high" => 1000.0,
+                        "duration_distance" | "repeat_distance" => 100.0,
+                        _ => 1.0,
+                    };
+                    Value::UInt32((val * scale).round() as u32)
+                }
+                val => val.clone(),
+            };
+            (field.number(), value)
+        })
+        .collect();
+    FitDataMessage::new(record.kind().as_u16(), fields)
+}
+
+/// Numeric value of the enum fields written by `Workout::to_records`
+fn raw_enum(name: &str, value: &str) -> Option<i64> {
+    let num = match name {
+        "sport" => Sport::from(value).as_i64(),
+        "sub_sport" => SubSport::from(value).as_i64(),
+        "duration_type" => WktStepDuration::from(value).as_i64(),
+        "target_type" => WktStepTarget::from(value).as_i64(),
+        "intensity" => Intensity::from(value).as_i64(),
+        "target_stroke_type" => SwimStroke::from(value).as_i64(),
+        _ => return None,
+    };
+    Some(num)
+}
+
+/// The stroke type is stored in the uint32 `target_value` field, other enums use the enum type
+fn enum_raw_value(name: &str, value: i64) -> Value {
+    if name == "target_stroke_type" {
+        Value::UInt32(value as u32)
+    } else {
+        Value::Enum(value as u8)
+    }
+}
+
+/// Append the `WorkoutStep` records for the nodes, message indices follow the record position
+fn flatten(nodes: &[WorkoutNode], records: &mut Vec<FitDataRecord>) {
+    for node in nodes {
+        let first_step = records.len();
+        let mut record = FitDataRecord::new(MesgNum::WorkoutStep);
+        match node {
+            WorkoutNode::Step(step) => step.push_fields(&mut record),
+            WorkoutNode::Repeat(block) => {
+                flatten(&block.steps, records);
+                block.push_fields(&mut record, first_step);
+            }
+        }
+        record.push(field(
+            "message_index",
+            254,
+            Value::UInt16(records.len() as u16),
+            "",
+        ));
+        records.push(record);
+    }
+}
+
+fn is_repeat(duration_type: WktStepDuration) -> bool {
+    matches!(
+        duration_type,
+        WktStepDuration::RepeatUntilStepsCmplt
+            | WktStepDuration::RepeatUntilTime
+            | WktStepDuration::RepeatUntilDistance
+            | WktStepDuration::RepeatUntilCalories
+            | WktStepDuration::RepeatUntilHrLessThan
+            | WktStepDuration::RepeatUntilHrGreaterThan
+            | WktStepDuration::RepeatUntilPowerLessThan
+            | WktStepDuration::RepeatUntilPowerGreaterThan
+            | WktStepDuration::RepeatUntilPowerLastLapLessThan
+            | WktStepDuration::RepeatUntilMaxPowerLastLapLessThan
+            | WktStepDuration::RepeatUntilTrainingPeaksTss
+    )
+}
+
+fn invalid_workout(message: String) -> crate::Error {
+    ErrorKind::InvalidWorkout(message).into()
+}
+
+/// Find the first field decoded under one of the names
+fn find_field<'a>(record: &'a FitDataRecord, names: &[&str]) -> Option<&'a FitDataField> {
+    record.fields().iter().find(|f| names.contains(&f.name()))
+}
+
+/// Return the value of a field stored without a scale
+fn value_u32(record: &FitDataRecord, names: &[&str]) -> Option<u32> {
+    let value: i64 = find_field(record, names)?.value().try_into().ok()?;
+    value.try_into().ok()
+}
+
+/// Return the value of a scaled field. If the field was decoded under another name (e.g. the
+/// generic field name or a subfield selected by the target type) it holds the unscaled value.
+fn value_f64(record: &FitDataRecord, names: &[&str], name: &str, scale: f64) -> Option<f64> {
+    let field = find_field(record, names)?;
+    let value: f64 = field.value().try_into().ok()?;
+    if field.name() == name {
+        Some(value)
+    } else {
+        Some(value / scale)
+    }
+}
+
+fn string_value(record: &FitDataRecord, name: &str) -> Option<String> {
+    match record.field(name)?.value() {
+        Value::String(val) => Some(val.clone()),
+        _ => None,
+    }
+}
+
+fn field(name: &str, number: u8, value: Value, units: &str) -> FitDataField {
+    FitDataField::new(name.to_string(), number, None, value, units.to_string())
+}
+
+/// Enums are stored by name like the decoder does, unknown variants keep their numeric value
+fn enum_field<T: ToString>(name: &str, number: u8, value: T) -> FitDataField {
+    let name_or_value = value.to_string();
+    let value = match name_or_value.strip_prefix("unknown_variant_") {
+        Some(num) => Value::SInt64(num.parse().unwrap_or_default()),
+        None => Value::String(name_or_value),
+    };
+    field(name, number, value, "")
+}
+
+/// A target value of 0 means the custom limits are used instead of a zone
+fn target_range<T>(zone: u32, low: T, high: T) -> TargetRange<T> {
+    if zone == 0 {
+        TargetRange::Custom { low, high }
+    } else {
+        TargetRange::Zone(zone)
+    }
+}
+
+/// Build the zone or custom limit fields of a target
+fn range_fields<T: Copy>(
+    range: TargetRange<T>,
+    zone_name: &str,
+    custom_name: &str,
+    units: &str,
+    convert: fn(T) -> Value,
+) -> Vec<FitDataField> {
+    match range {
+        TargetRange::Zone(zone) => vec![field(zone_name, 4, Value::UInt32(zone), "")],
+        TargetRange::Custom { low, high } => vec![
+            field(zone_name, 4, Value::UInt32(0), ""),
+            field(&format!("{}_low", custom_name), 5, convert(low), units),
+            field(&format!("{}_high", custom_name), 6, convert(high), units),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_bytes;
+
+    #[test]
+    fn repeat_steps_tree() {
+        let data = include_bytes!("../tests/fixtures/WorkoutRepeatSteps.fit").to_vec();
+        let records = from_bytes(&data).unwrap();
+        let workout = Workout::from_records(&records).unwrap();
+        assert_eq!(workout.name(), Some("Example 2"));
+        assert_eq!(workout.num_valid_steps(), 5);
+        assert_eq!(workout.steps().len(), 3);
+
+        let block = match &workout.steps()[1] {
+            WorkoutNode::Repeat(block) => block,
+            node => panic!("expected a repeat block, got {:?}", node),
+        };
+        assert_eq!(block.condition(), RepeatCondition::Count(3));
+        assert_eq!(block.steps().len(), 2);
+        match &block.steps()[0] {
+            WorkoutNode::Step(step) => {
+                assert_eq!(step.name(), Some("B1_"));
+                assert_eq!(step.duration(), StepDuration::Distance(500.0));
+                assert_eq!(step.target(), StepTarget::Power(TargetRange::Zone(5)));
+            }
+            node => panic!("expected a step, got {:?}", node),
+        }
+        match &workout.steps()[2] {
+            WorkoutNode::Step(step) => assert_eq!(
+                step.duration(),
+                StepDuration::HeartRateLessThan(HeartRate::Bpm(125))
+            ),
+            node => panic!("expected a step, got {:?}", node),
+        }
+
+        let round_trip = Workout::from_records(&workout.to_records()).unwrap();
+        assert_eq!(round_trip, workout);
+    }
+
+    #[test]
+    fn custom_targets_and_repeat_conditions() {
+        let data = include_bytes!("../tests/fixtures/WorkoutCustomTargetValues.fit").to_vec();
+        let records = from_bytes(&data).unwrap();
+        let workout = Workout::from_records(&records).unwrap();
+        let targets: Vec<_> = workout
+            .steps()
+            .iter()
+            .filter_map(|n| match n {
+                WorkoutNode::Step(step) => Some(step.target()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            targets[0],
+            StepTarget::HeartRate(TargetRange::Custom {
+                low: HeartRate::PercentMax(50),
+                high: HeartRate::PercentMax(60)
+            })
+        );
+        assert_eq!(
+            targets[1],
+            StepTarget::Power(TargetRange::Custom {
+                low: Power::Watts(300),
+                high: Power::Watts(310)
+            })
+        );
+        assert_eq!(
+            Workout::from_records(&workout.to_records()).unwrap(),
+            workout
+        );
+
+        // the repeat value is decoded as target_hr_zone since the step has a heart rate target
+        let data = include_bytes!("../tests/fixtures/WorkoutRepeatGreaterThanStep.fit").to_vec();
+        let records = from_bytes(&data).unwrap();
+        let workout = Workout::from_records(&records).unwrap();
+        match &workout.steps()[1] {
+            WorkoutNode::Repeat(block) => assert_eq!(
+                block.condition(),
+                RepeatCondition::UntilHeartRateGreaterThan(HeartRate::PercentMax(80))
+            ),
+            node => panic!("expected a repeat block, got {:?}", node),
+        }
+    }
+
+    #[test]
+    fn nested_repeats() {
+        let step = |secs| WorkoutStep::new(StepDuration::Time(secs), StepTarget::Open);
+        let mut inner = RepeatBlock::new(RepeatCondition::Count(4));
+        inner.push(step(30.0));
+        inner.push(step(30.0));
+        let mut outer = RepeatBlock::new(RepeatCondition::UntilTime(1200.0));
+        outer.push(inner);
+        outer.push(step(120.0));
+        let mut workout = Workout::new();
+        workout.set_sport(Sport::Running);
+        workout.push(step(600.0));
+        workout.push(outer);
+
+        let records = workout.to_records();
+        assert_eq!(records.len(), 7);
+        assert_eq!(workout.num_valid_steps(), 6);
+        let inner_repeat = &records[4];
+        assert_eq!(
+            inner_repeat.field("duration_step").unwrap().value(),
+            &Value::UInt32(1)
+        );
+        let outer_repeat = &records[6];
+        assert_eq!(
+            outer_repeat.field("duration_step").unwrap().value(),
+            &Value::UInt32(1)
+        );
+        assert_eq!(Workout::from_records(&records).unwrap(), workout);
+
+        let mut bad = records.clone();
+        bad[6] = {
+            let mut record = FitDataRecord::new(MesgNum::WorkoutStep);
+            record.push(enum_field(
+                "duration_type",
+                1,
+                WktStepDuration::RepeatUntilStepsCmplt,
+            ));
+            record.push(field("duration_step", 2, Value::UInt32(2), ""));
+            record.push(field("message_index", 254, Value::UInt16(5), ""));
+            record
+        };
+        assert!(Workout::from_records(&bad).is_err());
+    }
+
+    #[test]
+    fn workout_file_round_trip() {
+        let fixtures: [&[u8]; 2] = [
+            include_bytes!("../tests/fixtures/WorkoutRepeatSteps.fit"),
+            include_bytes!("../tests/fixtures/WorkoutCustomTargetValues.fit"),
+        ];
+        for data in fixtures.iter() {
+            let workout = Workout::from_records(&from_bytes(data).unwrap()).unwrap();
+            let mut file = WorkoutFile::new(workout.clone());
+            file.set_serial_number(1234);
+            let records = from_bytes(&file.to_bytes().unwrap()).unwrap();
+
+            assert_eq!(records[0].kind(), MesgNum::FileId);
+            assert_eq!(
+                records[0].field("type").unwrap().value(),
+                &Value::String("workout".to_string())
+            );
+            assert_eq!(
+                records[0].field("serial_number").unwrap().value(),
+                &Value::UInt32z(1234)
+            );
+            assert_eq!(records[1].kind(), MesgNum::Workout);
+            assert_eq!(
+                records[1].field("num_valid_steps").unwrap().value(),
+                &Value::UInt16(workout.num_valid_steps() as u16)
+            );
+            assert_eq!(Workout::from_records(&records).unwrap(), workout);
+        }
+    }
+
+    #[test]
+    fn workout_file_validation() {
+        let validate = |node: WorkoutNode| {
+            let mut workout = Workout::new();
+            workout.push(node);
+            WorkoutFile::new(workout).validate()
+        };
+        assert!(WorkoutFile::new(Workout::new()).validate().is_err());
+        assert!(validate(RepeatBlock::new(RepeatCondition::Count(2)).into()).is_err());
+        assert!(
+            validate(WorkoutStep::new(StepDuration::Time(-1.0), StepTarget::Open).into()).is_err()
+        );
+
+        let hr = |low, high| {
+            WorkoutStep::new(
+                StepDuration::Open,
+                StepTarget::HeartRate(TargetRange::Custom { low, high }),
+            )
+            .into()
+        };
+        assert!(validate(hr(HeartRate::PercentMax(60), HeartRate::PercentMax(70))).is_ok());
+        assert!(validate(hr(HeartRate::PercentMax(70), HeartRate::PercentMax(60))).is_err());
+        assert!(validate(hr(HeartRate::PercentMax(90), HeartRate::PercentMax(120))).is_err());
+
+        let mut block = RepeatBlock::new(RepeatCondition::Count(0));
+        block.push(WorkoutStep::new(
+            StepDuration::Open,
+            StepTarget::Power(TargetRange::Zone(3)),
+        ));
+        assert!(validate(block.clone().into()).is_err());
+        block = RepeatBlock::new(RepeatCondition::Count(3));
+        block.push(WorkoutStep::new(
+            StepDuration::Open,
+            StepTarget::Power(TargetRange::Zone(0)),
+        ));
+        assert!(validate(block.into()).is_err());
+    }
+}
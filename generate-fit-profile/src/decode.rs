@@ -48,21 +48,53 @@ fn decode_function_def(msg: &MessageDefinition) -> TokenStream {
         }
     }
 
-    quote! {
-        #(#comments)*
-        fn #fn_name(mesg_num: MesgNum, data_map: &mut HashMap<u8, Value> , accumlators: &mut HashMap<u32, Value>, options: &HashSet<DecodeOption>) -> Result<Vec<FitDataField>> {
-            let mut fields = Vec::new();
-            let mut entries: VecDeque<(u8, Value)> = data_map.iter().map(|(k, v)| (*k, v.clone())).collect();
-            while let Some((field_nr, value)) = entries.pop_front() {
-                match field_nr {
-                    #(#match_arms)*
-                    _ => {
-                        if !options.contains(&DecodeOption::DropUnknownFields) {
-                            fields.push(unknown_field(field_nr, value));
-                        }
+    let decode_field = quote! {
+        let (field_nr, value) = match data_map.get_index(idx) {
+            Some((field_nr, value)) => (field_nr, value.clone()),
+            None => break,
+        };
+        match field_nr {
+            #(#match_arms)*
+            _ => {
+                if !options.contains(&DecodeOption::DropUnknownFields) {
+                    fields.push(unknown_field(field_nr, value));
+                }
+            }
+        }
+    };
+    let comp_nums: Vec<u8> = msg
+        .field_map()
+        .values()
+        .filter(|fld| !fld.components().is_empty())
+        .map(|fld| fld.def_number())
+        .collect();
+    // component values are appended to the map, only decode the fields that were parsed
+    let decode_loop = if comp_nums.is_empty() {
+        quote! {
+            for idx in 0..data_map.len() {
+                #decode_field
+            }
+        }
+    } else {
+        // expand the components first so subfields can be resolved using the expanded values
+        quote! {
+            let parsed = data_map.len();
+            for expand in [true, false] {
+                for idx in 0..parsed {
+                    if data_map.get_index(idx).is_some_and(|(field_nr, _)| matches!(field_nr, #(#comp_nums)|*) != expand) {
+                        continue;
                     }
+                    #decode_field
                 }
             }
+        }
+    };
+
+    quote! {
+        #(#comments)*
+        fn #fn_name(mesg_num: MesgNum, data_map: &mut FieldMap, accumlators: &mut HashMap<u32, Value>, options: &HashSet<DecodeOption>) -> Result<Vec<FitDataField>> {
+            let mut fields = Vec::with_capacity(data_map.len());
+            #decode_loop
             Ok(fields)
         }
         #(#sub_field_fns)*
@@ -288,7 +320,7 @@ fn create_fn_def(mesg_def: &MessageDefinition, fld_def: &MessageFieldDefinition)
         fn #field_fn_name(mesg_num: MesgNum,
             accumlators: &mut HashMap<u32, Value>,
             options: &HashSet<DecodeOption>,
-            data_map: &HashMap<u8, Value>,
+            data_map: &FieldMap,
             accumulate: bool,
             scale: f64,
             offset: f64,
@@ -326,7 +358,7 @@ fn generate_create_fn_call(
 fn unknown_mesg_fn() -> TokenStream {
     quote! {
         fn unknown_message(
-            data_map: &HashMap<u8, Value>,
+            data_map: &FieldMap,
             options: &HashSet<DecodeOption>,
         ) -> Result<Vec<FitDataField>> {
             // since it's an unknown message all the fields are unknown
@@ -341,18 +373,149 @@ fn unknown_mesg_fn() -> TokenStream {
     }
 }
 
+/// Check if decoding the field, its components or subfields updates an accumulated value
+fn accumulates(fld_def: &MessageFieldDefinition) -> bool {
+    fld_def.accumulate()
+        || fld_def
+            .components()
+            .iter()
+            .any(|(_, comp)| accumulates(comp))
+        || fld_def
+            .subfields()
+            .iter()
+            .any(|(_, _, sub_field_info)| accumulates(sub_field_info))
+}
+
+/// Collect the names of the fields decoded from the field, its components and subfields in the
+/// order they are decoded
+fn decoded_names(fld_def: &MessageFieldDefinition, names: &mut Vec<String>) {
+    if !fld_def.components().is_empty() {
+        push_name(fld_def.name(), names);
+        for (_, comp) in fld_def.components() {
+            decoded_names(comp, names);
+        }
+    } else {
+        // subfields are checked first and the field itself is the fallback
+        for (_, _, sub_field_info) in fld_def.subfields() {
+            decoded_names(sub_field_info, names);
+        }
+        push_name(fld_def.name(), names);
+    }
+}
+
+fn push_name(name: &str, names: &mut Vec<String>) {
+    if !names.iter().any(|n| n == name) {
+        names.push(name.to_string());
+    }
+}
+
+/// Collect the fields referenced to select the subfields of the field, its components and
+/// subfields
+fn subfield_references(
+    mesg_def: &MessageDefinition,
+    fld_def: &MessageFieldDefinition,
+    def_nums: &mut Vec<u8>,
+) {
+    if !fld_def.components().is_empty() {
+        for (_, comp) in fld_def.components() {
+            subfield_references(mesg_def, comp, def_nums);
+        }
+    } else {
+        for (ref_name, _, sub_field_info) in fld_def.subfields() {
+            let ref_def_num = mesg_def.get_field_by_name(ref_name).def_number();
+            if !def_nums.contains(&ref_def_num) {
+                def_nums.push(ref_def_num);
+            }
+            for (_, comp) in sub_field_info.components() {
+                subfield_references(mesg_def, comp, def_nums);
+            }
+        }
+    }
+}
+
 fn mesg_num_to_mesg_decode_fn(messages: &[MessageDefinition]) -> TokenStream {
     let msg_variants = messages.iter().map(MessageDefinition::struct_ident);
     let fn_names = messages.iter().map(MessageDefinition::function_name);
+    let acc_arms = messages.iter().filter_map(|msg| {
+        let variant = msg.struct_ident();
+        let def_nums: Vec<u8> = msg
+            .field_map()
+            .values()
+            .filter(|fld| accumulates(fld))
+            .map(|fld| fld.def_number())
+            .collect();
+        if def_nums.is_empty() {
+            None
+        } else {
+            Some(quote!(MesgNum::#variant => matches!(field_nr, #(#def_nums)|*),))
+        }
+    });
+    let name_arms = messages.iter().map(|msg| {
+        let variant = msg.struct_ident();
+        let field_arms = msg.field_map().values().map(|fld| {
+            let def_num = fld.def_number();
+            let mut names = Vec::new();
+            decoded_names(fld, &mut names);
+            quote!(#def_num => &[#(#names),*],)
+        });
+        quote!(MesgNum::#variant => match field_nr { #(#field_arms)* _ => &[] },)
+    });
+    let ref_arms = messages.iter().filter_map(|msg| {
+        let variant = msg.struct_ident();
+        let field_arms: Vec<TokenStream> = msg
+            .field_map()
+            .values()
+            .filter_map(|fld| {
+                let def_num = fld.def_number();
+                let mut def_nums = Vec::new();
+                subfield_references(msg, fld, &mut def_nums);
+                if def_nums.is_empty() {
+                    None
+                } else {
+                    Some(quote!(#def_num => &[#(#def_nums),*],))
+                }
+            })
+            .collect();
+        if field_arms.is_empty() {
+            None
+        } else {
+            Some(quote!(MesgNum::#variant => match field_nr { #(#field_arms)* _ => &[] },))
+        }
+    });
     quote! {
         impl MesgNum {
             /// Decode the raw values from a FitDataMessage based on the Global Message Number
-            pub fn decode_message(self, data_map: &mut HashMap<u8, Value>, accumlators: &mut HashMap<u32, Value>, options: &HashSet<DecodeOption>) -> Result<Vec<FitDataField>> {
+            pub fn decode_message(self, data_map: &mut FieldMap, accumlators: &mut HashMap<u32, Value>, options: &HashSet<DecodeOption>) -> Result<Vec<FitDataField>> {
                 match self {
                     #(MesgNum::#msg_variants => #fn_names(self, data_map, accumlators, options),)*
                     _ => unknown_message(data_map, options)
                 }
             }
+
+            /// Check if decoding the raw field updates a value accumulated across messages
+            pub fn accumulates(self, field_nr: u8) -> bool {
+                match self {
+                    #(#acc_arms)*
+                    _ => false
+                }
+            }
+
+            /// Names of the fields decoded from the raw field, including its components and
+            /// subfields, empty if the field isn't defined by the profile
+            pub fn decoded_field_names(self, field_nr: u8) -> &'static [&'static str] {
+                match self {
+                    #(#name_arms)*
+                    _ => &[]
+                }
+            }
+
+            /// Raw fields that select which subfield of the raw field is decoded
+            pub fn subfield_references(self, field_nr: u8) -> &'static [u8] {
+                match self {
+                    #(#ref_arms)*
+                    _ => &[]
+                }
+            }
         }
     }
 }
@@ -371,10 +534,10 @@ pub fn write_decode_file(profile: &FitProfile, out: &mut File) -> Result<(), std
         #![doc = #comment]
         #![allow(unused_variables)]
         #![allow(clippy::if_same_then_else, clippy::too_many_arguments)]
-        use std::collections::{HashMap, HashSet, VecDeque};
+        use std::collections::{HashMap, HashSet};
         use std::convert::TryInto;
         use crate::{{FitDataField, Value}};
-        use crate::de::{{DecodeOption}};
+        use crate::de::{{DecodeOption, FieldMap}};
         use crate::error::{{Result}};
         use super::{
             calculate_cumulative_value,
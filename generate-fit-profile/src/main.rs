@@ -37,23 +37,20 @@ fn rustfmt(fname: &PathBuf) {
 fn run() -> Result<(), Box<dyn std::error::Error>> {
     let opts = Cli::from_args();
     let profile_fname = opts.profile_path;
-    let profile_vers = opts.sdk_version.map_or_else(
-        || {
-            profile_fname
-                .parent()
-                .and_then(std::path::Path::file_name)
-                .map_or_else(
-                    || String::from("unknown"),
-                    |dirname| {
-                        dirname
-                            .to_str()
-                            .expect("Unable to convert dirname to str")
-                            .replace("FitSDKRelease_", "")
-                    },
-                )
-        },
-        |vers| vers,
-    );
+    let profile_vers = opts.sdk_version.unwrap_or_else(|| {
+        profile_fname
+            .parent()
+            .and_then(std::path::Path::file_name)
+            .map_or_else(
+                || String::from("unknown"),
+                |dirname| {
+                    dirname
+                        .to_str()
+                        .expect("Unable to convert dirname to str")
+                        .replace("FitSDKRelease_", "")
+                },
+            )
+    });
     assert!(
         profile_vers.chars().all(|c| c.is_ascii_digit() || c == '.'),
         "Could not determine version from Profile.xslx path: '{profile_fname:?}' - %{profile_vers}%"